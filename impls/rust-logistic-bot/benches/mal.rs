@@ -0,0 +1,157 @@
+//! Benchmarks for the reader and the evaluator.
+//!
+//! The reader is measured on large generated sources, and the evaluator on a generated
+//! expression and on small programs that exercise calls, loops, closures, destructuring, errors
+//! and EDN, each with both engines.
+//!
+//! Criterion compares each run with the previous one. To compare against a fixed baseline, run
+//! `cargo bench -- --save-baseline main` before a change and `cargo bench -- --baseline main`
+//! after it.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use mal::env::default_env;
+use mal::eval::{eval_source, with_engine, Engine};
+use mal::interpreter::Interpreter;
+use mal::reader::read_all;
+
+/// Generates `n` maps like the ones in a configuration file.
+fn data_source(n: usize) -> String {
+    (0..n)
+        .map(|i| {
+            format!(
+                "{{:id {} :name \"item \\\"{}\\\"\" :tags [:a :b :c] :size {}\n \
+                 \"nested\" {{:list (1 2 3) :big {}0000000000000000000}}}}\n",
+                i,
+                i,
+                i * 31 % 1000,
+                i
+            )
+        })
+        .collect()
+}
+
+/// Generates `n` function definitions, with comments and nested forms.
+fn code_source(n: usize) -> String {
+    (0..n)
+        .map(|i| {
+            format!(
+                ";; function number {}\n(def! f{} (fn* [a b]\n  (if (< a b)\n    \
+                 (+ a (* b {}))\n    (let* [c (- a b)] `(~c ~@(list a b))))))\n",
+                i, i, i
+            )
+        })
+        .collect()
+}
+
+/// Generates a list that is nested `depth` levels deep.
+fn nested_source(depth: usize) -> String {
+    "(a ".repeat(depth) + &")".repeat(depth)
+}
+
+/// Generates a vector of `n` arithmetic expressions, which only need the builtins.
+fn arithmetic_source(n: usize) -> String {
+    let items: String = (0..n)
+        .map(|i| format!("(+ (* {} 3) (- (/ {} 2) (% {} 7))) ", i, i, i))
+        .collect();
+    format!("[{}]", items)
+}
+
+fn reader(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read");
+    let sources = [
+        ("data", data_source(1_000)),
+        ("code", code_source(1_000)),
+        ("nested", nested_source(500)),
+    ];
+    for (name, source) in sources {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || source.clone(),
+                |source| read_all(source).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn evaluator(c: &mut Criterion) {
+    let env = default_env();
+    let source = arithmetic_source(10_000);
+    let mut group = c.benchmark_group("eval");
+    for (name, engine) in [
+        ("arithmetic", Engine::TreeWalker),
+        ("arithmetic-vm", Engine::Vm),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || source.clone(),
+                |source| with_engine(engine, || eval_source(source, &mut env.clone())).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Programs that only need the special forms and the builtins, by name
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "(def! fib (fn* [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))\n(fib 20)",
+    ),
+    (
+        "loop",
+        "(loop [i 0 acc 0] (if (< i 100000) (recur (+ i 1) (+ acc (% i 7))) acc))",
+    ),
+    (
+        "closures",
+        "(def! compose (fn* [f g] (fn* [x] (f (g x)))))\n\
+         (def! inc (fn* [x] (+ x 1)))\n\
+         (def! add100 (loop [i 1 f inc] (if (< i 100) (recur (+ i 1) (compose inc f)) f)))\n\
+         (loop [i 0 acc 0] (if (< i 100) (recur (+ i 1) (+ acc (add100 i))) acc))",
+    ),
+    (
+        "destructure",
+        "(loop [i 0 v [0 0]] (if (< i 10000) (let* [[a b] v] (recur (+ i 1) [b (+ a i)])) v))",
+    ),
+    (
+        "throw",
+        "(def! down (fn* [n] (if (= n 0) (throw :done) (down (- n 1)))))\n\
+         (loop [i 0] (if (< i 1000) (do (try* (down 10) (catch* e e)) (recur (+ i 1))) i))",
+    ),
+    (
+        "edn",
+        "(loop [i 0 s \"{:a [1 2 {:b \\\"c\\\"}] :d #{1 2} :e (-5 nil true)}\"]\n\
+         (if (< i 1000) (recur (+ i 1) (edn/write-string (edn/read-string s))) s))",
+    ),
+];
+
+fn programs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("program");
+    for (name, source) in PROGRAMS {
+        for (suffix, engine) in [("", Engine::TreeWalker), ("-vm", Engine::Vm)] {
+            let mut mal = Interpreter::new();
+            mal.set_engine(engine);
+            // a program that stops working should fail the benchmark, not be skipped
+            mal.eval_str(source)
+                .unwrap_or_else(|e| panic!("{}: {:#}", name, e));
+            group.bench_function(format!("{}{}", name, suffix), |b| {
+                b.iter_batched(
+                    || {
+                        let mut mal = Interpreter::new();
+                        mal.set_engine(engine);
+                        mal
+                    },
+                    |mut mal| mal.eval_str(source).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, reader, evaluator, programs);
+criterion_main!(benches);
@@ -1,28 +1,267 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 
-use color_eyre::eyre::eyre;
 use color_eyre::Result;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 
+use crate::compiler::Chunk;
+use crate::env::Env;
+use crate::error::MalError;
+
+// builtins are compared by address, which is good enough to tell them apart
+#[allow(unknown_lints, unpredictable_function_pointer_comparisons)]
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub enum Atom {
     List(Vec<Atom>),
     Vector(Vec<Atom>),
+    Nil,
+    Bool(bool),
     Integer(i64),
+    /// An integer that does not fit in an `i64`. Use [`Atom::from_bigint`] to create this, so
+    /// that values which fit are stored as [`Atom::Integer`] instead.
+    BigInteger(BigInt),
     Symbol(String),
     Keyword(String),
     String(String),
     HashMap(BTreeMap<Atom, Atom>),
+    Set(BTreeSet<Atom>),
+    Char(char),
+    /// A value with a tag, like `#inst "1985-04-12T23:20:50.52Z"` in EDN
+    Tagged(String, Box<Atom>),
     Builtin(fn(Vec<Atom>) -> Result<Atom>),
+    /// A builtin that is a Rust closure, registered by an application that embeds mal
+    NativeFn(NativeFn),
+    /// A function made with `fn*`
+    Closure(Arc<Closure>),
+}
+
+/// A function made with `fn*`. Closures are compared by identity, like builtins.
+#[derive(Clone, Debug)]
+pub struct Closure {
+    /// The name the function was defined with by `def!`, which its body can use to call it
+    pub name: Option<String>,
+    /// The binding forms of the parameters before `&`
+    pub params: Vec<Atom>,
+    /// The binding form after `&`, which is bound to the rest of the arguments
+    pub rest: Option<Atom>,
+    pub body: Atom,
+    /// The bindings of the locals used by the parameters and the body, from where the function
+    /// was made. Other symbols are looked up when it is called.
+    pub env: Env,
+    /// The file of the module the function was made in, whose definitions its body sees
+    pub module: Option<Arc<Path>>,
+    /// The body compiled for the VM, when the VM first calls the function
+    pub(crate) code: OnceLock<Arc<Chunk>>,
+}
+
+impl Closure {
+    /// Makes a function in the module that is running, if any.
+    pub fn new(params: Vec<Atom>, rest: Option<Atom>, body: Atom, env: Env) -> Self {
+        Self {
+            name: None,
+            params,
+            rest,
+            body,
+            env,
+            module: crate::module::current(),
+            code: OnceLock::new(),
+        }
+    }
+
+    /// Returns a copy of the function with the name `name`.
+    pub fn named(&self, name: &str) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            // the name is bound to a slot, so the code has to be compiled again
+            code: OnceLock::new(),
+            ..self.clone()
+        }
+    }
+
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl Eq for Closure {}
+
+impl PartialOrd for Closure {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Closure {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.address().cmp(&other.address())
+    }
+}
+
+impl std::hash::Hash for Closure {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.address().hash(state)
+    }
+}
+
+/// A named Rust closure that can be called from mal. Unlike [`Atom::Builtin`], it can capture
+/// state from the application.
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: String,
+    #[allow(clippy::type_complexity)]
+    pub f: Arc<dyn Fn(Vec<Atom>) -> Result<Atom> + Send + Sync>,
+}
+
+impl NativeFn {
+    pub fn new(name: &str, f: impl Fn(Vec<Atom>) -> Result<Atom> + Send + Sync + 'static) -> Self {
+        Self {
+            name: name.to_string(),
+            f: Arc::new(f),
+        }
+    }
+
+    /// The address of the closure, which tells native functions apart
+    fn address(&self) -> usize {
+        Arc::as_ptr(&self.f) as *const () as usize
+    }
+}
+
+impl std::fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NativeFn({})", self.name)
+    }
+}
+
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl Eq for NativeFn {}
+
+impl PartialOrd for NativeFn {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NativeFn {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.address().cmp(&other.address())
+    }
+}
+
+impl std::hash::Hash for NativeFn {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.address().hash(state)
+    }
+}
+
+impl From<i64> for Atom {
+    fn from(num: i64) -> Self {
+        Atom::Integer(num)
+    }
+}
+
+impl From<bool> for Atom {
+    fn from(b: bool) -> Self {
+        Atom::Bool(b)
+    }
+}
+
+impl From<&str> for Atom {
+    fn from(s: &str) -> Self {
+        Atom::String(s.to_string())
+    }
+}
+
+impl From<String> for Atom {
+    fn from(s: String) -> Self {
+        Atom::String(s)
+    }
+}
+
+impl From<Vec<Atom>> for Atom {
+    fn from(list: Vec<Atom>) -> Self {
+        Atom::List(list)
+    }
 }
 
 impl Atom {
+    /// Creates an integer atom, using [`Atom::Integer`] if the value fits in 64 bits.
+    pub fn from_bigint(num: BigInt) -> Atom {
+        match num.to_i64() {
+            Some(num) => Atom::Integer(num),
+            None => Atom::BigInteger(num),
+        }
+    }
+
+    /// Returns the name of the variant of this atom.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Atom::List(_) => "List",
+            Atom::Vector(_) => "Vector",
+            Atom::Nil => "Nil",
+            Atom::Bool(_) => "Bool",
+            Atom::Integer(_) => "Integer",
+            Atom::BigInteger(_) => "BigInteger",
+            Atom::Symbol(_) => "Symbol",
+            Atom::Keyword(_) => "Keyword",
+            Atom::String(_) => "String",
+            Atom::HashMap(_) => "HashMap",
+            Atom::Set(_) => "Set",
+            Atom::Char(_) => "Char",
+            Atom::Tagged(_, _) => "Tagged",
+            Atom::Builtin(_) => "Builtin",
+            Atom::NativeFn(_) => "NativeFn",
+            Atom::Closure(_) => "Closure",
+        }
+    }
+
     pub fn as_integer(&self) -> Result<i64> {
         match self {
             Atom::Integer(num) => Ok(*num),
-            a => Err(eyre!(
-                "type error: expected integer but got {}, which is the wrong type",
-                a
-            )),
+            a @ Atom::BigInteger(_) => Err(MalError::type_error("64-bit integer", a).into()),
+            a => Err(MalError::type_error("integer", a).into()),
+        }
+    }
+
+    /// Prints the atom for people instead of for the reader, like `println` does: strings and
+    /// characters are printed as they are, without quotes or escapes.
+    pub fn to_plain_string(&self) -> String {
+        fn join<'a>(items: impl Iterator<Item = &'a Atom>) -> String {
+            items
+                .map(Atom::to_plain_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        match self {
+            Atom::String(s) => s.clone(),
+            Atom::Char(c) => c.to_string(),
+            Atom::List(list) => format!("({})", join(list.iter())),
+            Atom::Vector(list) => format!("[{}]", join(list.iter())),
+            Atom::HashMap(map) => format!("{{{}}}", join(map.iter().flat_map(|(k, v)| [k, v]))),
+            Atom::Set(set) => format!("#{{{}}}", join(set.iter())),
+            Atom::Tagged(tag, value) => format!("#{} {}", tag, value.to_plain_string()),
+            a => a.to_string(),
+        }
+    }
+
+    /// Like [`Atom::as_integer`], but also accepts integers that do not fit in 64 bits.
+    pub fn as_bigint(&self) -> Result<BigInt> {
+        match self {
+            Atom::Integer(num) => Ok(BigInt::from(*num)),
+            Atom::BigInteger(num) => Ok(num.clone()),
+            a => Err(MalError::type_error("integer", a).into()),
         }
     }
 }
@@ -32,7 +271,10 @@ impl std::fmt::Display for Atom {
         match self {
             Atom::Symbol(sym) => write!(f, "{}", sym),
             Atom::Keyword(sym) => write!(f, ":{}", sym),
+            Atom::Nil => write!(f, "nil"),
+            Atom::Bool(b) => write!(f, "{}", b),
             Atom::Integer(num) => write!(f, "{}", num),
+            Atom::BigInteger(num) => write!(f, "{}", num),
             Atom::List(list) => write!(
                 f,
                 "({})",
@@ -60,7 +302,22 @@ impl std::fmt::Display for Atom {
                         .join(" ")
                 )
             }
+            Atom::Set(set) => write!(
+                f,
+                "#{{{}}}",
+                set.iter()
+                    .map(|x| format!("{}", x))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Atom::Char(c) => write!(f, "\\{}", c),
+            Atom::Tagged(tag, value) => write!(f, "#{} {}", tag, value),
             Atom::Builtin(b) => write!(f, "#<BUILTIN {:?}>", b),
+            Atom::NativeFn(native) => write!(f, "#<NATIVE {}>", native.name),
+            Atom::Closure(closure) => match &closure.name {
+                Some(name) => write!(f, "#<FN {}>", name),
+                None => write!(f, "#<FN>"),
+            },
         }
     }
 }
@@ -69,12 +326,7 @@ impl std::fmt::Display for Atom {
 fn escape(s: &str) -> String {
     let mut output = String::with_capacity(s.len());
     for c in s.chars() {
-        let escape = match c {
-            '"' => true,
-            '\\' => true,
-            _ => false,
-        };
-        if escape {
+        if matches!(c, '"' | '\\') {
             output.push('\\');
         }
         output.push(c);
@@ -0,0 +1,193 @@
+//! Reading and writing [bencode](https://en.wikipedia.org/wiki/Bencode), the encoding used by
+//! nREPL.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+
+use color_eyre::{eyre::eyre, Result};
+
+/// The longest string that is read, so that a message can't make the server allocate any amount
+/// of memory
+pub const MAX_LENGTH: usize = 64 << 20;
+/// How deeply lists and dicts can be nested, so that reading a message can't overflow the stack
+pub const MAX_DEPTH: usize = 64;
+/// The most digits that integers and the lengths of strings can have
+const MAX_DIGITS: u64 = 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bencode {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(BTreeMap<Vec<u8>, Bencode>),
+}
+
+impl From<&str> for Bencode {
+    fn from(s: &str) -> Self {
+        Bencode::Bytes(s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for Bencode {
+    fn from(s: String) -> Self {
+        Bencode::Bytes(s.into_bytes())
+    }
+}
+
+impl From<i64> for Bencode {
+    fn from(num: i64) -> Self {
+        Bencode::Integer(num)
+    }
+}
+
+impl<T: Into<Bencode>> From<Vec<T>> for Bencode {
+    fn from(list: Vec<T>) -> Self {
+        Bencode::List(list.into_iter().map(Into::into).collect())
+    }
+}
+
+impl Bencode {
+    /// Creates a dict from string keys.
+    pub fn dict<'a>(entries: impl IntoIterator<Item = (&'a str, Bencode)>) -> Self {
+        Bencode::Dict(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v))
+                .collect(),
+        )
+    }
+
+    /// Returns the value of `key` if this is a dict.
+    pub fn get(&self, key: &str) -> Option<&Bencode> {
+        match self {
+            Bencode::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of `key` if this is a dict and the value is a UTF-8 string.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            Bencode::Bytes(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn write(&self, output: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Bencode::Integer(num) => write!(output, "i{}e", num),
+            Bencode::Bytes(bytes) => {
+                write!(output, "{}:", bytes.len())?;
+                output.write_all(bytes)
+            }
+            Bencode::List(list) => {
+                output.write_all(b"l")?;
+                for x in list {
+                    x.write(output)?;
+                }
+                output.write_all(b"e")
+            }
+            Bencode::Dict(dict) => {
+                output.write_all(b"d")?;
+                for (k, v) in dict {
+                    Bencode::Bytes(k.clone()).write(output)?;
+                    v.write(output)?;
+                }
+                output.write_all(b"e")
+            }
+        }
+    }
+
+    /// Reads one value. Returns `None` if `input` is at end of file.
+    ///
+    /// Strings longer than [`MAX_LENGTH`] and lists or dicts nested deeper than [`MAX_DEPTH`] are
+    /// errors.
+    pub fn read(input: &mut impl BufRead) -> Result<Option<Bencode>> {
+        read_nested(input, 0)
+    }
+}
+
+/// Reads one value inside `depth` lists or dicts.
+fn read_nested(input: &mut impl BufRead, depth: usize) -> Result<Option<Bencode>> {
+    let first = match input.fill_buf()?.first() {
+        Some(c) => *c,
+        None => return Ok(None),
+    };
+    let res = match first {
+        b'i' => {
+            input.consume(1);
+            let digits = read_until(input, b'e')?;
+            Bencode::Integer(digits.parse()?)
+        }
+        b'l' | b'd' if depth >= MAX_DEPTH => {
+            return Err(eyre!(
+                "lists and dicts are nested more than {} deep",
+                MAX_DEPTH
+            ))
+        }
+        b'l' => {
+            input.consume(1);
+            let mut list = Vec::new();
+            while !at_end_marker(input)? {
+                list.push(read_required(input, depth + 1)?);
+            }
+            Bencode::List(list)
+        }
+        b'd' => {
+            input.consume(1);
+            let mut dict = BTreeMap::new();
+            while !at_end_marker(input)? {
+                let key = match read_required(input, depth + 1)? {
+                    Bencode::Bytes(key) => key,
+                    _ => return Err(eyre!("dict keys must be strings")),
+                };
+                dict.insert(key, read_required(input, depth + 1)?);
+            }
+            Bencode::Dict(dict)
+        }
+        b'0'..=b'9' => {
+            let len = read_until(input, b':')?.parse()?;
+            if len > MAX_LENGTH {
+                return Err(eyre!(
+                    "string of {} bytes is longer than {}",
+                    len,
+                    MAX_LENGTH
+                ));
+            }
+            let mut bytes = vec![0; len];
+            input.read_exact(&mut bytes)?;
+            Bencode::Bytes(bytes)
+        }
+        c => return Err(eyre!("unexpected byte {:?}", c as char)),
+    };
+    Ok(Some(res))
+}
+
+/// Reads a value that must be there.
+fn read_required(input: &mut impl BufRead, depth: usize) -> Result<Bencode> {
+    read_nested(input, depth)?.ok_or_else(|| eyre!("unexpected end of file"))
+}
+
+/// Consumes the `e` that ends lists and dicts, and returns whether it was there.
+fn at_end_marker(input: &mut impl BufRead) -> Result<bool> {
+    match input.fill_buf()?.first() {
+        Some(b'e') => {
+            input.consume(1);
+            Ok(true)
+        }
+        Some(_) => Ok(false),
+        None => Err(eyre!("unexpected end of file")),
+    }
+}
+
+/// Reads the digits up to `end`, which is consumed but not returned.
+fn read_until(input: &mut impl BufRead, end: u8) -> Result<String> {
+    let mut bytes = Vec::new();
+    Read::take(input.by_ref(), MAX_DIGITS + 1).read_until(end, &mut bytes)?;
+    match bytes.pop() {
+        Some(c) if c == end => {}
+        Some(_) => return Err(eyre!("number has more than {} digits", MAX_DIGITS)),
+        None => return Err(eyre!("unexpected end of file")),
+    }
+    Ok(String::from_utf8(bytes)?)
+}
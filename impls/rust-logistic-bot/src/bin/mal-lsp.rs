@@ -0,0 +1,6 @@
+//! A language server for mal, which talks to the editor over standard input and output.
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+    mal::lsp::run(std::io::stdin().lock(), std::io::stdout().lock())
+}
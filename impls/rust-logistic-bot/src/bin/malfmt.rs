@@ -0,0 +1,123 @@
+use std::io::Read;
+use std::process::ExitCode;
+
+use mal::format::format_source;
+
+const USAGE: &str = "\
+usage: malfmt [options] [files...]
+
+Formats mal source files. With no files, formats standard input.
+
+options:
+  --check         do not write anything, and fail if a file is not formatted
+  -i, --in-place  overwrite the files with the formatted source
+  -h, --help      show this help
+
+exit codes:
+  0   success
+  1   a file could not be parsed, or is not formatted with --check
+  64  invalid command line
+  66  a file could not be read
+  73  a file could not be written";
+
+/// Exit code when a file could not be parsed or is not formatted
+const EXIT_ERROR: u8 = 1;
+/// Exit code for an invalid command line, from sysexits.h
+const EXIT_USAGE: u8 = 64;
+/// Exit code when the input could not be read, from sysexits.h
+const EXIT_NO_INPUT: u8 = 66;
+/// Exit code when an output file could not be written, from sysexits.h
+const EXIT_CANT_CREATE: u8 = 73;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Print the formatted source
+    Print,
+    /// Only check whether the source is formatted
+    Check,
+    /// Overwrite the files
+    InPlace,
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+/// Runs the command line, and returns the exit code on failure.
+fn run(args: &[String]) -> Result<(), u8> {
+    let mut mode = Mode::Print;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            "--check" => mode = Mode::Check,
+            "-i" | "--in-place" => mode = Mode::InPlace,
+            option if option.starts_with('-') && option != "-" => {
+                eprintln!(
+                    "malfmt: unknown option {}\ntry 'malfmt --help' for more information",
+                    option
+                );
+                return Err(EXIT_USAGE);
+            }
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() {
+        if mode == Mode::InPlace {
+            eprintln!("malfmt: --in-place needs files");
+            return Err(EXIT_USAGE);
+        }
+        paths.push("-");
+    }
+
+    // keep going after errors, so that all problems are reported at once
+    let mut res = Ok(());
+    for path in paths {
+        if let Err(code) = format_file(path, mode) {
+            res = Err(code);
+        }
+    }
+    res
+}
+
+fn format_file(path: &str, mode: Mode) -> Result<(), u8> {
+    let source = if path == "-" {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source).map(|_| source)
+    } else {
+        std::fs::read_to_string(path)
+    };
+    let source = source.map_err(|e| {
+        eprintln!("malfmt: could not read {}: {}", path, e);
+        EXIT_NO_INPUT
+    })?;
+    let formatted = format_source(&source).map_err(|e| {
+        eprintln!("malfmt: {}: {}", path, e);
+        EXIT_ERROR
+    })?;
+    match mode {
+        Mode::Print => print!("{}", formatted),
+        Mode::Check => {
+            if formatted != source {
+                eprintln!("malfmt: {} is not formatted", path);
+                return Err(EXIT_ERROR);
+            }
+        }
+        Mode::InPlace => {
+            if formatted != source {
+                std::fs::write(path, formatted).map_err(|e| {
+                    eprintln!("malfmt: could not write {}: {}", path, e);
+                    EXIT_CANT_CREATE
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,478 @@
+//! Compiles forms to bytecode for the [`vm`](crate::vm).
+//!
+//! The bytecode evaluates a form in the same order as the tree-walker, [`walk`], and counts the
+//! same evaluation steps. The symbols bound by `let*`, `loop` and the parameters of `fn*` are
+//! resolved to numbered slots when the form is compiled, `if` and `recur` are jumps, and calls
+//! in tail position replace the running function instead of nesting in it. The body of a `fn*`
+//! is compiled when the VM first calls the function.
+//!
+//! Special forms that change how the tree-walker evaluates, like `debug` and `profile`, and
+//! special forms that are not valid, are left to the tree-walker with [`Op::Walk`], which
+//! reports the same errors when the form is evaluated.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::atom::{Atom, Closure};
+use crate::destructure;
+use crate::eval::is_special_form;
+#[cfg(doc)]
+use crate::eval::walk;
+
+/// An instruction for the VM. Indexes refer to [`Chunk::constants`], unless they say otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Counts an evaluation step for a special form
+    Step,
+    /// Pushes nil, without counting a step
+    Nil,
+    /// Pushes a constant
+    Const(usize),
+    /// Pushes the value that the symbol is bound to in the environment
+    Global(usize),
+    /// Pushes the value of a slot
+    Local(usize),
+    /// Starts evaluating a vector or map, whose elements follow
+    Collection,
+    /// Replaces the top `n` values with a vector of them
+    Vector(usize),
+    /// Replaces the top `2 * n` values, which are keys and values, with a map of them
+    HashMap(usize),
+    /// Starts evaluating a call, which pushes it onto the call stack
+    Enter(usize),
+    /// Replaces the top `n + 1` values with the result of calling the first one with the others,
+    /// and pops the call stack. The constant is the call.
+    Call(usize, usize),
+    /// Like [`Op::Call`], for a call in tail position: if the function is a closure, the VM
+    /// returns from the running function and calls it instead.
+    TailCall(usize, usize),
+    /// Pops a value, and binds it with one of [`Chunk::bindings`]
+    Bind(usize),
+    /// Pops a value
+    Pop,
+    /// Continues at an index in the code
+    Jump(usize),
+    /// Pops a value, and continues at an index in the code if it is nil or false
+    JumpIfFalse(usize),
+    /// Pushes a closure made from one of [`Chunk::functions`]
+    Closure(usize),
+    /// Pops `n` values, binds them with one of [`Chunk::loops`], and continues at its start
+    Recur(usize, usize),
+    /// Pushes the value of the form, evaluated by the tree-walker with the locals in one of
+    /// [`Chunk::scopes`] bound
+    Walk(usize, usize),
+    /// Starts the body of a `try*`, whose errors are handled by one of [`Chunk::handlers`]
+    Try(usize),
+    /// Ends the body of the innermost `try*`
+    EndTry,
+    /// Ends the handler of the innermost `catch*` that is handling an error
+    EndCatch,
+}
+
+/// How a value is bound to locals
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    /// The value is stored in a slot
+    Slot(usize),
+    /// The value is bound to a binding form with [`destructure`], and the symbols it binds are
+    /// stored in slots. `scope` is one of [`Chunk::scopes`], for evaluating the defaults.
+    Destructure {
+        form: Atom,
+        slots: Vec<(String, usize)>,
+        scope: Option<usize>,
+    },
+}
+
+/// A `fn*` form
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub params: Atom,
+    pub body: Atom,
+    /// One of [`Chunk::scopes`], with the locals that the function uses
+    pub captures: usize,
+}
+
+/// Where `recur` continues, in a `loop` or in the body of a function
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loop {
+    /// The index in the code
+    pub start: usize,
+    /// Indexes in [`Chunk::bindings`], one for each value given to `recur`
+    pub bindings: Vec<usize>,
+}
+
+/// The `catch*` of a `try*`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Handler {
+    /// The index in the code where the handler starts
+    pub start: usize,
+    /// The slot that `*stack*` is bound to
+    pub stack: usize,
+    /// The index in [`Chunk::bindings`] for the error
+    pub binding: usize,
+}
+
+/// Compiled code for a form, or for the body of a function
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// Values and forms used by the code
+    pub constants: Vec<Atom>,
+    /// Number of slots for locals
+    pub slots: usize,
+    pub bindings: Vec<Binding>,
+    pub functions: Vec<Function>,
+    pub loops: Vec<Loop>,
+    pub handlers: Vec<Handler>,
+    /// Names of locals and their slots, for code that needs them in an environment
+    pub scopes: Vec<Vec<(String, usize)>>,
+    /// For the body of a function, the slot that the name of the function is bound to
+    pub name: Option<usize>,
+    /// For the body of a function, indexes in [`Chunk::bindings`] for the parameters
+    pub params: Vec<usize>,
+    /// For the body of a function, the index in [`Chunk::bindings`] for the rest parameter
+    pub rest: Option<usize>,
+}
+
+impl Chunk {
+    fn constant(&mut self, atom: &Atom) -> usize {
+        self.constants.push(atom.clone());
+        self.constants.len() - 1
+    }
+}
+
+/// What a form in tail position may do instead of returning to the form around it
+#[derive(Clone, Copy, Debug, Default)]
+struct Tail {
+    /// Calls can replace the running function
+    calls: bool,
+    /// `recur` continues at this index in [`Chunk::loops`]
+    recur: Option<usize>,
+}
+
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    /// The locals that are in scope, innermost last
+    locals: Vec<(String, usize)>,
+}
+
+/// Compiles `ast` to code that pushes its value.
+pub fn compile(ast: &Atom) -> Chunk {
+    let mut compiler = Compiler::default();
+    compiler.form(ast, Tail::default());
+    compiler.chunk
+}
+
+/// Compiles the body of `f` to code that returns its value. The VM binds the parameters before
+/// running it.
+pub fn compile_function(f: &Closure) -> Chunk {
+    let mut compiler = Compiler::default();
+    if let Some(name) = &f.name {
+        let slot = compiler.slot();
+        compiler.locals.push((name.clone(), slot));
+        compiler.chunk.name = Some(slot);
+    }
+    for param in &f.params {
+        let binding = compiler.binding(param);
+        compiler.chunk.params.push(binding);
+    }
+    if let Some(rest) = &f.rest {
+        let binding = compiler.binding(rest);
+        compiler.chunk.rest = Some(binding);
+    }
+    let bindings = compiler
+        .chunk
+        .params
+        .iter()
+        .copied()
+        .chain(compiler.chunk.rest)
+        .collect();
+    compiler.chunk.loops.push(Loop { start: 0, bindings });
+    let tail = Tail {
+        calls: true,
+        recur: Some(0),
+    };
+    compiler.form(&f.body, tail);
+    compiler.chunk
+}
+
+impl Compiler {
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.code.len() - 1
+    }
+
+    /// Makes the jump at `index` continue at the end of the code.
+    fn patch(&mut self, index: usize) {
+        let end = self.chunk.code.len();
+        match &mut self.chunk.code[index] {
+            Op::Jump(target) | Op::JumpIfFalse(target) => *target = end,
+            op => panic!(
+                "Expected a jump, but got {:?} (this should never happen)",
+                op
+            ),
+        }
+    }
+
+    fn slot(&mut self) -> usize {
+        self.chunk.slots += 1;
+        self.chunk.slots - 1
+    }
+
+    fn local(&self, sym: &str) -> Option<usize> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(name, _)| name == sym)
+            .map(|(_, slot)| *slot)
+    }
+
+    /// Adds the locals in scope that `used` accepts to [`Chunk::scopes`], and returns its index.
+    fn scope(&mut self, used: impl Fn(&str) -> bool) -> usize {
+        let scope = self
+            .locals
+            .iter()
+            .filter(|(name, _)| used(name))
+            .cloned()
+            .collect::<BTreeMap<_, _>>();
+        self.chunk.scopes.push(scope.into_iter().collect());
+        self.chunk.scopes.len() - 1
+    }
+
+    /// Adds a binding of `form` to [`Chunk::bindings`], and puts the symbols it binds in scope.
+    fn binding(&mut self, form: &Atom) -> usize {
+        let binding = match form {
+            Atom::Symbol(sym) => {
+                let slot = self.slot();
+                self.locals.push((sym.clone(), slot));
+                Binding::Slot(slot)
+            }
+            form => {
+                // defaults are evaluated before the symbols of the form are in scope
+                let scope = destructure::has_defaults(form).then(|| self.scope(|_| true));
+                let slots = destructure::symbols(form)
+                    .into_iter()
+                    .map(|sym| (sym, self.slot()))
+                    .collect::<Vec<_>>();
+                self.locals.extend(slots.iter().cloned());
+                Binding::Destructure {
+                    form: form.clone(),
+                    slots,
+                    scope,
+                }
+            }
+        };
+        self.chunk.bindings.push(binding);
+        self.chunk.bindings.len() - 1
+    }
+
+    /// Compiles the bindings of `let*` or `loop`, and returns their indexes in
+    /// [`Chunk::bindings`].
+    fn bindings(&mut self, pairs: &[Atom]) -> Vec<usize> {
+        let mut bindings = Vec::new();
+        for pair in pairs.chunks(2) {
+            self.form(&pair[1], Tail::default());
+            let binding = self.binding(&pair[0]);
+            self.emit(Op::Bind(binding));
+            bindings.push(binding);
+        }
+        bindings
+    }
+
+    /// Leaves `ast` to the tree-walker.
+    fn walk(&mut self, ast: &Atom) {
+        let index = self.chunk.constant(ast);
+        let mut symbols = BTreeSet::new();
+        collect_symbols(ast, &mut symbols);
+        let scope = self.scope(|name| symbols.contains(name));
+        self.emit(Op::Walk(index, scope));
+    }
+
+    fn form(&mut self, ast: &Atom, tail: Tail) {
+        let list = match ast {
+            Atom::List(list) if !list.is_empty() => list,
+            Atom::Vector(list) => {
+                self.emit(Op::Collection);
+                for x in list {
+                    self.form(x, Tail::default());
+                }
+                self.emit(Op::Vector(list.len()));
+                return;
+            }
+            Atom::HashMap(map) => {
+                self.emit(Op::Collection);
+                for (k, v) in map {
+                    self.form(k, Tail::default());
+                    self.form(v, Tail::default());
+                }
+                self.emit(Op::HashMap(map.len()));
+                return;
+            }
+            Atom::Symbol(sym) => {
+                match self.local(sym) {
+                    Some(slot) => self.emit(Op::Local(slot)),
+                    None => {
+                        let index = self.chunk.constant(ast);
+                        self.emit(Op::Global(index))
+                    }
+                };
+                return;
+            }
+            a => {
+                let index = self.chunk.constant(a);
+                self.emit(Op::Const(index));
+                return;
+            }
+        };
+        let args = &list[1..];
+        let special = match &list[0] {
+            Atom::Symbol(sym) if is_special_form(sym) => sym.as_str(),
+            _ => return self.call(list, ast, tail),
+        };
+        match (special, args) {
+            ("quote", [quoted]) => {
+                let index = self.chunk.constant(quoted);
+                self.emit(Op::Const(index));
+            }
+            ("if", [test, then, otherwise @ ..]) if otherwise.len() <= 1 => {
+                self.emit(Op::Step);
+                self.form(test, Tail::default());
+                let jump_to_else = self.emit(Op::JumpIfFalse(0));
+                self.form(then, tail);
+                let jump_to_end = self.emit(Op::Jump(0));
+                self.patch(jump_to_else);
+                match otherwise.first() {
+                    Some(otherwise) => self.form(otherwise, tail),
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.patch(jump_to_end);
+            }
+            ("do", forms) => {
+                self.emit(Op::Step);
+                match forms.split_last() {
+                    Some((last, forms)) => {
+                        for x in forms {
+                            self.form(x, Tail::default());
+                            self.emit(Op::Pop);
+                        }
+                        self.form(last, tail);
+                    }
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+            }
+            ("let*", [Atom::List(pairs) | Atom::Vector(pairs), body]) if pairs.len() % 2 == 0 => {
+                self.emit(Op::Step);
+                let outer = self.locals.len();
+                self.bindings(pairs);
+                self.form(body, tail);
+                self.locals.truncate(outer);
+            }
+            ("loop", [Atom::List(pairs) | Atom::Vector(pairs), body]) if pairs.len() % 2 == 0 => {
+                self.emit(Op::Step);
+                let outer = self.locals.len();
+                let bindings = self.bindings(pairs);
+                let start = self.chunk.code.len();
+                self.chunk.loops.push(Loop { start, bindings });
+                let tail = Tail {
+                    recur: Some(self.chunk.loops.len() - 1),
+                    ..tail
+                };
+                self.form(body, tail);
+                self.locals.truncate(outer);
+            }
+            ("fn*", [params, body]) => {
+                self.emit(Op::Step);
+                let mut symbols = BTreeSet::new();
+                collect_symbols(params, &mut symbols);
+                collect_symbols(body, &mut symbols);
+                let captures = self.scope(|name| symbols.contains(name));
+                self.chunk.functions.push(Function {
+                    params: params.clone(),
+                    body: body.clone(),
+                    captures,
+                });
+                self.emit(Op::Closure(self.chunk.functions.len() - 1));
+            }
+            ("recur", values) => match tail.recur {
+                Some(target) => {
+                    self.emit(Op::Step);
+                    for x in values {
+                        self.form(x, Tail::default());
+                    }
+                    self.emit(Op::Recur(values.len(), target));
+                }
+                // the tree-walker reports that recur is not in tail position
+                None => self.walk(ast),
+            },
+            ("try*", [body]) => {
+                self.emit(Op::Step);
+                self.form(body, Tail::default());
+            }
+            ("try*", [body, Atom::List(catch)])
+                if catch.len() == 3 && catch[0] == Atom::Symbol(String::from("catch*")) =>
+            {
+                self.emit(Op::Step);
+                let outer = self.locals.len();
+                let stack = self.slot();
+                self.locals.push((String::from("*stack*"), stack));
+                let binding = self.binding(&catch[1]);
+                let handler = Handler {
+                    start: 0,
+                    stack,
+                    binding,
+                };
+                // the body does not see the bindings of the handler
+                let handler_locals = self.locals.split_off(outer);
+                self.chunk.handlers.push(handler);
+                let index = self.chunk.handlers.len() - 1;
+                self.emit(Op::Try(index));
+                self.form(body, Tail::default());
+                self.emit(Op::EndTry);
+                let jump_to_end = self.emit(Op::Jump(0));
+                self.chunk.handlers[index].start = self.chunk.code.len();
+                self.locals.extend(handler_locals);
+                self.form(&catch[2], Tail::default());
+                self.emit(Op::EndCatch);
+                self.locals.truncate(outer);
+                self.patch(jump_to_end);
+            }
+            _ => self.walk(ast),
+        }
+    }
+
+    /// Compiles the call `ast`, whose elements are `list`.
+    fn call(&mut self, list: &[Atom], ast: &Atom, tail: Tail) {
+        let index = self.chunk.constant(ast);
+        self.emit(Op::Enter(index));
+        for x in list {
+            self.form(x, Tail::default());
+        }
+        if tail.calls {
+            self.emit(Op::TailCall(list.len() - 1, index));
+        } else {
+            self.emit(Op::Call(list.len() - 1, index));
+        }
+    }
+}
+
+/// Adds the symbols in `ast` to `symbols`.
+fn collect_symbols(ast: &Atom, symbols: &mut BTreeSet<String>) {
+    match ast {
+        Atom::Symbol(sym) => {
+            symbols.insert(sym.clone());
+        }
+        Atom::List(list) | Atom::Vector(list) => {
+            list.iter().for_each(|x| collect_symbols(x, symbols));
+        }
+        Atom::HashMap(map) => map.iter().for_each(|(k, v)| {
+            collect_symbols(k, symbols);
+            collect_symbols(v, symbols);
+        }),
+        Atom::Set(set) => set.iter().for_each(|x| collect_symbols(x, symbols)),
+        _ => {}
+    }
+}
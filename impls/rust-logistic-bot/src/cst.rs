@@ -0,0 +1,256 @@
+//! A lossless concrete syntax tree for mal source.
+//!
+//! Unlike [`crate::reader`], which produces [`Atom`](crate::atom::Atom)s, this keeps everything
+//! that is in the source: whitespace, commas, comments and reader macros like `'` and `@`.
+//! Printing the nodes returned by [`parse`] gives back exactly the source they were parsed from,
+//! which is what tools like formatters need.
+
+use std::ops::Range;
+
+use color_eyre::Result;
+
+use crate::error::MalError;
+
+/// A node of the syntax tree, with its byte range in the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    /// Whitespace and commas
+    Whitespace(String),
+    /// A comment, from the `;` to the end of the line, not including the newline
+    Comment(String),
+    /// A symbol, keyword, number or string, as it is written in the source
+    Token(String),
+    /// A list, vector or map. `open` and `close` are the brackets, and `children` includes the
+    /// whitespace and comments between them.
+    Seq {
+        open: char,
+        close: char,
+        children: Vec<Node>,
+    },
+    /// A reader macro like `'` or `~@`, applied to `form`. `trivia` is the whitespace and
+    /// comments between the reader macro and the form.
+    Prefix {
+        prefix: String,
+        trivia: Vec<Node>,
+        form: Box<Node>,
+    },
+}
+
+impl Node {
+    /// Whether this node is whitespace or a comment, which does not change the meaning of the
+    /// source
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.kind, NodeKind::Whitespace(_) | NodeKind::Comment(_))
+    }
+
+    /// Returns the children that are not whitespace or comments, if this is a list, vector or
+    /// map.
+    pub fn forms(&self) -> impl Iterator<Item = &Node> {
+        let children = match &self.kind {
+            NodeKind::Seq { children, .. } => &children[..],
+            _ => &[],
+        };
+        children.iter().filter(|x| !x.is_trivia())
+    }
+
+    /// Returns the text of this node if it is a token.
+    pub fn token(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Token(token) => Some(token),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            NodeKind::Whitespace(s) | NodeKind::Comment(s) | NodeKind::Token(s) => {
+                write!(f, "{}", s)
+            }
+            NodeKind::Seq {
+                open,
+                close,
+                children,
+            } => {
+                write!(f, "{}", open)?;
+                for child in children {
+                    write!(f, "{}", child)?;
+                }
+                write!(f, "{}", close)
+            }
+            NodeKind::Prefix {
+                prefix,
+                trivia,
+                form,
+            } => {
+                write!(f, "{}", prefix)?;
+                for node in trivia {
+                    write!(f, "{}", node)?;
+                }
+                write!(f, "{}", form)
+            }
+        }
+    }
+}
+
+/// Parses all of `source` into a list of nodes.
+pub fn parse(source: &str) -> Result<Vec<Node>> {
+    let mut parser = Parser {
+        src: source,
+        position: 0,
+    };
+    let mut res = Vec::new();
+    while let Some(c) = parser.peek() {
+        if matches!(c, ')' | ']' | '}') {
+            parser.next();
+            return Err(parser
+                .error(&format!("unexpected {}", c), parser.position - 1)
+                .into());
+        }
+        res.push(parser.node()?);
+    }
+    Ok(res)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    position: usize,
+}
+
+/// Characters that end a token, like in the regex of [`crate::reader`]
+pub(crate) fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "[]{}('\"`,;)".contains(c)
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str, start: usize) -> MalError {
+        MalError::reader(message, start..self.position)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    /// Advances while `f` returns true for the next character.
+    fn take_while(&mut self, f: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&f) {
+            self.next();
+        }
+    }
+
+    /// Parses the next node, which must not be a closing bracket.
+    fn node(&mut self) -> Result<Node> {
+        let _nesting = crate::limits::nest()?;
+        let start = self.position;
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Err(self.error("unexpected end of file", start).into()),
+        };
+        let kind = match c {
+            c if c.is_whitespace() || c == ',' => {
+                self.take_while(|c| c.is_whitespace() || c == ',');
+                NodeKind::Whitespace(self.src[start..self.position].to_string())
+            }
+            ';' => {
+                self.take_while(|c| c != '\n');
+                NodeKind::Comment(self.src[start..self.position].to_string())
+            }
+            '(' | '[' | '{' => self.seq(c, start)?,
+            '\'' | '`' | '~' | '^' | '@' => {
+                let mut prefix = c.to_string();
+                if c == '~' && self.peek() == Some('@') {
+                    self.next();
+                    prefix.push('@');
+                }
+                let mut trivia = Vec::new();
+                loop {
+                    match self.peek() {
+                        None | Some(')' | ']' | '}') => {
+                            return Err(self
+                                .error(&format!("expected a form after {}", prefix), start)
+                                .into())
+                        }
+                        _ => {}
+                    }
+                    let node = self.node()?;
+                    if !node.is_trivia() {
+                        break NodeKind::Prefix {
+                            prefix,
+                            trivia,
+                            form: Box::new(node),
+                        };
+                    }
+                    trivia.push(node);
+                }
+            }
+            '"' => {
+                loop {
+                    match self.next() {
+                        Some('\\') => {
+                            self.next();
+                        }
+                        Some('"') => break,
+                        Some(_) => {}
+                        None => return Err(self.error("unclosed string", start).into()),
+                    }
+                }
+                NodeKind::Token(self.src[start..self.position].to_string())
+            }
+            _ => {
+                self.take_while(|c| !is_delimiter(c));
+                NodeKind::Token(self.src[start..self.position].to_string())
+            }
+        };
+        Ok(Node {
+            kind,
+            span: start..self.position,
+        })
+    }
+
+    /// Parses the rest of a list, vector or map, after the opening bracket.
+    fn seq(&mut self, open: char, start: usize) -> Result<NodeKind> {
+        let close = match open {
+            '(' => ')',
+            '[' => ']',
+            _ => '}',
+        };
+        let mut children = Vec::new();
+        loop {
+            match self.peek() {
+                Some(c) if c == close => {
+                    self.next();
+                    return Ok(NodeKind::Seq {
+                        open,
+                        close,
+                        children,
+                    });
+                }
+                Some(c @ (')' | ']' | '}')) => {
+                    self.next();
+                    return Err(self
+                        .error(&format!("expected {} but got {}", close, c), start)
+                        .into());
+                }
+                Some(_) => children.push(self.node()?),
+                None => {
+                    return Err(self
+                        .error(&format!("expected {}, got end of file", close), start)
+                        .into())
+                }
+            }
+        }
+    }
+}
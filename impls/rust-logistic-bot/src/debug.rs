@@ -0,0 +1,168 @@
+//! An interactive debugger that can pause before each evaluation step.
+//!
+//! `(debug expr)` evaluates `expr` one step at a time, and `(break)` pauses a running program.
+//! While paused, the debugger reads commands from the input of the program, which is stdin unless
+//! it was redirected with [`crate::io::set_input`]; `help` lists them. It writes to the
+//! [error output](crate::io::write_error), so that it does not mix with the output of the program.
+
+use std::cell::Cell;
+
+use color_eyre::Result;
+
+use crate::atom::Atom;
+use crate::env::Env;
+use crate::error::MalError;
+use crate::eval::{eval, walk, with_engine, Engine};
+use crate::io::{read_input_line, write_error};
+use crate::stack::{call_stack, clear_backtrace, format_backtrace};
+
+const HELP: &str = "\
+step, s       evaluate the next step (also the default)
+next, n       evaluate the current form without stopping inside of it
+continue, c   run until the next (break)
+print, p expr evaluate expr in the current environment and print it
+env           list the bindings in the current environment
+backtrace, bt show the call stack
+quit, q       abort the evaluation
+help, h       show this help";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Don't pause
+    Run,
+    /// Pause before the next step
+    Step,
+    /// Pause before the next step that is at most this deep
+    StepOver(usize),
+}
+
+thread_local! {
+    static MODE: Cell<Mode> = const { Cell::new(Mode::Run) };
+    /// Number of nested calls to `eval`
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Decrements the eval depth when dropped.
+pub struct DepthGuard(());
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Called by `eval` before evaluating `ast`. Pauses if the debugger is stepping.
+pub fn before_eval(ast: &Atom, env: &Env) -> Result<DepthGuard> {
+    let depth = DEPTH.with(|depth| {
+        depth.set(depth.get() + 1);
+        depth.get()
+    });
+    let guard = DepthGuard(());
+    let pause = match MODE.with(|mode| mode.get()) {
+        Mode::Run => false,
+        Mode::Step => true,
+        Mode::StepOver(max_depth) => depth <= max_depth,
+    };
+    if pause {
+        prompt(ast, env, depth)?;
+    }
+    Ok(guard)
+}
+
+/// Evaluates `ast` in the debugger, pausing before the first step. This implements `(debug)`.
+///
+/// The debugger steps through the forms as the tree-walker evaluates them, so `ast` and the
+/// functions it calls are walked even if the VM is the engine.
+pub fn debug(ast: &Atom, env: &Env) -> Result<Atom> {
+    let old_mode = MODE.with(|mode| mode.replace(Mode::Step));
+    let res = with_engine(Engine::TreeWalker, || walk(ast, env));
+    MODE.with(|mode| mode.set(old_mode));
+    res
+}
+
+/// Pauses the program at the current form. This implements `(break)`.
+pub fn pause(form: &Atom, env: &Env) -> Result<Atom> {
+    show("break")?;
+    prompt(form, env, DEPTH.with(|depth| depth.get()))?;
+    Ok(Atom::Nil)
+}
+
+/// Shows the form that is about to be evaluated, and reads debugger commands until one of them
+/// resumes the evaluation.
+fn prompt(form: &Atom, env: &Env, depth: usize) -> Result<()> {
+    show(&format!("[{}] {}", depth, form))?;
+    loop {
+        write_error("debug> ")?;
+        let line = match read_input_line()? {
+            Some(line) => line,
+            None => {
+                // end of input, so nobody can tell us what to do
+                MODE.with(|mode| mode.set(Mode::Run));
+                return Ok(());
+            }
+        };
+        let line = line.trim();
+        let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mode = match command {
+            "" | "s" | "step" => Mode::Step,
+            "n" | "next" => Mode::StepOver(depth),
+            "c" | "continue" => Mode::Run,
+            "p" | "print" => {
+                show(&inspect(arg, env))?;
+                continue;
+            }
+            "env" => {
+                let mut all = crate::eval::globals();
+                all.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+                for (k, v) in all
+                    .iter()
+                    .filter(|(_, v)| !matches!(v, Atom::Builtin(_) | Atom::NativeFn(_)))
+                {
+                    show(&format!("{} = {}", k, v))?;
+                }
+                continue;
+            }
+            "bt" | "backtrace" => {
+                show(&format_backtrace(&call_stack()))?;
+                continue;
+            }
+            "q" | "quit" => {
+                MODE.with(|mode| mode.set(Mode::Run));
+                return Err(MalError::Interrupted.into());
+            }
+            "h" | "help" => {
+                show(HELP)?;
+                continue;
+            }
+            _ => {
+                show(&format!(
+                    "unknown command {}, type help for a list of commands",
+                    command
+                ))?;
+                continue;
+            }
+        };
+        MODE.with(|m| m.set(mode));
+        return Ok(());
+    }
+}
+
+/// Writes a line to the error output.
+fn show(line: &str) -> Result<()> {
+    write_error(&format!("{}\n", line))
+}
+
+/// Evaluates `source` without stepping, for the `print` command.
+fn inspect(source: &str, env: &Env) -> String {
+    let old_mode = MODE.with(|mode| mode.replace(Mode::Run));
+    let res = crate::reader::read_str(source.to_string()).and_then(|atom| eval(&atom, env));
+    MODE.with(|mode| mode.set(old_mode));
+    match res {
+        Ok(atom) => atom.to_string(),
+        Err(e) => {
+            // the error is shown here, so its backtrace must not show up later
+            clear_backtrace();
+            e.to_string()
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Binding forms, which bind the parts of a value to symbols, like in Clojure.
+//!
+//! A binding form is one of:
+//!
+//! - a symbol, which is bound to the whole value.
+//! - a vector, which binds its elements to the elements of a sequence, like `[a b & rest :as all]`.
+//!   Elements that are missing are `nil`, and so is `rest` if there are no more elements.
+//! - a map, which binds its values to the values of a map under its keys, like `{a :a [x y] :pos}`.
+//!   `:keys [x y]` binds `x` and `y` to the values of `:x` and `:y`, and `:strs` and `:syms` do the
+//!   same with string and symbol keys. `:or {y 0}` gives defaults for keys that are missing, and
+//!   `:as m` binds the whole map.
+//!
+//! Binding forms can be nested. `let*`, `loop` and the parameters of `fn*` bind with [`bind`].
+
+use color_eyre::Result;
+
+use crate::atom::Atom;
+use crate::env::Env;
+use crate::error::MalError;
+use crate::eval::walk;
+
+/// Binds `binding` to `value` in `env`.
+///
+/// Defaults given with `:or` are evaluated in `env` as it was before `binding` was bound, so they
+/// can use the bindings made before `binding`, but not the other symbols that `binding` binds.
+pub fn bind(binding: &Atom, value: Atom, env: &mut Env) -> Result<()> {
+    let bound = bindings(binding, value, env)?;
+    env.extend(bound);
+    Ok(())
+}
+
+/// Returns the bindings made by binding `binding` to `value`, with the defaults evaluated in
+/// `scope`.
+pub fn bindings(binding: &Atom, value: Atom, scope: &Env) -> Result<Env> {
+    let mut bound = Env::new();
+    bind_into(binding, value, scope, &mut bound)?;
+    Ok(bound)
+}
+
+/// Returns the symbols that `binding` binds. Parts of `binding` that are not valid are skipped.
+pub fn symbols(binding: &Atom) -> Vec<String> {
+    fn collect(binding: &Atom, symbols: &mut Vec<String>) {
+        match binding {
+            Atom::Symbol(sym) if sym != "&" => symbols.push(sym.clone()),
+            Atom::Vector(forms) => forms.iter().for_each(|form| collect(form, symbols)),
+            Atom::HashMap(pattern) => {
+                for (form, key) in pattern {
+                    match (form, key) {
+                        (Atom::Keyword(k), Atom::Vector(names))
+                            if matches!(k.as_str(), "keys" | "strs" | "syms") =>
+                        {
+                            names.iter().for_each(|name| collect(name, symbols));
+                        }
+                        (Atom::Keyword(k), _) if k == "as" => collect(key, symbols),
+                        (Atom::Keyword(_), _) => {}
+                        (form, _) => collect(form, symbols),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut symbols = Vec::new();
+    collect(binding, &mut symbols);
+    symbols
+}
+
+/// Returns whether `binding` has defaults, given with `:or`.
+pub fn has_defaults(binding: &Atom) -> bool {
+    match binding {
+        Atom::Vector(forms) => forms.iter().any(has_defaults),
+        Atom::HashMap(pattern) => pattern
+            .keys()
+            .any(|form| *form == Atom::Keyword(String::from("or")) || has_defaults(form)),
+        _ => false,
+    }
+}
+
+/// Splits the parameters of `fn*` into the binding forms before `&`, and the one after it.
+pub fn params(params: &Atom) -> Result<(Vec<Atom>, Option<Atom>)> {
+    let forms = match params {
+        Atom::Vector(forms) | Atom::List(forms) => forms,
+        a => return Err(MalError::type_error("a vector of parameters", a).into()),
+    };
+    let mut fixed = Vec::new();
+    let mut forms = forms.iter();
+    while let Some(form) = forms.next() {
+        match form {
+            Atom::Symbol(sym) if sym == "&" => {
+                let rest = forms
+                    .next()
+                    .ok_or_else(|| malformed(params, "& needs a binding"))?;
+                if forms.next().is_some() {
+                    return Err(malformed(params, "only one binding can follow &"));
+                }
+                check_form(rest)?;
+                return Ok((fixed, Some(rest.clone())));
+            }
+            form => {
+                check_form(form)?;
+                fixed.push(form.clone());
+            }
+        }
+    }
+    Ok((fixed, None))
+}
+
+/// Checks that `form` can be a binding form, without checking the forms inside of it.
+fn check_form(form: &Atom) -> Result<()> {
+    match form {
+        Atom::Symbol(_) | Atom::Vector(_) | Atom::HashMap(_) => Ok(()),
+        a => Err(MalError::type_error("a symbol, vector or map to bind", a).into()),
+    }
+}
+
+/// Binds `binding` to `value` in `out`. `scope` is where defaults are evaluated.
+fn bind_into(binding: &Atom, value: Atom, scope: &Env, out: &mut Env) -> Result<()> {
+    match binding {
+        Atom::Symbol(sym) => {
+            out.insert(sym.clone(), value);
+            Ok(())
+        }
+        Atom::Vector(forms) => bind_sequence(binding, forms, value, scope, out),
+        Atom::HashMap(_) => bind_map(binding, value, scope, out),
+        a => Err(MalError::type_error("a symbol, vector or map to bind", a).into()),
+    }
+}
+
+fn bind_sequence(
+    binding: &Atom,
+    forms: &[Atom],
+    value: Atom,
+    scope: &Env,
+    out: &mut Env,
+) -> Result<()> {
+    let items = match &value {
+        Atom::List(items) | Atom::Vector(items) => items.as_slice(),
+        Atom::Nil => &[],
+        _ => return Err(mismatch(binding, "a sequence", &value)),
+    };
+    let mut forms = forms.iter();
+    let mut index = 0;
+    let mut has_rest = false;
+    while let Some(form) = forms.next() {
+        match form {
+            Atom::Symbol(sym) if sym == "&" => {
+                let rest = forms
+                    .next()
+                    .ok_or_else(|| malformed(binding, "& needs a binding"))?;
+                let rest_value = match items.get(index..) {
+                    Some(rest) if !rest.is_empty() => Atom::List(rest.to_vec()),
+                    _ => Atom::Nil,
+                };
+                bind_into(rest, rest_value, scope, out)?;
+                index = items.len();
+                has_rest = true;
+            }
+            Atom::Keyword(k) if k == "as" => {
+                let all = forms
+                    .next()
+                    .ok_or_else(|| malformed(binding, ":as needs a symbol"))?;
+                bind_as(binding, all, value.clone(), out)?;
+            }
+            _ if has_rest => {
+                return Err(malformed(binding, "only :as can follow & and its binding"))
+            }
+            form => {
+                let item = items.get(index).cloned().unwrap_or(Atom::Nil);
+                bind_into(form, item, scope, out)?;
+                index += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn bind_map(binding: &Atom, value: Atom, scope: &Env, out: &mut Env) -> Result<()> {
+    let pattern = match binding {
+        Atom::HashMap(pattern) => pattern,
+        a => panic!("Expected a map, but got {} (this should never happen)", a),
+    };
+    let map = match &value {
+        Atom::HashMap(map) => Some(map),
+        Atom::Nil => None,
+        _ => return Err(mismatch(binding, "a map", &value)),
+    };
+    let defaults = match pattern.get(&Atom::Keyword(String::from("or"))) {
+        Some(Atom::HashMap(defaults)) => Some(defaults),
+        Some(_) => return Err(malformed(binding, ":or needs a map")),
+        None => None,
+    };
+    let lookup = |form: &Atom, key: &Atom| -> Result<Atom> {
+        if let Some(v) = map.and_then(|map| map.get(key)) {
+            return Ok(v.clone());
+        }
+        match defaults.and_then(|defaults| defaults.get(form)) {
+            Some(default) => walk(default, scope),
+            None => Ok(Atom::Nil),
+        }
+    };
+    for (form, key) in pattern {
+        match form {
+            Atom::Keyword(k) if matches!(k.as_str(), "keys" | "strs" | "syms") => {
+                let names = match key {
+                    Atom::Vector(names) => names,
+                    _ => return Err(malformed(binding, &format!(":{} needs a vector", k))),
+                };
+                for name in names {
+                    let sym = match name {
+                        Atom::Symbol(sym) => sym,
+                        _ => return Err(malformed(binding, &format!(":{} needs symbols", k))),
+                    };
+                    let key = match k.as_str() {
+                        "keys" => Atom::Keyword(sym.clone()),
+                        "strs" => Atom::String(sym.clone()),
+                        _ => Atom::Symbol(sym.clone()),
+                    };
+                    out.insert(sym.clone(), lookup(name, &key)?);
+                }
+            }
+            Atom::Keyword(k) if k == "as" => bind_as(binding, key, value.clone(), out)?,
+            Atom::Keyword(k) if k == "or" => {}
+            form => {
+                let v = lookup(form, key)?;
+                bind_into(form, v, scope, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Binds the symbol after `:as` in `binding` to the whole value.
+fn bind_as(binding: &Atom, all: &Atom, value: Atom, out: &mut Env) -> Result<()> {
+    match all {
+        Atom::Symbol(sym) => {
+            out.insert(sym.clone(), value);
+            Ok(())
+        }
+        _ => Err(malformed(binding, ":as needs a symbol")),
+    }
+}
+
+/// The error for a value that does not have the shape of `binding`
+fn mismatch(binding: &Atom, expected: &str, got: &Atom) -> color_eyre::Report {
+    MalError::Destructure {
+        binding: binding.clone(),
+        message: format!("expected {} but got {}", expected, got),
+    }
+    .into()
+}
+
+/// The error for a binding form that is not valid
+fn malformed(binding: &Atom, message: &str) -> color_eyre::Report {
+    MalError::Destructure {
+        binding: binding.clone(),
+        message: message.to_string(),
+    }
+    .into()
+}
@@ -0,0 +1,425 @@
+//! Reading and writing data in [EDN](https://github.com/edn-format/edn).
+//!
+//! Unlike [`crate::reader`], this follows the EDN rules: there are no reader macros like `'` or
+//! `@`, and sets, characters, tagged values and discarded forms (`#_`) are supported. Floating
+//! point numbers are not supported, because mal has no floats.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+
+use color_eyre::Result;
+use regex::Regex;
+
+use crate::atom::Atom;
+use crate::error::MalError;
+use crate::reader::parse_integer;
+
+/// Reads the first EDN value in `s`.
+pub fn read_edn(s: &str) -> Result<Atom> {
+    let mut reader = EdnReader {
+        src: s,
+        position: 0,
+    };
+    match reader.read()? {
+        Some(atom) => Ok(atom),
+        None => Err(reader
+            .error("unexpected end of file", reader.position)
+            .into()),
+    }
+}
+
+/// Writes `atom` as EDN. Fails if `atom` contains a function, or a symbol, keyword or tagged value
+/// that EDN can not read back.
+pub fn write_edn(atom: &Atom) -> Result<String> {
+    let mut res = String::new();
+    write_atom(atom, &mut res)?;
+    Ok(res)
+}
+
+struct EdnReader<'a> {
+    src: &'a str,
+    position: usize,
+}
+
+/// Characters that end a symbol, keyword or number
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+}
+
+impl<'a> EdnReader<'a> {
+    fn error(&self, message: &str, start: usize) -> MalError {
+        MalError::reader(message, start..self.position)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skips whitespace, commas and comments.
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while !matches!(self.next(), Some('\n') | None) {}
+            } else if c.is_whitespace() || c == ',' {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Skips whitespace, comments and forms discarded with `#_`.
+    fn skip_ignored(&mut self) -> Result<()> {
+        loop {
+            self.skip_whitespace();
+            if !self.src[self.position..].starts_with("#_") {
+                return Ok(());
+            }
+            let start = self.position;
+            self.position += 2;
+            self.read()?
+                .ok_or_else(|| self.error("unexpected end of file after #_", start))?;
+        }
+    }
+
+    /// Reads characters until the next delimiter.
+    fn read_token(&mut self) -> &'a str {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            if is_delimiter(c) {
+                break;
+            }
+            self.next();
+        }
+        &self.src[start..self.position]
+    }
+
+    /// Reads the next value, or returns `None` at the end of the input.
+    fn read(&mut self) -> Result<Option<Atom>> {
+        let _nesting = crate::limits::nest()?;
+        self.skip_ignored()?;
+        let start = self.position;
+        let c = match self.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let atom = match c {
+            '(' => Atom::List(self.read_seq(')', start)?),
+            '[' => Atom::Vector(self.read_seq(']', start)?),
+            '{' => {
+                let items = self.read_seq('}', start)?;
+                if items.len() % 2 != 0 {
+                    return Err(self
+                        .error("map literal must contain an even number of forms", start)
+                        .into());
+                }
+                let mut map = BTreeMap::new();
+                let mut items = items.into_iter();
+                while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                    if map.insert(k, v).is_some() {
+                        return Err(self.error("duplicate key in map", start).into());
+                    }
+                }
+                Atom::HashMap(map)
+            }
+            ')' | ']' | '}' => {
+                return Err(self.error("unexpected closing delimiter", start).into())
+            }
+            '"' => Atom::String(self.read_string(start)?),
+            '\\' => Atom::Char(self.read_char(start)?),
+            '#' => return self.read_dispatch(start).map(Some),
+            _ => {
+                self.position = start;
+                let token = self.read_token();
+                read_token(token).map_err(|message| self.error(message, start))?
+            }
+        };
+        Ok(Some(atom))
+    }
+
+    /// Reads values until `end` is found.
+    fn read_seq(&mut self, end: char, start: usize) -> Result<Vec<Atom>> {
+        let mut res = Vec::new();
+        loop {
+            self.skip_ignored()?;
+            if self.peek() == Some(end) {
+                self.next();
+                return Ok(res);
+            }
+            match self.read()? {
+                Some(atom) => res.push(atom),
+                None => {
+                    return Err(self
+                        .error(
+                            &format!("unexpected end of file (missing '{}')", end),
+                            start,
+                        )
+                        .into())
+                }
+            }
+        }
+    }
+
+    /// Reads a value that starts with `#`: sets and tagged values.
+    fn read_dispatch(&mut self, start: usize) -> Result<Atom> {
+        match self.peek() {
+            Some('{') => {
+                self.next();
+                let mut set = BTreeSet::new();
+                for item in self.read_seq('}', start)? {
+                    if !set.insert(item) {
+                        return Err(self.error("duplicate element in set", start).into());
+                    }
+                }
+                Ok(Atom::Set(set))
+            }
+            Some(c) if c.is_alphabetic() => {
+                let tag = self.read_token();
+                if !is_symbol(tag) {
+                    return Err(self.error("invalid tag", start).into());
+                }
+                let value = self
+                    .read()?
+                    .ok_or_else(|| self.error("unexpected end of file after tag", start))?;
+                check_tagged(tag, &value).map_err(|message| self.error(message, start))?;
+                Ok(Atom::Tagged(tag.to_string(), Box::new(value)))
+            }
+            _ => Err(self
+                .error("invalid dispatch character after #", start)
+                .into()),
+        }
+    }
+
+    fn read_string(&mut self, start: usize) -> Result<String> {
+        let mut res = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error("unclosed string", start).into()),
+                Some('"') => return Ok(res),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('n') => '\n',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some('u') => self.read_unicode(start)?,
+                        _ => return Err(self.error("unsupported escape sequence", start).into()),
+                    };
+                    res.push(c);
+                }
+                Some(c) => res.push(c),
+            }
+        }
+    }
+
+    /// Reads the four hex digits of a `\uXXXX` escape.
+    fn read_unicode(&mut self, start: usize) -> Result<char> {
+        let digits = self.src.get(self.position..self.position + 4);
+        self.position = (self.position + 4).min(self.src.len());
+        digits
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape", start).into())
+    }
+
+    fn read_char(&mut self, start: usize) -> Result<char> {
+        // the first character is always part of the literal, even if it is a delimiter
+        let first = self
+            .next()
+            .ok_or_else(|| self.error("unexpected end of file in character", start))?;
+        let rest = self.read_token();
+        match (first, rest) {
+            (c, "") => Ok(c),
+            ('n', "ewline") => Ok('\n'),
+            ('r', "eturn") => Ok('\r'),
+            ('s', "pace") => Ok(' '),
+            ('t', "ab") => Ok('\t'),
+            ('u', _) if rest.len() == 4 => {
+                self.position -= 4;
+                self.read_unicode(start)
+            }
+            _ => Err(self.error("invalid character literal", start).into()),
+        }
+    }
+}
+
+/// Reads a token that is not a collection, string, character or tagged value.
+fn read_token(token: &str) -> std::result::Result<Atom, &'static str> {
+    match token {
+        "nil" => return Ok(Atom::Nil),
+        "true" => return Ok(Atom::Bool(true)),
+        "false" => return Ok(Atom::Bool(false)),
+        _ => {}
+    }
+    let digits = token.strip_prefix(['+', '-']).unwrap_or(token);
+    if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        let integer = token.strip_suffix('N').unwrap_or(token);
+        return match parse_integer(integer) {
+            Some(num) => Ok(num),
+            None if token.contains(['.', 'e', 'E', 'M']) => {
+                Err("floating point numbers are not supported")
+            }
+            None => Err("invalid number"),
+        };
+    }
+    if let Some(keyword) = token.strip_prefix(':') {
+        if is_symbol(keyword) && keyword != "/" {
+            Ok(Atom::Keyword(keyword.to_string()))
+        } else {
+            Err("invalid keyword")
+        }
+    } else if is_symbol(token) {
+        Ok(Atom::Symbol(token.to_string()))
+    } else {
+        Err("invalid symbol")
+    }
+}
+
+/// Checks that `s` is a valid EDN symbol, with an optional namespace prefix.
+fn is_symbol(s: &str) -> bool {
+    if s == "/" {
+        return true;
+    }
+    match s.split_once('/') {
+        Some((namespace, name)) => is_symbol_part(namespace) && is_symbol_part(name),
+        None => is_symbol_part(s),
+    }
+}
+
+/// Checks a symbol without its namespace.
+fn is_symbol_part(s: &str) -> bool {
+    let mut chars = s.chars();
+    let first = match chars.next() {
+        Some(c) => c,
+        None => return false,
+    };
+    let valid = |c: char| c.is_alphanumeric() || ".*+!-_?$%&=<>:#".contains(c);
+    if first.is_ascii_digit() || matches!(first, ':' | '#') || !valid(first) {
+        return false;
+    }
+    if matches!(first, '-' | '+' | '.') && s[1..].starts_with(|c: char| c.is_ascii_digit()) {
+        return false;
+    }
+    chars.all(valid)
+}
+
+/// Checks the value of the `#inst` and `#uuid` tags. Other tags accept any value.
+fn check_tagged(tag: &str, value: &Atom) -> std::result::Result<(), &'static str> {
+    static INST: OnceLock<Regex> = OnceLock::new();
+    static UUID: OnceLock<Regex> = OnceLock::new();
+    let (re, pattern, message) = match tag {
+        "inst" => (
+            &INST,
+            r"^\d{4}(-\d{2}(-\d{2}(T\d{2}:\d{2}(:\d{2}(\.\d+)?)?(Z|[+-]\d{2}:\d{2})?)?)?)?$",
+            "#inst expects an RFC 3339 timestamp string",
+        ),
+        "uuid" => (
+            &UUID,
+            r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$",
+            "#uuid expects a UUID string",
+        ),
+        _ => return Ok(()),
+    };
+    let re = re.get_or_init(|| Regex::new(pattern).unwrap());
+    match value {
+        Atom::String(s) if re.is_match(s) => Ok(()),
+        _ => Err(message),
+    }
+}
+
+/// Checks that a symbol, keyword or tag can be read back as what it was written as.
+fn check_name(kind: &str, name: &str, valid: bool) -> Result<()> {
+    if valid {
+        return Ok(());
+    }
+    Err(MalError::Conversion(format!("{} {:?} can not be written as EDN", kind, name)).into())
+}
+
+fn write_seq<'a>(
+    items: impl Iterator<Item = &'a Atom>,
+    delimiters: (&str, &str),
+    res: &mut String,
+) -> Result<()> {
+    res.push_str(delimiters.0);
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            res.push(' ');
+        }
+        write_atom(item, res)?;
+    }
+    res.push_str(delimiters.1);
+    Ok(())
+}
+
+fn write_atom(atom: &Atom, res: &mut String) -> Result<()> {
+    match atom {
+        Atom::Nil => res.push_str("nil"),
+        Atom::Bool(b) => res.push_str(&b.to_string()),
+        Atom::Integer(num) => res.push_str(&num.to_string()),
+        Atom::BigInteger(num) => {
+            res.push_str(&num.to_string());
+            res.push('N');
+        }
+        Atom::Symbol(sym) => {
+            // nil, true and false would be read back as themselves, not as symbols
+            let valid = is_symbol(sym) && !matches!(sym.as_str(), "nil" | "true" | "false");
+            check_name("symbol", sym, valid)?;
+            res.push_str(sym);
+        }
+        Atom::Keyword(sym) => {
+            check_name("keyword", sym, is_symbol(sym) && sym != "/")?;
+            res.push(':');
+            res.push_str(sym);
+        }
+        Atom::String(s) => {
+            res.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => res.push_str("\\\""),
+                    '\\' => res.push_str("\\\\"),
+                    '\n' => res.push_str("\\n"),
+                    '\r' => res.push_str("\\r"),
+                    '\t' => res.push_str("\\t"),
+                    c => res.push(c),
+                }
+            }
+            res.push('"');
+        }
+        Atom::Char(c) => match c {
+            '\n' => res.push_str("\\newline"),
+            '\r' => res.push_str("\\return"),
+            ' ' => res.push_str("\\space"),
+            '\t' => res.push_str("\\tab"),
+            c => {
+                res.push('\\');
+                res.push(*c);
+            }
+        },
+        Atom::List(list) => write_seq(list.iter(), ("(", ")"), res)?,
+        Atom::Vector(list) => write_seq(list.iter(), ("[", "]"), res)?,
+        Atom::Set(set) => write_seq(set.iter(), ("#{", "}"), res)?,
+        Atom::HashMap(map) => write_seq(map.iter().flat_map(|(k, v)| [k, v]), ("{", "}"), res)?,
+        Atom::Tagged(tag, value) => {
+            let valid = is_symbol(tag) && tag.starts_with(char::is_alphabetic);
+            check_name("tag", tag, valid)?;
+            check_tagged(tag, value).map_err(|message| MalError::Conversion(message.into()))?;
+            res.push('#');
+            res.push_str(tag);
+            res.push(' ');
+            write_atom(value, res)?;
+        }
+        Atom::Builtin(_) | Atom::NativeFn(_) | Atom::Closure(_) => {
+            return Err(
+                MalError::Conversion(String::from("functions can not be written as EDN")).into(),
+            )
+        }
+    }
+    Ok(())
+}
@@ -1,94 +1,279 @@
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 
-use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use num_bigint::BigInt;
 
 use crate::atom::Atom;
+use crate::edn::{read_edn, write_edn};
+use crate::error::MalError;
+use crate::eval::global;
+use crate::io::{read_input_line, write_output};
+use crate::stack::{call_stack, caught_stack};
+use crate::trace::{trace, untrace};
 
 pub type Env = BTreeMap<String, Atom>;
 
-pub fn default_env() -> Env {
-    let mut env: Env = BTreeMap::new();
+/// The builtins of [`default_env`] that read or write outside of the program
+pub const IO_BUILTINS: &[&str] = &["println", "readline"];
 
-    env.insert(
-        String::from("+"),
-        Atom::Builtin(|args| {
-            if args.len() != 2 {
-                Err(eyre!(
-                    "Expected exactly 2 arguments, got {}. Args: {}",
-                    args.len(),
-                    Atom::List(args)
-                ))
-            } else {
-                let num1 = args[0].as_integer()?;
-                let num2 = args[1].as_integer()?;
-                Ok(Atom::Integer(num1 + num2))
-            }
-        }),
-    );
-    env.insert(
-        String::from("*"),
-        Atom::Builtin(|args| {
-            if args.len() != 2 {
-                Err(eyre!(
-                    "Expected exactly 2 arguments, got {}. Args: {}",
-                    args.len(),
-                    Atom::List(args)
-                ))
-            } else {
-                let num1 = args[0].as_integer()?;
-                let num2 = args[1].as_integer()?;
-                Ok(Atom::Integer(num1 * num2))
+/// A builtin of [`default_env`]
+pub struct Builtin {
+    pub name: &'static str,
+    /// How it is called
+    pub usage: &'static str,
+    /// What it does
+    pub doc: &'static str,
+    pub function: fn(Vec<Atom>) -> Result<Atom>,
+}
+
+/// The builtins of [`default_env`]
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "+",
+        usage: "(+ a b)",
+        doc: "Adds two integers.",
+        function: |args| arithmetic(args, i64::checked_add, |a, b| a + b),
+    },
+    Builtin {
+        name: "*",
+        usage: "(* a b)",
+        doc: "Multiplies two integers.",
+        function: |args| arithmetic(args, i64::checked_mul, |a, b| a * b),
+    },
+    Builtin {
+        name: "-",
+        usage: "(- a b)",
+        doc: "Subtracts b from a.",
+        function: |args| arithmetic(args, i64::checked_sub, |a, b| a - b),
+    },
+    Builtin {
+        name: "/",
+        usage: "(/ a b)",
+        doc: "Divides a by b, rounding towards zero.",
+        function: |args| {
+            MalError::check_arity(&args, 2..=2)?;
+            if args[1] == Atom::Integer(0) {
+                return Err(MalError::DivisionByZero.into());
             }
-        }),
-    );
-    env.insert(
-        String::from("-"),
-        Atom::Builtin(|args| {
-            if args.len() != 2 {
-                Err(eyre!(
-                    "Expected exactly 2 arguments, got {}. Args: {}",
-                    args.len(),
-                    Atom::List(args)
-                ))
-            } else {
-                let num1 = args[0].as_integer()?;
-                let num2 = args[1].as_integer()?;
-                Ok(Atom::Integer(num1 - num2))
+            arithmetic(args, i64::checked_div, |a, b| a / b)
+        },
+    },
+    Builtin {
+        name: "%",
+        usage: "(% a b)",
+        doc: "Returns the remainder of dividing a by b.",
+        function: |args| {
+            MalError::check_arity(&args, 2..=2)?;
+            if args[1] == Atom::Integer(0) {
+                return Err(MalError::DivisionByZero.into());
             }
-        }),
-    );
-    env.insert(
-        String::from("/"),
-        Atom::Builtin(|args| {
-            if args.len() != 2 {
-                Err(eyre!(
-                    "Expected exactly 2 arguments, got {}. Args: {}",
-                    args.len(),
-                    Atom::List(args)
-                ))
-            } else {
-                let num1 = args[0].as_integer()?;
-                let num2 = args[1].as_integer()?;
-                Ok(Atom::Integer(num1 / num2))
+            arithmetic(args, i64::checked_rem, |a, b| a % b)
+        },
+    },
+    Builtin {
+        name: "=",
+        usage: "(= a b)",
+        doc: "Returns whether a and b are equal. Lists and vectors with equal elements are equal.",
+        function: |args| {
+            MalError::check_arity(&args, 2..=2)?;
+            Ok(Atom::Bool(equal(&args[0], &args[1])))
+        },
+    },
+    Builtin {
+        name: "<",
+        usage: "(< a b)",
+        doc: "Returns whether the integer a is less than b.",
+        function: |args| comparison(args, Ordering::is_lt),
+    },
+    Builtin {
+        name: "<=",
+        usage: "(<= a b)",
+        doc: "Returns whether the integer a is at most b.",
+        function: |args| comparison(args, Ordering::is_le),
+    },
+    Builtin {
+        name: ">",
+        usage: "(> a b)",
+        doc: "Returns whether the integer a is greater than b.",
+        function: |args| comparison(args, Ordering::is_gt),
+    },
+    Builtin {
+        name: ">=",
+        usage: "(>= a b)",
+        doc: "Returns whether the integer a is at least b.",
+        function: |args| comparison(args, Ordering::is_ge),
+    },
+    Builtin {
+        name: "throw",
+        usage: "(throw value)",
+        doc: "Raises value as an exception.",
+        function: |args| {
+            MalError::check_arity(&args, 1..=1)?;
+            Err(MalError::Thrown(args[0].clone()).into())
+        },
+    },
+    Builtin {
+        name: "edn/read-string",
+        usage: "(edn/read-string s)",
+        doc: "Reads the first EDN value in the string s.",
+        function: |args| {
+            MalError::check_arity(&args, 1..=1)?;
+            match &args[0] {
+                Atom::String(s) => read_edn(s),
+                a => Err(MalError::type_error("string", a).into()),
             }
-        }),
-    );
-    env.insert(
-        String::from("%"),
-        Atom::Builtin(|args| {
-            if args.len() != 2 {
-                Err(eyre!(
-                    "Expected exactly 2 arguments, got {}. Args: {}",
-                    args.len(),
-                    Atom::List(args)
-                ))
-            } else {
-                let num1 = args[0].as_integer()?;
-                let num2 = args[1].as_integer()?;
-                Ok(Atom::Integer(num1 % num2))
+        },
+    },
+    Builtin {
+        name: "edn/write-string",
+        usage: "(edn/write-string value)",
+        doc: "Writes value as an EDN string.",
+        function: |args| {
+            MalError::check_arity(&args, 1..=1)?;
+            Ok(Atom::String(write_edn(&args[0])?))
+        },
+    },
+    Builtin {
+        name: "println",
+        usage: "(println & values)",
+        doc: "Prints the values separated by spaces, followed by a newline. Strings are printed \
+         without quotes.",
+        function: |args| {
+            let line = args
+                .iter()
+                .map(Atom::to_plain_string)
+                .collect::<Vec<_>>()
+                .join(" ");
+            write_output(&format!("{}\n", line))?;
+            Ok(Atom::Nil)
+        },
+    },
+    Builtin {
+        name: "readline",
+        usage: "(readline prompt)",
+        doc: "Prints prompt and reads a line of input. Returns nil at end of file.",
+        function: |args| {
+            MalError::check_arity(&args, 1..=1)?;
+            write_output(&args[0].to_plain_string())?;
+            Ok(read_input_line()?.map_or(Atom::Nil, Atom::String))
+        },
+    },
+    Builtin {
+        name: "stacktrace",
+        usage: "(stacktrace)",
+        doc: "Returns the calls that are being evaluated, innermost first. In the handler of \
+         catch*, returns the calls from where the error was raised.",
+        function: |args| {
+            MalError::check_arity(&args, 0..=0)?;
+            let frames = caught_stack().unwrap_or_else(call_stack);
+            Ok(Atom::List(
+                frames.into_iter().rev().map(|x| x.form).collect(),
+            ))
+        },
+    },
+    Builtin {
+        name: "trace",
+        usage: "(trace 'f)",
+        doc: "Logs every call to the function named f, and what it returned, to the error output.",
+        function: |args| {
+            MalError::check_arity(&args, 1..=1)?;
+            let f = named_function(&args[0])?;
+            trace(&f);
+            Ok(f)
+        },
+    },
+    Builtin {
+        name: "untrace",
+        usage: "(untrace 'f) (untrace)",
+        doc: "Stops tracing the function named f, or all functions if no name is given.",
+        function: |args| {
+            MalError::check_arity(&args, 0..=1)?;
+            match args.first() {
+                Some(name) => untrace(Some(&named_function(name)?)),
+                None => untrace(None),
             }
-        }),
-    );
+            Ok(Atom::Nil)
+        },
+    },
+];
+
+/// Returns the builtin of [`default_env`] called `name`.
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|x| x.name == name)
+}
+
+/// Returns the function that the symbol `name` is bound to, for `trace` and `untrace`.
+fn named_function(name: &Atom) -> Result<Atom> {
+    let sym = match name {
+        Atom::Symbol(sym) => sym,
+        a => return Err(MalError::type_error("symbol", a).into()),
+    };
+    match global(sym) {
+        Some(f @ (Atom::Builtin(_) | Atom::NativeFn(_) | Atom::Closure(_))) => Ok(f),
+        Some(a) => Err(MalError::type_error("function", &a).into()),
+        None => Err(MalError::UnboundSymbol(sym.clone()).into()),
+    }
+}
+
+/// Applies an operation to two integer arguments. The operation is first tried with `small`,
+/// and redone with `big` if that overflows, so results are always exact.
+fn arithmetic(
+    args: Vec<Atom>,
+    small: fn(i64, i64) -> Option<i64>,
+    big: fn(BigInt, BigInt) -> BigInt,
+) -> Result<Atom> {
+    MalError::check_arity(&args, 2..=2)?;
+    if let (Atom::Integer(num1), Atom::Integer(num2)) = (&args[0], &args[1]) {
+        if let Some(res) = small(*num1, *num2) {
+            return Ok(Atom::Integer(res));
+        }
+    }
+    let num1 = args[0].as_bigint()?;
+    let num2 = args[1].as_bigint()?;
+    Ok(Atom::from_bigint(big(num1, num2)))
+}
+
+/// Compares two integer arguments, and returns whether `test` accepts how they are ordered.
+fn comparison(args: Vec<Atom>, test: fn(Ordering) -> bool) -> Result<Atom> {
+    MalError::check_arity(&args, 2..=2)?;
+    let ordering = match (&args[0], &args[1]) {
+        (Atom::Integer(num1), Atom::Integer(num2)) => num1.cmp(num2),
+        _ => args[0].as_bigint()?.cmp(&args[1].as_bigint()?),
+    };
+    Ok(Atom::Bool(test(ordering)))
+}
+
+/// Returns whether `a` and `b` are equal, where lists and vectors with equal elements are equal.
+fn equal(a: &Atom, b: &Atom) -> bool {
+    match (a, b) {
+        (Atom::List(a) | Atom::Vector(a), Atom::List(b) | Atom::Vector(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b))
+        }
+        (Atom::HashMap(a), Atom::HashMap(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, v)| b.get(k).is_some_and(|other| equal(v, other)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Returns an environment with the [`BUILTINS`].
+pub fn default_env() -> Env {
+    BUILTINS
+        .iter()
+        .map(|x| (x.name.to_string(), Atom::Builtin(x.function)))
+        .collect()
+}
 
+/// Like [`default_env`], but without the builtins in [`IO_BUILTINS`], for running code that is
+/// not trusted. The special forms that do IO are rejected by setting
+/// [`Limits::sandbox`](crate::limits::Limits::sandbox).
+pub fn sandbox_env() -> Env {
+    let mut env = default_env();
+    for name in IO_BUILTINS {
+        env.remove(*name);
+    }
     env
 }
@@ -0,0 +1,156 @@
+use std::ops::{Range, RangeInclusive};
+
+use crate::atom::Atom;
+
+/// The errors that can happen while reading or evaluating mal code.
+///
+/// This implements [`std::error::Error`], so it can be returned with `?` from any function
+/// returning a [`color_eyre::Result`]. Code that needs to know what went wrong can get it back
+/// with `report.downcast_ref::<MalError>()`, even if context was added to the report.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MalError {
+    /// A value of the wrong type was given, for example a string to `+`.
+    Type { expected: String, got: Atom },
+    /// A function was called with the wrong number of arguments.
+    Arity {
+        expected: RangeInclusive<usize>,
+        got: usize,
+    },
+    /// A symbol was evaluated, but it is not bound in the environment.
+    UnboundSymbol(String),
+    /// The source could not be read. `span` is the byte range in the source where the error was
+    /// found.
+    Reader { message: String, span: Range<usize> },
+    /// An index was outside of the bounds of a sequence.
+    IndexOutOfRange { index: i64, len: usize },
+    /// An integer was divided by zero.
+    DivisionByZero,
+    /// A value could not be converted to or from a Rust type.
+    Conversion(String),
+    /// A value was thrown with `throw`.
+    Thrown(Atom),
+    /// The evaluation was stopped before it finished, for example from the debugger.
+    Interrupted,
+    /// The evaluation used more resources than its [`Limits`](crate::limits::Limits) allow.
+    LimitExceeded(Limit),
+    /// A form that changes the environment, like `require`, was used inside of another form, or
+    /// `def!` was evaluated without [`eval_toplevel`](crate::eval::eval_toplevel).
+    TopLevelOnly(String),
+    /// A required module is not on the load path.
+    ModuleNotFound(String),
+    /// Modules require each other. The first and last module are the same.
+    CyclicRequire(Vec<String>),
+    /// A value does not have the shape of the binding form it is bound to, or the binding form
+    /// is not valid.
+    Destructure { binding: Atom, message: String },
+    /// `recur` was used outside of the tail position of a `fn*` or `loop`.
+    RecurNotInTailPosition,
+    /// A special form that does IO was used in a sandbox.
+    NotAllowed(String),
+}
+
+/// A resource that can be limited with [`Limits`](crate::limits::Limits)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Fuel,
+    Depth,
+    Allocation,
+    Deadline,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Fuel => write!(f, "ran out of fuel"),
+            Limit::Depth => write!(f, "maximum depth of calls or nested forms exceeded"),
+            Limit::Allocation => write!(f, "allocation budget exceeded"),
+            Limit::Deadline => write!(f, "deadline exceeded"),
+        }
+    }
+}
+
+impl MalError {
+    pub fn type_error(expected: &str, got: &Atom) -> Self {
+        MalError::Type {
+            expected: expected.to_string(),
+            got: got.clone(),
+        }
+    }
+
+    pub fn reader(message: &str, span: Range<usize>) -> Self {
+        MalError::Reader {
+            message: message.to_string(),
+            span,
+        }
+    }
+
+    /// Returns an arity error if the number of arguments is not in the `expected` range.
+    pub fn check_arity(args: &[Atom], expected: RangeInclusive<usize>) -> Result<(), Self> {
+        if expected.contains(&args.len()) {
+            Ok(())
+        } else {
+            Err(MalError::Arity {
+                expected,
+                got: args.len(),
+            })
+        }
+    }
+}
+
+impl std::fmt::Display for MalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MalError::Type { expected, got } => write!(
+                f,
+                "type error: expected {} but got {}, which is the wrong type",
+                expected, got
+            ),
+            MalError::Arity { expected, got } => {
+                if expected.start() == expected.end() {
+                    write!(f, "expected exactly {} arguments", expected.start())?;
+                } else if *expected.end() == usize::MAX {
+                    write!(f, "expected at least {} arguments", expected.start())?;
+                } else {
+                    write!(
+                        f,
+                        "expected between {} and {} arguments",
+                        expected.start(),
+                        expected.end()
+                    )?;
+                }
+                write!(f, ", got {}", got)
+            }
+            MalError::UnboundSymbol(sym) => write!(f, "symbol {} is not bound to any value", sym),
+            MalError::Reader { message, span } => {
+                write!(f, "{} (at position {})", message, span.start)
+            }
+            MalError::IndexOutOfRange { index, len } => write!(
+                f,
+                "index {} is out of range for a sequence of length {}",
+                index, len
+            ),
+            MalError::DivisionByZero => write!(f, "division by zero"),
+            MalError::Conversion(message) => write!(f, "conversion error: {}", message),
+            MalError::Thrown(atom) => write!(f, "uncaught exception: {}", atom),
+            MalError::Interrupted => write!(f, "interrupted"),
+            MalError::LimitExceeded(limit) => write!(f, "limit exceeded: {}", limit),
+            MalError::TopLevelOnly(form) => write!(f, "{} can only be used at the top level", form),
+            MalError::ModuleNotFound(name) => write!(f, "module {} not found on MAL_PATH", name),
+            MalError::CyclicRequire(modules) => {
+                write!(f, "cyclic require: {}", modules.join(" -> "))
+            }
+            MalError::Destructure { binding, message } => {
+                write!(f, "can not bind {}: {}", binding, message)
+            }
+            MalError::RecurNotInTailPosition => {
+                write!(
+                    f,
+                    "recur can only be used in the tail position of fn* or loop"
+                )
+            }
+            MalError::NotAllowed(form) => write!(f, "{} is not allowed in the sandbox", form),
+        }
+    }
+}
+
+impl std::error::Error for MalError {}
@@ -0,0 +1,655 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use color_eyre::{eyre::WrapErr, Result};
+
+use crate::atom::{Atom, Closure};
+use crate::compiler::compile;
+use crate::debug;
+use crate::destructure;
+use crate::env::Env;
+use crate::error::MalError;
+use crate::interrupt;
+use crate::limits;
+use crate::module;
+use crate::profile;
+use crate::stack::{
+    add_locations, call_stack, clear_backtrace, push_frame, record_error, set_caught,
+    take_backtrace, Frame,
+};
+use crate::trace;
+use crate::vm;
+
+/// Number of forms that were evaluated
+pub static EVAL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// How forms are evaluated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Walks the tree of the form, with [`walk`]
+    #[default]
+    TreeWalker,
+    /// Compiles the form to bytecode, and runs it on the [`vm`]
+    Vm,
+}
+
+thread_local! {
+    static ENGINE: Cell<Engine> = const { Cell::new(Engine::TreeWalker) };
+    /// The environment that [`eval_toplevel`] is evaluating a form in, where `def!` binds names.
+    /// Closures look up the symbols they don't bind here when they are called, so that they see
+    /// the definitions made after them.
+    static GLOBALS: RefCell<Option<Env>> = const { RefCell::new(None) };
+}
+
+/// Makes [`eval`] use `engine` on this thread, and returns the previous engine.
+pub fn set_engine(engine: Engine) -> Engine {
+    ENGINE.with(|x| x.replace(engine))
+}
+
+/// Returns the engine that [`eval`] uses on this thread.
+pub fn engine() -> Engine {
+    ENGINE.with(|x| x.get())
+}
+
+/// Puts back the engine that was used before [`with_engine`] when dropped.
+struct EngineGuard(Engine);
+
+impl Drop for EngineGuard {
+    fn drop(&mut self) {
+        set_engine(self.0);
+    }
+}
+
+/// Calls `f` with [`eval`] using `engine`.
+pub fn with_engine<T>(engine: Engine, f: impl FnOnce() -> T) -> T {
+    let _guard = EngineGuard(set_engine(engine));
+    f()
+}
+
+/// Puts back the environment that was moved into [`GLOBALS`] by [`with_globals`], and the
+/// globals from before, when dropped.
+struct GlobalsGuard<'a> {
+    env: &'a mut Env,
+    previous: Option<Env>,
+}
+
+impl Drop for GlobalsGuard<'_> {
+    fn drop(&mut self) {
+        let globals = GLOBALS.with(|x| x.replace(self.previous.take()));
+        *self.env = globals.unwrap_or_default();
+    }
+}
+
+/// Calls `f` with `env` as the [`GLOBALS`], and then puts them back in `env`.
+fn with_globals<T>(env: &mut Env, f: impl FnOnce() -> T) -> T {
+    let previous = GLOBALS.with(|x| x.replace(Some(std::mem::take(env))));
+    let _guard = GlobalsGuard { env, previous };
+    f()
+}
+
+/// Returns the value of the symbol `sym` that is not bound locally: in the module that the
+/// running function was defined in, or else in the environment of the top-level form that is
+/// being evaluated.
+pub(crate) fn global(sym: &str) -> Option<Atom> {
+    if let Some(value) = module::global(sym) {
+        return value;
+    }
+    GLOBALS.with(|x| x.borrow().as_ref()?.get(sym).cloned())
+}
+
+/// Returns the bindings of the top-level form that is being evaluated.
+pub(crate) fn globals() -> Env {
+    GLOBALS.with(|x| x.borrow().clone().unwrap_or_default())
+}
+
+/// Evaluates `ast` with the engine selected by [`set_engine`].
+pub fn eval(ast: &Atom, env: &Env) -> Result<Atom> {
+    match engine() {
+        Engine::TreeWalker => walk(ast, env),
+        Engine::Vm => vm::run(&compile(ast), env),
+    }
+}
+
+/// Evaluates `ast` by walking its tree.
+pub fn walk(ast: &Atom, env: &Env) -> Result<Atom> {
+    match walk_tail(ast, env, Tail::default())? {
+        Flow::Value(value) => Ok(value),
+        _ => panic!("Expected a value outside of a tail position (this should never happen)"),
+    }
+}
+
+/// What a form in a tail position may leave to the form around it
+#[derive(Clone, Copy, Debug, Default)]
+struct Tail {
+    /// Calls to closures can be left to [`call_closure`], so that they don't use the Rust stack
+    calls: bool,
+    /// `recur` can be used, and is left to the `fn*` or `loop` around it
+    recur: bool,
+}
+
+/// The result of walking a form in a tail position
+enum Flow {
+    Value(Atom),
+    /// The closure should be called with the arguments
+    TailCall(Arc<Closure>, Vec<Atom>),
+    /// The innermost `fn*` or `loop` should be evaluated again with these values
+    Recur(Vec<Atom>),
+}
+
+fn walk_tail(ast: &Atom, env: &Env, tail: Tail) -> Result<Flow> {
+    EVAL_COUNT.fetch_add(1, Ordering::Relaxed);
+    interrupt::check()?;
+    limits::step()?;
+    let _nesting = limits::nest()?;
+    let _depth = debug::before_eval(ast, env)?;
+    match ast {
+        Atom::List(lst) => {
+            if lst.is_empty() {
+                Ok(Flow::Value(ast.clone()))
+            } else if let Some(res) = eval_special_form(lst, env, tail) {
+                res
+            } else {
+                let _frame = push_frame(ast);
+                let res = limits::check_depth()
+                    .map_err(Into::into)
+                    .and_then(|()| eval_ast(ast, env))
+                    .and_then(|evaluated| {
+                        let mut evaluated = match evaluated {
+                            Atom::List(evaluated) => evaluated,
+                            a => {
+                                panic!("Expected a list, but got {} (this should never happen)", a)
+                            }
+                        };
+                        let args = evaluated.split_off(1);
+                        match evaluated.pop() {
+                            // traced and profiled calls have to return to be logged
+                            Some(Atom::Closure(f))
+                                if tail.calls
+                                    && !profile::is_running()
+                                    && !trace::is_traced(&Atom::Closure(f.clone())) =>
+                            {
+                                Ok(Flow::TailCall(f, args))
+                            }
+                            Some(f) => {
+                                let value = profile::call(&lst[0], || {
+                                    trace::apply_traced(&lst[0], &f, args)
+                                })?;
+                                Ok(Flow::Value(value))
+                            }
+                            None => panic!("Expected a function (this should never happen)"),
+                        }
+                    });
+                if res.is_err() {
+                    record_error();
+                }
+                res
+            }
+        }
+        a => eval_ast(a, env).map(Flow::Value),
+    }
+}
+
+/// The special forms, which [`walk`] evaluates itself instead of calling a function
+pub const SPECIAL_FORMS: &[&str] = &[
+    "quote",
+    "let*",
+    "if",
+    "do",
+    "fn*",
+    "loop",
+    "recur",
+    "try*",
+    "debug",
+    "break",
+    "profile",
+    "trace-all",
+    "def!",
+    "ns",
+    "require",
+];
+
+/// Returns whether `sym` names one of the [`SPECIAL_FORMS`].
+pub(crate) fn is_special_form(sym: &str) -> bool {
+    SPECIAL_FORMS.contains(&sym)
+}
+
+/// The special forms that do IO or read files, which are not allowed in a sandbox
+pub const IO_SPECIAL_FORMS: &[&str] = &["debug", "break", "profile", "require"];
+
+/// Evaluates `lst` if it is a special form, or returns `None` if it is a normal call.
+fn eval_special_form(lst: &[Atom], env: &Env, tail: Tail) -> Option<Result<Flow>> {
+    if let Atom::Symbol(sym) = &lst[0] {
+        if IO_SPECIAL_FORMS.contains(&sym.as_str()) {
+            if let Err(e) = limits::check_io(sym) {
+                return Some(Err(e.into()));
+            }
+        }
+    }
+    let res = match &lst[0] {
+        Atom::Symbol(sym) if sym == "quote" => MalError::check_arity(&lst[1..], 1..=1)
+            .map(|()| Flow::Value(lst[1].clone()))
+            .map_err(Into::into),
+        Atom::Symbol(sym) if sym == "let*" => MalError::check_arity(&lst[1..], 2..=2)
+            .map_err(Into::into)
+            .and_then(|()| eval_let(&lst[1], &lst[2], env, tail)),
+        Atom::Symbol(sym) if sym == "if" => MalError::check_arity(&lst[1..], 2..=3)
+            .map_err(Into::into)
+            .and_then(|()| match walk(&lst[1], env)? {
+                Atom::Nil | Atom::Bool(false) => match lst.get(3) {
+                    Some(otherwise) => walk_tail(otherwise, env, tail),
+                    None => Ok(Flow::Value(Atom::Nil)),
+                },
+                _ => walk_tail(&lst[2], env, tail),
+            }),
+        Atom::Symbol(sym) if sym == "do" => match lst[1..].split_last() {
+            Some((last, forms)) => forms
+                .iter()
+                .try_for_each(|x| walk(x, env).map(drop))
+                .and_then(|()| walk_tail(last, env, tail)),
+            None => Ok(Flow::Value(Atom::Nil)),
+        },
+        Atom::Symbol(sym) if sym == "fn*" => MalError::check_arity(&lst[1..], 2..=2)
+            .map_err(Into::into)
+            .and_then(|()| make_closure(&lst[1], &lst[2], env))
+            .map(Flow::Value),
+        Atom::Symbol(sym) if sym == "loop" => MalError::check_arity(&lst[1..], 2..=2)
+            .map_err(Into::into)
+            .and_then(|()| eval_loop(&lst[1], &lst[2], env, tail)),
+        Atom::Symbol(sym) if sym == "recur" => {
+            if tail.recur {
+                lst[1..]
+                    .iter()
+                    .map(|x| walk(x, env))
+                    .collect::<Result<Vec<Atom>>>()
+                    .map(Flow::Recur)
+            } else {
+                Err(MalError::RecurNotInTailPosition.into())
+            }
+        }
+        Atom::Symbol(sym) if sym == "try*" => MalError::check_arity(&lst[1..], 1..=2)
+            .map_err(Into::into)
+            .and_then(|()| eval_try(&lst[1], lst.get(2), env))
+            .map(Flow::Value),
+        Atom::Symbol(sym) if sym == "debug" => MalError::check_arity(&lst[1..], 1..=1)
+            .map_err(Into::into)
+            .and_then(|()| debug::debug(&lst[1], env))
+            .map(Flow::Value),
+        Atom::Symbol(sym) if sym == "break" => MalError::check_arity(&lst[1..], 0..=0)
+            .map_err(Into::into)
+            .and_then(|()| debug::pause(&Atom::List(lst.to_vec()), env))
+            .map(Flow::Value),
+        Atom::Symbol(sym) if sym == "profile" => MalError::check_arity(&lst[1..], 1..=2)
+            .map_err(Into::into)
+            .and_then(|()| profile::profile(&lst[1..], env))
+            .map(Flow::Value),
+        Atom::Symbol(sym) if sym == "trace-all" => MalError::check_arity(&lst[1..], 1..=1)
+            .map_err(Into::into)
+            .and_then(|()| trace::trace_all(&lst[1], env))
+            .map(Flow::Value),
+        Atom::Symbol(sym) if sym == "def!" => MalError::check_arity(&lst[1..], 2..=2)
+            .map_err(Into::into)
+            .and_then(|()| define(&lst[1], &lst[2], env))
+            .map(Flow::Value),
+        Atom::Symbol(sym) if matches!(sym.as_str(), "ns" | "require") => {
+            Err(MalError::TopLevelOnly(sym.clone()).into())
+        }
+        _ => return None,
+    };
+    Some(res)
+}
+
+/// Binds the symbol `name` to the value of `value` in the [`GLOBALS`]. This implements `(def!)`.
+fn define(name: &Atom, value: &Atom, env: &Env) -> Result<Atom> {
+    let name = match name {
+        Atom::Symbol(name) => name,
+        a => return Err(MalError::type_error("symbol", a).into()),
+    };
+    if GLOBALS.with(|x| x.borrow().is_none()) {
+        return Err(MalError::TopLevelOnly(String::from("def!")).into());
+    }
+    let value = match eval(value, env)? {
+        // name the function, so that it can call itself
+        Atom::Closure(f) if f.name.is_none() => Atom::Closure(Arc::new(f.named(name))),
+        value => value,
+    };
+    GLOBALS.with(|x| {
+        if let Some(globals) = x.borrow_mut().as_mut() {
+            globals.insert(name.clone(), value.clone());
+        }
+    });
+    Ok(value)
+}
+
+/// Evaluates `body`, and if that raises an error, evaluates the handler of `catch`, which is
+/// like `(catch* e handler)`, with `e` bound to the error and `*stack*` to the call stack from
+/// where it was raised. This implements `(try*)`.
+fn eval_try(body: &Atom, catch: Option<&Atom>, env: &Env) -> Result<Atom> {
+    let catch = catch.map(parse_catch).transpose()?;
+    // a backtrace from before is not of an error that this can catch
+    clear_backtrace();
+    let (e, (binding, handler)) = match (walk(body, env), catch) {
+        (Err(e), Some(catch)) => (e, catch),
+        (res, _) => return res,
+    };
+    let value = match caught_value(&e) {
+        Some(value) => value,
+        None => return Err(e),
+    };
+    // without a backtrace, the error was raised by a form of this call
+    let frames = take_backtrace().unwrap_or_else(call_stack);
+    let mut inner = env.clone();
+    inner.insert(String::from("*stack*"), stack_value(&frames));
+    destructure::bind(binding, value, &mut inner)?;
+    let _caught = set_caught(frames);
+    walk(handler, &inner)
+}
+
+/// Returns the binding form and the handler of a `(catch* e handler)` form.
+pub(crate) fn parse_catch(catch: &Atom) -> Result<(&Atom, &Atom)> {
+    match catch {
+        Atom::List(list) if list.len() == 3 && list[0] == Atom::Symbol(String::from("catch*")) => {
+            Ok((&list[1], &list[2]))
+        }
+        a => Err(MalError::type_error("(catch* binding handler)", a).into()),
+    }
+}
+
+/// Returns the value that `catch*` binds for the error `e`: the value that was thrown, or the
+/// message of other errors. Errors that stop the evaluation, like running out of fuel, can't be
+/// caught.
+pub(crate) fn caught_value(e: &color_eyre::Report) -> Option<Atom> {
+    match e.downcast_ref::<MalError>() {
+        Some(MalError::Interrupted | MalError::LimitExceeded(_)) => None,
+        Some(MalError::Thrown(value)) => Some(value.clone()),
+        _ => Some(Atom::String(format!("{:#}", e))),
+    }
+}
+
+/// Returns `frames` as the value of `*stack*`: a list of the calls, innermost first.
+pub(crate) fn stack_value(frames: &[Frame]) -> Atom {
+    Atom::List(
+        frames
+            .iter()
+            .rev()
+            .map(|frame| frame.form.clone())
+            .collect(),
+    )
+}
+
+/// Returns the pairs of binding forms and values of `let*` or `loop`.
+fn binding_pairs(bindings: &Atom) -> Result<&[Atom]> {
+    match bindings {
+        Atom::List(bindings) | Atom::Vector(bindings) if bindings.len() % 2 == 0 => Ok(bindings),
+        a => Err(MalError::type_error("an even number of bindings", a).into()),
+    }
+}
+
+/// Evaluates `body` with the pairs of binding forms and values in `bindings` bound, each in an
+/// environment with the ones before it. This implements `(let*)`.
+fn eval_let(bindings: &Atom, body: &Atom, env: &Env, tail: Tail) -> Result<Flow> {
+    let mut inner = env.clone();
+    for pair in binding_pairs(bindings)?.chunks(2) {
+        let value = walk(&pair[1], &inner)?;
+        destructure::bind(&pair[0], value, &mut inner)?;
+    }
+    walk_tail(body, &inner, tail)
+}
+
+/// Evaluates `body` like `let*`, and again with the binding forms bound to the values given to
+/// `recur`, until it returns without `recur`. This implements `(loop)`.
+fn eval_loop(bindings: &Atom, body: &Atom, env: &Env, tail: Tail) -> Result<Flow> {
+    let pairs = binding_pairs(bindings)?;
+    let mut inner = env.clone();
+    for pair in pairs.chunks(2) {
+        let value = walk(&pair[1], &inner)?;
+        destructure::bind(&pair[0], value, &mut inner)?;
+    }
+    let tail = Tail {
+        recur: true,
+        ..tail
+    };
+    loop {
+        let values = match walk_tail(body, &inner, tail)? {
+            Flow::Recur(values) => values,
+            flow => return Ok(flow),
+        };
+        MalError::check_arity(&values, pairs.len() / 2..=pairs.len() / 2)?;
+        inner = env.clone();
+        for (pair, value) in pairs.chunks(2).zip(values) {
+            destructure::bind(&pair[0], value, &mut inner)?;
+        }
+    }
+}
+
+/// Makes a closure with the parameters `params` and the body `body`. This implements `(fn*)`.
+///
+/// The closure keeps the bindings in `env` of the symbols that `params` and `body` use, which
+/// are its locals. The other symbols are looked up with [`global`] when it is called, so it sees
+/// the definitions made after it.
+fn make_closure(params: &Atom, body: &Atom, env: &Env) -> Result<Atom> {
+    let (fixed, rest) = destructure::params(params)?;
+    let mut captured = Env::new();
+    capture(params, env, &mut captured);
+    capture(body, env, &mut captured);
+    Ok(Atom::Closure(Arc::new(Closure::new(
+        fixed,
+        rest,
+        body.clone(),
+        captured,
+    ))))
+}
+
+/// Copies the bindings in `env` of the symbols in `ast` to `captured`.
+pub(crate) fn capture(ast: &Atom, env: &Env, captured: &mut Env) {
+    match ast {
+        Atom::Symbol(sym) => {
+            if let Some(value) = env.get(sym) {
+                captured.insert(sym.clone(), value.clone());
+            }
+        }
+        Atom::List(list) | Atom::Vector(list) => {
+            list.iter().for_each(|x| capture(x, env, captured));
+        }
+        Atom::HashMap(map) => map.iter().for_each(|(k, v)| {
+            capture(k, env, captured);
+            capture(v, env, captured);
+        }),
+        Atom::Set(set) => set.iter().for_each(|x| capture(x, env, captured)),
+        _ => {}
+    }
+}
+
+/// Calls the closure `f` with `args`. Calls in tail position in its body, and `recur`, are
+/// evaluated in a loop here, so that they don't use the Rust stack.
+fn call_closure(f: &Arc<Closure>, args: Vec<Atom>) -> Result<Atom> {
+    let mut f = f.clone();
+    let mut args = args;
+    let mut recur = false;
+    loop {
+        let _module = module::enter(f.module.clone());
+        let env = bind_args(&f, args, recur)?;
+        let tail = Tail {
+            calls: true,
+            recur: true,
+        };
+        (f, args, recur) = match walk_tail(&f.body, &env, tail)? {
+            Flow::Value(value) => return Ok(value),
+            Flow::TailCall(g, args) => (g, args, false),
+            Flow::Recur(args) => (f, args, true),
+        };
+    }
+}
+
+/// Returns the environment for the body of `f`, with its parameters bound to `args`. The values
+/// given to `recur` have one value for the rest parameter instead of the rest of the arguments.
+fn bind_args(f: &Arc<Closure>, mut args: Vec<Atom>, recur: bool) -> Result<Env> {
+    let fixed = f.params.len();
+    let expected = match (&f.rest, recur) {
+        (None, _) => fixed..=fixed,
+        (Some(_), true) => fixed + 1..=fixed + 1,
+        (Some(_), false) => fixed..=usize::MAX,
+    };
+    MalError::check_arity(&args, expected)?;
+    let mut env = f.env.clone();
+    if let Some(name) = &f.name {
+        env.insert(name.clone(), Atom::Closure(f.clone()));
+    }
+    let rest = args.split_off(fixed);
+    for (param, arg) in f.params.iter().zip(args) {
+        destructure::bind(param, arg, &mut env)?;
+    }
+    if let Some(param) = &f.rest {
+        let rest = if recur {
+            rest.into_iter().next().unwrap_or(Atom::Nil)
+        } else if rest.is_empty() {
+            Atom::Nil
+        } else {
+            Atom::List(rest)
+        };
+        destructure::bind(param, rest, &mut env)?;
+    }
+    Ok(env)
+}
+
+/// Calls `f` with `args`. Like in Clojure, keywords, maps and vectors can also be called:
+/// `(:k m default)` and `(m :k default)` look up `:k` in `m`, and `(v i)` gets the `i`th element
+/// of `v`.
+pub fn apply(f: &Atom, args: Vec<Atom>) -> Result<Atom> {
+    match f {
+        // values that builtins return are made in Rust, so they are counted here
+        Atom::Builtin(builtin) => {
+            let value = builtin(args)?;
+            limits::allocate(&value)?;
+            Ok(value)
+        }
+        Atom::NativeFn(native) => {
+            let value = (native.f)(args)?;
+            limits::allocate(&value)?;
+            Ok(value)
+        }
+        Atom::Closure(closure) => match engine() {
+            Engine::TreeWalker => call_closure(closure, args),
+            Engine::Vm => vm::call_closure(closure, args),
+        },
+        Atom::Keyword(_) => {
+            MalError::check_arity(&args, 1..=2)?;
+            let default = args.get(1).cloned().unwrap_or(Atom::Nil);
+            match &args[0] {
+                Atom::HashMap(map) => Ok(map.get(f).cloned().unwrap_or(default)),
+                _ => Ok(default),
+            }
+        }
+        Atom::HashMap(map) => {
+            MalError::check_arity(&args, 1..=2)?;
+            let default = args.get(1).cloned().unwrap_or(Atom::Nil);
+            Ok(map.get(&args[0]).cloned().unwrap_or(default))
+        }
+        Atom::Vector(vec) => {
+            MalError::check_arity(&args, 1..=1)?;
+            let index = args[0].as_integer()?;
+            usize::try_from(index)
+                .ok()
+                .and_then(|i| vec.get(i))
+                .cloned()
+                .ok_or_else(|| {
+                    MalError::IndexOutOfRange {
+                        index,
+                        len: vec.len(),
+                    }
+                    .into()
+                })
+        }
+        a => Err(MalError::type_error("a function or builtin", a).into()),
+    }
+}
+
+fn eval_ast(ast: &Atom, env: &Env) -> Result<Atom> {
+    match ast {
+        Atom::Symbol(sym) => env
+            .get(sym)
+            .cloned()
+            .or_else(|| global(sym))
+            .ok_or_else(|| MalError::UnboundSymbol(sym.clone()).into()),
+        Atom::List(lst) => Ok(Atom::List(
+            lst.iter()
+                .map(|x| walk(x, env))
+                .collect::<Result<Vec<Atom>>>()?,
+        )),
+        Atom::Vector(lst) => {
+            let items = lst
+                .iter()
+                .map(|x| walk(x, env))
+                .collect::<Result<Vec<Atom>>>()?;
+            limits::allocate_collection(items.len())?;
+            Ok(Atom::Vector(items))
+        }
+        Atom::HashMap(map) => {
+            let mut res = BTreeMap::new();
+            for (k, v) in map.iter() {
+                res.insert(walk(k, env)?, walk(v, env)?);
+            }
+            limits::allocate_collection(map.len())?;
+            Ok(Atom::HashMap(res))
+        }
+        a => Ok(a.clone()),
+    }
+}
+
+/// Evaluates all forms in `source`, and returns the value of the last one. A `#!` line at the
+/// start of `source` is skipped, so that scripts can be made executable.
+pub fn eval_source(source: String, env: &mut Env) -> Result<Atom> {
+    let mut res = Atom::Nil;
+    for atom in crate::reader::read_all(skip_shebang(source))? {
+        res = eval_toplevel(&atom, env)?;
+    }
+    Ok(res)
+}
+
+/// Like [`eval_source`], for source that was read from the file `name`. Backtraces say where in
+/// the file their calls are.
+pub fn eval_named_source(name: &str, source: String, env: &mut Env) -> Result<Atom> {
+    let source = skip_shebang(source);
+    let (forms, spans) = crate::reader::read_all_with_spans(source.clone())?;
+    add_locations(name, &source, spans);
+    let mut res = Atom::Nil;
+    for atom in forms {
+        res = eval_toplevel(&atom, env)?;
+    }
+    Ok(res)
+}
+
+/// Blanks out a `#!` line at the start of `source`.
+fn skip_shebang(source: String) -> String {
+    if source.starts_with("#!") {
+        // blank out the line instead of removing it, so that reader errors have the right position
+        let end = source.find('\n').unwrap_or(source.len());
+        " ".repeat(end) + &source[end..]
+    } else {
+        source
+    }
+}
+
+/// Evaluates a form that is not inside of another form. Unlike [`eval`], this can change `env`
+/// with `def!`, which can also be used inside of the form, and with `ns` and `require`.
+pub fn eval_toplevel(ast: &Atom, env: &mut Env) -> Result<Atom> {
+    if let Atom::List(lst) = ast {
+        match lst.first() {
+            Some(Atom::Symbol(sym)) if sym == "ns" => return module::ns(&lst[1..], env),
+            Some(Atom::Symbol(sym)) if sym == "require" => {
+                limits::check_io(sym)?;
+                return module::require(&lst[1..], env);
+            }
+            _ => {}
+        }
+    }
+    with_globals(env, || eval(ast, &Env::new()))
+}
+
+/// Evaluates all forms in the file at `path`, and returns the value of the last one.
+pub fn load_file(path: &str, env: &mut Env) -> Result<Atom> {
+    let source =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("could not read {}", path))?;
+    eval_named_source(path, source, env)
+}
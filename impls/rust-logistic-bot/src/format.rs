@@ -0,0 +1,168 @@
+//! Formatting of mal source, used by `malfmt`.
+//!
+//! The formatter keeps the line breaks and comments of the source, and normalizes everything
+//! else:
+//!
+//! - lines are indented with the usual Lisp rules: elements of vectors and maps are aligned,
+//!   arguments of a call are aligned with the first argument if it is on the same line as the
+//!   function, and the bodies of special forms and `def` forms are indented by two spaces
+//! - spaces between forms on the same line are collapsed into one, but spaces before comments
+//!   are kept, so that aligned comments stay aligned
+//! - whitespace after opening brackets and before closing brackets is removed
+//! - trailing whitespace is removed, and there is at most one blank line in a row
+//! - the output ends with exactly one newline
+
+use color_eyre::Result;
+
+use crate::cst::{parse, Node, NodeKind};
+
+/// Forms whose body is indented by two spaces, instead of being aligned with the first argument.
+/// These are special forms from [`crate::eval::SPECIAL_FORMS`], and `catch*`, which is only
+/// special inside of `try*`.
+const BODY_FORMS: &[&str] = &[
+    "catch*",
+    "debug",
+    "do",
+    "fn*",
+    "if",
+    "let*",
+    "loop",
+    "ns",
+    "profile",
+    "trace-all",
+    "try*",
+];
+
+/// Formats mal source.
+pub fn format_source(source: &str) -> Result<String> {
+    let nodes = parse(source)?;
+    let mut writer = Writer::default();
+    writer.nodes(&nodes, Indent::Fixed(0), true);
+    let mut res = writer.out;
+    res.truncate(res.trim_end().len());
+    if !res.is_empty() {
+        res.push('\n');
+    }
+    Ok(res)
+}
+
+/// How the lines in a list, vector or map are indented
+enum Indent {
+    /// Always at this column
+    Fixed(usize),
+    /// Aligned with the first argument if it is on the same line as the function, otherwise at
+    /// the given column
+    Align(usize),
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    /// Column of the end of `out`, in characters
+    column: usize,
+}
+
+impl Writer {
+    fn push(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.column = match s.rfind('\n') {
+            Some(i) => s[i + 1..].chars().count(),
+            None => self.column + s.chars().count(),
+        };
+    }
+
+    /// Writes the nodes of a sequence, or of the top level if `top_level` is true.
+    fn nodes(&mut self, nodes: &[Node], mut indent: Indent, top_level: bool) {
+        let mut forms = 0;
+        let mut on_first_line = true;
+        for (i, node) in nodes.iter().enumerate() {
+            match &node.kind {
+                NodeKind::Whitespace(ws) => {
+                    let previous = i.checked_sub(1).map(|i| &nodes[i].kind);
+                    let next = nodes.get(i + 1).map(|x| &x.kind);
+                    let newlines = ws.matches('\n').count();
+                    let before_comment = matches!(next, Some(NodeKind::Comment(_)));
+                    let after_comment = matches!(previous, Some(NodeKind::Comment(_)));
+                    if next.is_none() {
+                        // a closing bracket can not go on the line of a comment
+                        if after_comment && !top_level {
+                            self.newline(1, &indent);
+                        }
+                    } else if previous.is_none() && (top_level || !(before_comment && newlines > 0))
+                    {
+                        // leading whitespace is removed
+                    } else if newlines > 0 {
+                        on_first_line = false;
+                        self.newline(newlines, &indent);
+                    } else if before_comment {
+                        self.push(&ws.replace(',', " "));
+                    } else {
+                        self.push(" ");
+                    }
+                }
+                NodeKind::Comment(comment) => self.push(comment.trim_end()),
+                _ => {
+                    if forms == 1 && on_first_line {
+                        if let Indent::Align(_) = indent {
+                            indent = Indent::Fixed(self.column);
+                        }
+                    }
+                    forms += 1;
+                    self.node(node);
+                }
+            }
+        }
+    }
+
+    /// Starts a new line, keeping one blank line if there were blank lines in the source.
+    fn newline(&mut self, newlines: usize, indent: &Indent) {
+        let column = match *indent {
+            Indent::Fixed(column) | Indent::Align(column) => column,
+        };
+        self.push(&"\n".repeat(newlines.min(2)));
+        self.push(&" ".repeat(column));
+    }
+
+    fn node(&mut self, node: &Node) {
+        match &node.kind {
+            NodeKind::Whitespace(_) => {}
+            NodeKind::Comment(s) | NodeKind::Token(s) => self.push(s),
+            NodeKind::Seq {
+                open,
+                close,
+                children,
+            } => {
+                let open_column = self.column;
+                self.push(&open.to_string());
+                let indent = if *open != '(' {
+                    Indent::Fixed(open_column + 1)
+                } else {
+                    let head = node.forms().next().and_then(|x| x.token());
+                    match head {
+                        Some(head) if BODY_FORMS.contains(&head) || head.starts_with("def") => {
+                            Indent::Fixed(open_column + 2)
+                        }
+                        _ => Indent::Align(open_column + 1),
+                    }
+                };
+                self.nodes(children, indent, false);
+                self.push(&close.to_string());
+            }
+            NodeKind::Prefix {
+                prefix,
+                trivia,
+                form,
+            } => {
+                self.push(prefix);
+                if trivia
+                    .iter()
+                    .any(|x| matches!(x.kind, NodeKind::Comment(_)))
+                {
+                    let column = self.column;
+                    self.nodes(trivia, Indent::Fixed(column), false);
+                }
+                self.node(form);
+            }
+        }
+    }
+}
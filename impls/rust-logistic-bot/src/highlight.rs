@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::atom::Atom;
+use crate::eval::SPECIAL_FORMS;
+use crate::reader::parse_integer;
+
+const RESET: &str = "\x1b[0m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[36m";
+const KEYWORD: &str = "\x1b[35m";
+const CONSTANT: &str = "\x1b[34m";
+const COMMENT: &str = "\x1b[90m";
+const SPECIAL_FORM: &str = "\x1b[1;33m";
+const MATCHING_BRACKET: &str = "\x1b[1;4m";
+const UNBALANCED: &str = "\x1b[1;31m";
+
+/// A part of a line of mal source, with the color it should be highlighted with
+struct Segment {
+    range: Range<usize>,
+    color: Option<&'static str>,
+}
+
+/// Splits a line of source into segments. Whitespace is not part of any segment.
+fn segments(line: &str) -> Vec<Segment> {
+    static TOKEN: OnceLock<Regex> = OnceLock::new();
+    let re = TOKEN.get_or_init(|| {
+        Regex::new(r#"~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+"#).unwrap()
+    });
+    re.find_iter(line)
+        .map(|m| {
+            let token = m.as_str();
+            let color = if token.starts_with('"') {
+                Some(STRING)
+            } else if token.starts_with(';') {
+                Some(COMMENT)
+            } else if token.starts_with(':') {
+                Some(KEYWORD)
+            } else if parse_integer(token).is_some() {
+                Some(NUMBER)
+            } else if matches!(token, "nil" | "true" | "false") {
+                Some(CONSTANT)
+            } else if SPECIAL_FORMS.contains(&token) {
+                Some(SPECIAL_FORM)
+            } else {
+                None
+            };
+            Segment {
+                range: m.range(),
+                color,
+            }
+        })
+        .collect()
+}
+
+/// Pairs up the brackets in `segments`.
+///
+/// Returns the positions of matching brackets, in both directions, and the positions of
+/// closing brackets that do not match any opening bracket.
+fn match_brackets(line: &str, segments: &[Segment]) -> (BTreeMap<usize, usize>, Vec<usize>) {
+    let mut pairs = BTreeMap::new();
+    let mut unbalanced = Vec::new();
+    let mut stack: Vec<(usize, &str)> = Vec::new();
+    for segment in segments {
+        let start = segment.range.start;
+        let expected_opener = match &line[segment.range.clone()] {
+            opener @ ("(" | "[" | "{") => {
+                stack.push((start, opener));
+                continue;
+            }
+            ")" => "(",
+            "]" => "[",
+            "}" => "{",
+            _ => continue,
+        };
+        match stack.last() {
+            Some((open, opener)) if *opener == expected_opener => {
+                pairs.insert(*open, start);
+                pairs.insert(start, *open);
+                stack.pop();
+            }
+            _ => unbalanced.push(start),
+        }
+    }
+    (pairs, unbalanced)
+}
+
+/// Highlights a line of mal source for the terminal.
+///
+/// The bracket next to the cursor at `pos` and its matching bracket are underlined, and closing
+/// brackets without an opening bracket are red.
+pub fn highlight_source(line: &str, pos: usize) -> String {
+    let segments = segments(line);
+    let (pairs, unbalanced) = match_brackets(line, &segments);
+    // like in most editors, the bracket before the cursor has priority
+    let cursor_bracket = [pos.checked_sub(1), Some(pos)]
+        .into_iter()
+        .flatten()
+        .find(|i| pairs.contains_key(i));
+    let matching = cursor_bracket
+        .map(|i| vec![i, pairs[&i]])
+        .unwrap_or_default();
+
+    let mut res = String::with_capacity(line.len() * 2);
+    let mut last = 0;
+    for segment in segments {
+        let start = segment.range.start;
+        let color = if unbalanced.contains(&start) {
+            Some(UNBALANCED)
+        } else if matching.contains(&start) {
+            Some(MATCHING_BRACKET)
+        } else {
+            segment.color
+        };
+        res.push_str(&line[last..start]);
+        match color {
+            Some(color) => {
+                res.push_str(color);
+                res.push_str(&line[segment.range.clone()]);
+                res.push_str(RESET);
+            }
+            None => res.push_str(&line[segment.range.clone()]),
+        }
+        last = segment.range.end;
+    }
+    res.push_str(&line[last..]);
+    res
+}
+
+fn colorize_seq<'a>(items: impl Iterator<Item = &'a Atom>, open: &str, close: &str) -> String {
+    format!(
+        "{}{}{}",
+        open,
+        items.map(colorize).collect::<Vec<_>>().join(" "),
+        close
+    )
+}
+
+/// Prints an atom like its `Display` implementation, but colored by type for the terminal.
+pub fn colorize(atom: &Atom) -> String {
+    let color = match atom {
+        Atom::List(list) => return colorize_seq(list.iter(), "(", ")"),
+        Atom::Vector(list) => return colorize_seq(list.iter(), "[", "]"),
+        Atom::Set(set) => return colorize_seq(set.iter(), "#{", "}"),
+        Atom::HashMap(map) => {
+            return colorize_seq(map.iter().flat_map(|(k, v)| [k, v]), "{", "}");
+        }
+        Atom::Tagged(tag, value) => {
+            return format!("{}#{}{} {}", CONSTANT, tag, RESET, colorize(value));
+        }
+        Atom::String(_) | Atom::Char(_) => STRING,
+        Atom::Integer(_) | Atom::BigInteger(_) => NUMBER,
+        Atom::Keyword(_) => KEYWORD,
+        Atom::Nil | Atom::Bool(_) => CONSTANT,
+        Atom::Builtin(_) | Atom::NativeFn(_) | Atom::Closure(_) => COMMENT,
+        Atom::Symbol(_) => return atom.to_string(),
+    };
+    format!("{}{}{}", color, atom, RESET)
+}
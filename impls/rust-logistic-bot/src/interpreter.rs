@@ -0,0 +1,154 @@
+//! A mal interpreter for applications that embed mal, for example as a scripting or
+//! configuration language.
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use color_eyre::{eyre::WrapErr, Result};
+
+use crate::atom::{Atom, NativeFn};
+use crate::env::{default_env, Env};
+use crate::eval::{eval_named_source, eval_source, with_engine, Engine};
+use crate::io::{redirect_error_output, redirect_input, redirect_output, Redirect};
+use crate::limits::{with_limits, Limits};
+use crate::module::{with_modules, Modules};
+
+/// A mal interpreter with its own environment, input and output.
+///
+/// ```
+/// use mal::atom::Atom;
+/// use mal::interpreter::Interpreter;
+///
+/// let mut mal = Interpreter::new();
+/// mal.define("answer", 42);
+/// mal.register_builtin("double", |args| Ok(Atom::Integer(args[0].as_integer()? * 2)));
+/// assert_eq!(mal.eval_str("(double answer)").unwrap(), Atom::Integer(84));
+/// ```
+pub struct Interpreter {
+    env: Env,
+    stdout: Option<Box<dyn Write>>,
+    stderr: Option<Box<dyn Write>>,
+    stdin: Option<Box<dyn BufRead>>,
+    limits: Limits,
+    engine: Engine,
+    modules: Modules,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter with the builtins of [`default_env`], which reads from stdin and
+    /// writes to stdout.
+    pub fn new() -> Self {
+        Self::with_env(default_env())
+    }
+
+    /// Creates an interpreter with the bindings in `env`.
+    pub fn with_env(env: Env) -> Self {
+        Self {
+            env,
+            stdout: None,
+            stderr: None,
+            stdin: None,
+            limits: Limits::default(),
+            engine: Engine::default(),
+            modules: Modules::default(),
+        }
+    }
+
+    /// Sends the output of mal programs, like `println`, to `stdout`.
+    pub fn set_stdout(&mut self, stdout: impl Write + 'static) {
+        self.stdout = Some(Box::new(stdout));
+    }
+
+    /// Sends the error output of mal programs, like the calls logged by `trace`, to `stderr`.
+    pub fn set_stderr(&mut self, stderr: impl Write + 'static) {
+        self.stderr = Some(Box::new(stderr));
+    }
+
+    /// Makes mal programs, like `readline`, read from `stdin`.
+    pub fn set_stdin(&mut self, stdin: impl BufRead + 'static) {
+        self.stdin = Some(Box::new(stdin));
+    }
+
+    /// Limits the resources each call to [`Interpreter::eval_str`] or
+    /// [`Interpreter::eval_file`] may use. Use [`sandbox_env`](crate::env::sandbox_env) with
+    /// [`Interpreter::with_env`] to also keep code from doing IO.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Selects how forms are evaluated. The default is the tree-walker.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    /// Makes `require` load modules from the directories in `path`, instead of from `MAL_PATH`.
+    /// Modules that were loaded from other directories are loaded again.
+    pub fn set_load_path(&mut self, path: Vec<PathBuf>) {
+        self.modules.set_load_path(Some(path));
+    }
+
+    /// Evaluates all forms in `source`, and returns the value of the last one.
+    pub fn eval_str(&mut self, source: &str) -> Result<Atom> {
+        self.run(|env| eval_source(source.to_string(), env))
+    }
+
+    /// Evaluates all forms in the file at `path`, and returns the value of the last one.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Atom> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("could not read {}", path.display()))?;
+        self.run(|env| eval_named_source(&path.display().to_string(), source, env))
+    }
+
+    /// Runs `f` on the environment, with the engine, limits, modules, input and output of the
+    /// interpreter.
+    fn run(&mut self, f: impl FnOnce(&mut Env) -> Result<Atom>) -> Result<Atom> {
+        // without its own input and outputs, the interpreter uses those of the thread
+        let output = self.stdout.take().map(redirect_output);
+        let error_output = self.stderr.take().map(redirect_error_output);
+        let input = self.stdin.take().map(redirect_input);
+        let res = with_engine(self.engine, || {
+            with_modules(&mut self.modules, || {
+                with_limits(self.limits.clone(), || f(&mut self.env))
+            })
+        });
+        self.stdout = output.and_then(Redirect::restore);
+        self.stderr = error_output.and_then(Redirect::restore);
+        self.stdin = input.and_then(Redirect::restore);
+        res
+    }
+
+    /// Binds `name` to `value`.
+    pub fn define(&mut self, name: &str, value: impl Into<Atom>) {
+        self.env.insert(name.to_string(), value.into());
+    }
+
+    /// Binds `name` to a function implemented in Rust. Unlike the builtins of [`default_env`],
+    /// `f` can be a closure that captures state from the application.
+    pub fn register_builtin(
+        &mut self,
+        name: &str,
+        f: impl Fn(Vec<Atom>) -> Result<Atom> + Send + Sync + 'static,
+    ) {
+        self.define(name, Atom::NativeFn(NativeFn::new(name, f)));
+    }
+
+    /// Returns the value bound to `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&Atom> {
+        self.env.get(name)
+    }
+
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn env_mut(&mut self) -> &mut Env {
+        &mut self.env
+    }
+}
@@ -0,0 +1,55 @@
+//! Interrupting an evaluation from another thread, or with Ctrl-C.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use color_eyre::Result;
+
+use crate::error::MalError;
+
+/// Set by the handler installed with [`install_sigint_handler`]
+static SIGINT: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The flag that interrupts the evaluation on this thread when it is set
+    static FLAG: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Puts back the flag that was used before [`with_interrupt_flag`] when dropped.
+struct FlagGuard(Option<Arc<AtomicBool>>);
+
+impl Drop for FlagGuard {
+    fn drop(&mut self) {
+        FLAG.with(|x| x.replace(self.0.take()));
+    }
+}
+
+/// Calls `f`, and interrupts any evaluation it does when `flag` is set.
+pub fn with_interrupt_flag<T>(flag: Arc<AtomicBool>, f: impl FnOnce() -> T) -> T {
+    let _guard = FlagGuard(FLAG.with(|x| x.replace(Some(flag))));
+    f()
+}
+
+/// Makes Ctrl-C interrupt the running evaluation, instead of killing the process.
+pub fn install_sigint_handler() -> Result<()> {
+    ctrlc::set_handler(|| SIGINT.store(true, Ordering::Relaxed))?;
+    Ok(())
+}
+
+/// Forgets a Ctrl-C that was pressed while nothing was being evaluated.
+pub fn clear_sigint() {
+    SIGINT.store(false, Ordering::Relaxed);
+}
+
+/// Returns [`MalError::Interrupted`] if the evaluation on this thread should stop, and clears
+/// the flag, so that the next evaluation can run.
+pub fn check() -> Result<(), MalError> {
+    if SIGINT.swap(false, Ordering::Relaxed) {
+        return Err(MalError::Interrupted);
+    }
+    FLAG.with(|flag| match &*flag.borrow() {
+        Some(flag) if flag.swap(false, Ordering::Relaxed) => Err(MalError::Interrupted),
+        _ => Ok(()),
+    })
+}
@@ -0,0 +1,142 @@
+//! The input and output of mal programs, which can be redirected, for example to send the output
+//! to an nREPL client, or to let an application that embeds mal provide the input.
+//!
+//! The error output is for messages about the evaluation, like the calls logged by the tracer.
+
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+
+use color_eyre::Result;
+
+thread_local! {
+    /// Where the output goes, if not to stdout
+    static OUTPUT: RefCell<Option<Box<dyn Write>>> = const { RefCell::new(None) };
+    /// Where the error output goes, if not to stderr
+    static ERROR_OUTPUT: RefCell<Option<Box<dyn Write>>> = const { RefCell::new(None) };
+    /// Where the input comes from, if not from stdin
+    static INPUT: RefCell<Option<Box<dyn BufRead>>> = const { RefCell::new(None) };
+}
+
+/// Writes `s` to the output of mal programs, and flushes it.
+pub fn write_output(s: &str) -> Result<()> {
+    OUTPUT.with(|output| match &mut *output.borrow_mut() {
+        Some(output) => {
+            output.write_all(s.as_bytes())?;
+            output.flush()
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(s.as_bytes())?;
+            stdout.flush()
+        }
+    })?;
+    Ok(())
+}
+
+/// Writes `s` to the error output of mal programs, and flushes it.
+pub fn write_error(s: &str) -> Result<()> {
+    ERROR_OUTPUT.with(|output| match &mut *output.borrow_mut() {
+        Some(output) => {
+            output.write_all(s.as_bytes())?;
+            output.flush()
+        }
+        None => {
+            let mut stderr = std::io::stderr().lock();
+            stderr.write_all(s.as_bytes())?;
+            stderr.flush()
+        }
+    })?;
+    Ok(())
+}
+
+/// Reads a line from the input of mal programs, without the line ending. Returns `None` at end
+/// of file.
+pub fn read_input_line() -> Result<Option<String>> {
+    let mut line = String::new();
+    let len = INPUT.with(|input| match &mut *input.borrow_mut() {
+        Some(input) => input.read_line(&mut line),
+        None => std::io::stdin().lock().read_line(&mut line),
+    })?;
+    if len == 0 {
+        return Ok(None);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Some(line))
+}
+
+/// Redirects the output of mal programs on this thread to `output`, or back to stdout if it is
+/// `None`. Returns the previous output.
+pub fn set_output(output: Option<Box<dyn Write>>) -> Option<Box<dyn Write>> {
+    OUTPUT.with(|x| x.replace(output))
+}
+
+/// Redirects the error output of mal programs on this thread to `output`, or back to stderr if it
+/// is `None`. Returns the previous error output.
+pub fn set_error_output(output: Option<Box<dyn Write>>) -> Option<Box<dyn Write>> {
+    ERROR_OUTPUT.with(|x| x.replace(output))
+}
+
+/// Makes mal programs on this thread read from `input`, or from stdin again if it is `None`.
+/// Returns the previous input.
+pub fn set_input(input: Option<Box<dyn BufRead>>) -> Option<Box<dyn BufRead>> {
+    INPUT.with(|x| x.replace(input))
+}
+
+/// Puts back the input or output that was redirected, when dropped, also if the evaluation
+/// panicked.
+pub struct Redirect<T> {
+    set: fn(Option<T>) -> Option<T>,
+    /// What was there before the redirect, until it is put back
+    old: Option<Option<T>>,
+}
+
+impl<T> Redirect<T> {
+    fn new(set: fn(Option<T>) -> Option<T>, new: T) -> Self {
+        Self {
+            set,
+            old: Some(set(Some(new))),
+        }
+    }
+
+    /// Puts back what was there before the redirect, and returns what it was redirected to.
+    pub fn restore(mut self) -> Option<T> {
+        let old = self.old.take().flatten();
+        (self.set)(old)
+    }
+}
+
+impl<T> Drop for Redirect<T> {
+    fn drop(&mut self) {
+        if let Some(old) = self.old.take() {
+            (self.set)(old);
+        }
+    }
+}
+
+/// Redirects the output of mal programs on this thread to `output`, until the returned guard is
+/// dropped.
+pub fn redirect_output(output: Box<dyn Write>) -> Redirect<Box<dyn Write>> {
+    Redirect::new(set_output, output)
+}
+
+/// Redirects the error output of mal programs on this thread to `output`, until the returned
+/// guard is dropped.
+pub fn redirect_error_output(output: Box<dyn Write>) -> Redirect<Box<dyn Write>> {
+    Redirect::new(set_error_output, output)
+}
+
+/// Makes mal programs on this thread read from `input`, until the returned guard is dropped.
+pub fn redirect_input(input: Box<dyn BufRead>) -> Redirect<Box<dyn BufRead>> {
+    Redirect::new(set_input, input)
+}
+
+/// Calls `f` with the output of mal programs redirected to `output`.
+pub fn with_output<T>(output: Box<dyn Write>, f: impl FnOnce() -> T) -> T {
+    let _redirect = redirect_output(output);
+    f()
+}
@@ -1,3 +1,27 @@
 pub mod atom;
+pub mod bencode;
+pub mod compiler;
+pub mod cst;
+pub mod debug;
+pub mod destructure;
+pub mod edn;
 pub mod env;
+pub mod error;
+pub mod eval;
+pub mod format;
+pub mod highlight;
+pub mod interpreter;
+pub mod interrupt;
+pub mod io;
+pub mod limits;
+pub mod lsp;
+pub mod module;
+pub mod nrepl;
+pub mod profile;
 pub mod reader;
+pub mod repl;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod stack;
+pub mod trace;
+pub mod vm;
@@ -0,0 +1,191 @@
+//! Limits on the resources an evaluation may use, for running code that is not trusted.
+//!
+//! When a limit is exceeded, the evaluation fails with [`MalError::LimitExceeded`], which says
+//! which [`Limit`] it was.
+//!
+//! Forms nested deeper than [`MAX_NESTING`] are never read or evaluated, whatever the limits
+//! are, because reading and evaluating them uses the Rust stack.
+
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
+
+use crate::atom::Atom;
+use crate::error::{Limit, MalError};
+use crate::stack::depth;
+
+/// Maximum number of forms that are read or evaluated inside of each other, including the calls
+/// to functions that are not in tail position. Deeper nesting fails with [`Limit::Depth`].
+pub const MAX_NESTING: usize = 1000;
+
+/// Stack size for threads that read and evaluate mal code, with room for [`MAX_NESTING`] nested
+/// forms also in debug builds. Other threads may overflow their stack before that.
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// How often the deadline is checked, in eval steps, because getting the time is not free
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+/// The resources an evaluation may use. `None` means unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of eval steps
+    pub fuel: Option<u64>,
+    /// Maximum number of nested calls
+    pub max_depth: Option<usize>,
+    /// Maximum number of bytes of the values that are created: the values returned by builtins,
+    /// and vectors and maps that are built from their elements. This is an estimate, which does
+    /// not count memory that is freed or shared.
+    pub max_allocation: Option<usize>,
+    /// Maximum time the evaluation may take
+    pub timeout: Option<Duration>,
+    /// Rejects the special forms that do IO or read files: `debug`, `break`, `profile` and
+    /// `require`. Tracing, with `trace` or `trace-all`, is allowed. Use it with
+    /// [`sandbox_env`](crate::env::sandbox_env), which leaves out the builtins that do IO.
+    pub sandbox: bool,
+}
+
+/// The resources used by the running evaluation
+struct Usage {
+    limits: Limits,
+    steps: u64,
+    allocation: usize,
+    deadline: Option<Instant>,
+}
+
+thread_local! {
+    static USAGE: RefCell<Option<Usage>> = const { RefCell::new(None) };
+    /// Number of forms that are being read or evaluated inside of each other
+    static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Calls `f`, and fails its evaluations when they use more than `limits` allows. The usage is
+/// counted from zero.
+pub fn with_limits<T>(limits: Limits, f: impl FnOnce() -> T) -> T {
+    let usage = Usage {
+        deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+        limits,
+        steps: 0,
+        allocation: 0,
+    };
+    let _guard = UsageGuard(USAGE.with(|x| x.replace(Some(usage))));
+    f()
+}
+
+/// Puts back the usage of the evaluation around [`with_limits`] when dropped.
+struct UsageGuard(Option<Usage>);
+
+impl Drop for UsageGuard {
+    fn drop(&mut self) {
+        USAGE.with(|x| x.replace(self.0.take()));
+    }
+}
+
+/// Counts an eval step, and checks the fuel and the deadline.
+pub fn step() -> Result<(), MalError> {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        let usage = match &mut *usage {
+            Some(usage) => usage,
+            None => return Ok(()),
+        };
+        usage.steps += 1;
+        if usage.limits.fuel.is_some_and(|fuel| usage.steps > fuel) {
+            return Err(MalError::LimitExceeded(Limit::Fuel));
+        }
+        if usage.steps % DEADLINE_CHECK_INTERVAL == 0
+            && usage
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(MalError::LimitExceeded(Limit::Deadline));
+        }
+        Ok(())
+    })
+}
+
+/// Checks the depth of the call stack.
+pub fn check_depth() -> Result<(), MalError> {
+    USAGE.with(|usage| match &*usage.borrow() {
+        Some(usage) if usage.limits.max_depth.is_some_and(|max| depth() > max) => {
+            Err(MalError::LimitExceeded(Limit::Depth))
+        }
+        _ => Ok(()),
+    })
+}
+
+/// Fails with [`MalError::NotAllowed`] if the evaluation runs in a sandbox. `form` is the name of
+/// the special form, which does IO.
+pub fn check_io(form: &str) -> Result<(), MalError> {
+    USAGE.with(|usage| match &*usage.borrow() {
+        Some(usage) if usage.limits.sandbox => Err(MalError::NotAllowed(form.to_string())),
+        _ => Ok(()),
+    })
+}
+
+/// Ends a nested form that was started with [`nest`] when dropped.
+pub struct NestingGuard(());
+
+impl Drop for NestingGuard {
+    fn drop(&mut self) {
+        NESTING.with(|nesting| nesting.set(nesting.get() - 1));
+    }
+}
+
+/// Starts reading or evaluating a form inside of the ones that are being read or evaluated,
+/// until the returned guard is dropped. Fails if that nests more than [`MAX_NESTING`] forms.
+pub fn nest() -> Result<NestingGuard, MalError> {
+    NESTING.with(|nesting| {
+        if nesting.get() >= MAX_NESTING {
+            return Err(MalError::LimitExceeded(Limit::Depth));
+        }
+        nesting.set(nesting.get() + 1);
+        Ok(NestingGuard(()))
+    })
+}
+
+/// Counts the size of a value that a builtin created, and checks the allocation budget.
+pub fn allocate(value: &Atom) -> Result<(), MalError> {
+    count_allocation(|| approximate_size(value))
+}
+
+/// Counts the size of a vector or map with `len` elements, whose elements were counted when they
+/// were created, and checks the allocation budget.
+pub fn allocate_collection(len: usize) -> Result<(), MalError> {
+    count_allocation(|| (len + 1) * std::mem::size_of::<Atom>())
+}
+
+/// Adds `size` bytes to the allocation, if there is a budget, and checks it.
+fn count_allocation(size: impl FnOnce() -> usize) -> Result<(), MalError> {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        let usage = match &mut *usage {
+            Some(usage) => usage,
+            None => return Ok(()),
+        };
+        let max = match usage.limits.max_allocation {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        usage.allocation = usage.allocation.saturating_add(size());
+        if usage.allocation > max {
+            return Err(MalError::LimitExceeded(Limit::Allocation));
+        }
+        Ok(())
+    })
+}
+
+/// Estimates how many bytes `atom` takes.
+fn approximate_size(atom: &Atom) -> usize {
+    let children = match atom {
+        Atom::List(list) | Atom::Vector(list) => list.iter().map(approximate_size).sum(),
+        Atom::HashMap(map) => map
+            .iter()
+            .map(|(k, v)| approximate_size(k) + approximate_size(v))
+            .sum(),
+        Atom::Set(set) => set.iter().map(approximate_size).sum(),
+        Atom::Symbol(s) | Atom::Keyword(s) | Atom::String(s) => s.len(),
+        Atom::BigInteger(num) => num.bits() as usize / 8,
+        Atom::Tagged(tag, value) => tag.len() + approximate_size(value),
+        _ => 0,
+    };
+    std::mem::size_of::<Atom>() + children
+}
@@ -0,0 +1,405 @@
+//! A [Language Server Protocol](https://microsoft.github.io/language-server-protocol/) server for
+//! mal, used by the `mal-lsp` binary.
+//!
+//! It provides diagnostics for source that can not be read, document symbols for `def!`, go to
+//! the definitions in a document, hover for the builtins of [`default_env`], and completion.
+//! Documents are always synced in full.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use color_eyre::{eyre::eyre, Result};
+use serde_json::{json, Value};
+
+use crate::cst::{is_delimiter, parse, Node, NodeKind};
+use crate::env::{builtin, default_env, Env};
+use crate::error::MalError;
+use crate::eval::SPECIAL_FORMS;
+
+/// JSON-RPC error code for messages that are not valid JSON
+const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code for unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+/// LSP `CompletionItemKind`s and `SymbolKind`s
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+
+/// A definition with `def!` in a document
+struct Definition {
+    name: String,
+    /// Whether the value is a function
+    is_function: bool,
+    /// Byte range of the whole form
+    span: std::ops::Range<usize>,
+    /// Byte range of the name
+    name_span: std::ops::Range<usize>,
+}
+
+/// The state of the server
+pub struct Server {
+    /// Open documents, by URI
+    documents: BTreeMap<String, String>,
+    env: Env,
+    /// Whether `exit` was received
+    exited: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            documents: BTreeMap::new(),
+            env: default_env(),
+            exited: false,
+        }
+    }
+}
+
+/// Runs the server until the client sends `exit`, or closes `input`. A message that can not be
+/// parsed gets an error reply, and the server goes on with the next one.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut server = Server::default();
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) if e.downcast_ref::<std::io::Error>().is_some() => return Err(e),
+            Err(e) => {
+                let reply = json!({
+                    "jsonrpc": "2.0",
+                    "id": Value::Null,
+                    "error": {"code": PARSE_ERROR, "message": format!("{:#}", e)},
+                });
+                write_message(&mut output, &reply)?;
+                continue;
+            }
+        };
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a message with its `Content-Length` header. Returns `None` at end of file.
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let length = length.ok_or_else(|| eyre!("message without a Content-Length header"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+/// Writes a message with its `Content-Length` header.
+pub fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()?;
+    Ok(())
+}
+
+impl Server {
+    /// Handles a message from the client, and returns the messages to send back.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        // responses to requests from the server have no method, and need no reply
+        if message.get("method").is_none() {
+            return Vec::new();
+        }
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => {
+                return self.notification(method, params).into_iter().collect();
+            }
+        };
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "documentSymbolProvider": true,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {"triggerCharacters": ["(", ":"]},
+                },
+                "serverInfo": {"name": "mal-lsp"},
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            _ => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unknown method {}", method),
+                    },
+                })]
+            }
+        };
+        vec![json!({"jsonrpc": "2.0", "id": id, "result": result})]
+    }
+
+    /// Handles a notification, and returns the diagnostics to publish if a document changed.
+    fn notification(&mut self, method: &str, params: &Value) -> Option<Value> {
+        if method == "exit" {
+            self.exited = true;
+            return None;
+        }
+        let uri = params["textDocument"]["uri"].as_str()?.to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str()?.to_string(),
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()?
+                .last()?
+                .get("text")?
+                .as_str()?
+                .to_string(),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return Some(publish_diagnostics(&uri, Vec::new()));
+            }
+            _ => return None,
+        };
+        let diagnostics = diagnostics(&text);
+        self.documents.insert(uri.clone(), text);
+        Some(publish_diagnostics(&uri, diagnostics))
+    }
+
+    /// Returns the text of the document in `params`, and the byte offset of the position in
+    /// `params`, if any.
+    fn document<'a>(&'a self, params: &Value) -> Option<(&'a str, usize)> {
+        let text = self
+            .documents
+            .get(params["textDocument"]["uri"].as_str()?)?;
+        let offset = offset(text, &params["position"]);
+        Some((text, offset))
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let text = match self.document(params) {
+            Some((text, _)) => text,
+            None => return Value::Null,
+        };
+        let symbols = definitions(text)
+            .into_iter()
+            .map(|def| {
+                json!({
+                    "name": def.name,
+                    "kind": if def.is_function { SYMBOL_FUNCTION } else { SYMBOL_VARIABLE },
+                    "range": range(text, &def.span),
+                    "selectionRange": range(text, &def.name_span),
+                })
+            })
+            .collect();
+        Value::Array(symbols)
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let (text, offset) = match self.document(params) {
+            Some(document) => document,
+            None => return Value::Null,
+        };
+        // builtins have no definition in mal, and are described by hover instead
+        let word = word_at(text, offset);
+        match definitions(text).into_iter().find(|def| def.name == word) {
+            Some(def) => json!({
+                "uri": params["textDocument"]["uri"],
+                "range": range(text, &def.name_span),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let (text, offset) = match self.document(params) {
+            Some(document) => document,
+            None => return Value::Null,
+        };
+        let word = word_at(text, offset);
+        match builtin(word) {
+            Some(builtin) => json!({
+                "contents": {
+                    "kind": "markdown",
+                    "value": format!("```mal\n{}\n```\n{}", builtin.usage, builtin.doc),
+                },
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let (text, offset) = match self.document(params) {
+            Some(document) => document,
+            None => return Value::Null,
+        };
+        let start = text[..offset]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| is_delimiter(*c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let prefix = &text[start..offset];
+        let mut items = BTreeMap::new();
+        for name in self.env.keys() {
+            items.insert(name.clone(), COMPLETION_FUNCTION);
+        }
+        for name in SPECIAL_FORMS {
+            items.insert(name.to_string(), COMPLETION_KEYWORD);
+        }
+        for def in definitions(text) {
+            let kind = if def.is_function {
+                COMPLETION_FUNCTION
+            } else {
+                COMPLETION_VARIABLE
+            };
+            items.insert(def.name, kind);
+        }
+        let items = items
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, kind)| {
+                let detail = builtin(&name).map(|x| x.usage);
+                json!({"label": name, "kind": kind, "detail": detail})
+            })
+            .collect();
+        Value::Array(items)
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+/// Returns a diagnostic for the first error in reading `text`, if any.
+fn diagnostics(text: &str) -> Vec<Value> {
+    let error = match parse(text) {
+        Ok(_) => match crate::reader::read_all(text.to_string()) {
+            Ok(_) => return Vec::new(),
+            Err(e) => e,
+        },
+        Err(e) => e,
+    };
+    let span = match error.downcast_ref::<MalError>() {
+        Some(MalError::Reader { span, .. }) => span.clone(),
+        _ => 0..0,
+    };
+    vec![json!({
+        "range": range(text, &span),
+        "severity": 1,
+        "source": "mal",
+        "message": error.root_cause().to_string(),
+    })]
+}
+
+/// Finds all `def!` forms in `text`, including nested ones. Returns nothing if
+/// `text` can not be parsed.
+fn definitions(text: &str) -> Vec<Definition> {
+    fn walk(nodes: &[Node], res: &mut Vec<Definition>) {
+        for node in nodes {
+            match &node.kind {
+                NodeKind::Seq { children, .. } => {
+                    let mut forms = node.forms();
+                    let head = forms.next().and_then(|x| x.token());
+                    if let (Some("def!"), Some(name)) = (head, forms.next()) {
+                        if let Some(token) = name.token() {
+                            let value = forms.next();
+                            let value_head = value.and_then(|x| x.forms().next());
+                            res.push(Definition {
+                                name: token.to_string(),
+                                is_function: value_head.and_then(|x| x.token()) == Some("fn*"),
+                                span: node.span.clone(),
+                                name_span: name.span.clone(),
+                            });
+                        }
+                    }
+                    walk(children, res);
+                }
+                NodeKind::Prefix { form, .. } => walk(std::slice::from_ref(form), res),
+                _ => {}
+            }
+        }
+    }
+    let mut res = Vec::new();
+    if let Ok(nodes) = parse(text) {
+        walk(&nodes, &mut res);
+    }
+    res
+}
+
+/// Returns the symbol or keyword around the byte `offset`.
+fn word_at(text: &str, offset: usize) -> &str {
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| is_delimiter(*c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = text[offset..]
+        .char_indices()
+        .find(|(_, c)| is_delimiter(*c))
+        .map_or(text.len(), |(i, _)| offset + i);
+    &text[start..end]
+}
+
+/// Converts a byte offset to an LSP position, which counts characters in UTF-16.
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({"line": before.matches('\n').count(), "character": character})
+}
+
+fn range(text: &str, span: &std::ops::Range<usize>) -> Value {
+    json!({"start": position(text, span.start), "end": position(text, span.end)})
+}
+
+/// Converts an LSP position to a byte offset. Positions past the end of a line or of the text
+/// are clamped.
+fn offset(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let line_start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        },
+    };
+    let mut utf16 = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if utf16 >= character || c == '\n' {
+            return line_start + i;
+        }
+        utf16 += c.len_utf16();
+    }
+    text.len()
+}
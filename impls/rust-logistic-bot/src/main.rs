@@ -1,3 +1,192 @@
-fn main() {
-    println!("Hello, world!");
+use std::io::Read;
+use std::process::ExitCode;
+
+use mal::{
+    atom::Atom,
+    env::{default_env, Env},
+    eval::{eval_named_source, eval_source, set_engine, Engine},
+    limits::STACK_SIZE,
+    profile::CountingAllocator,
+    stack::{format_backtrace, take_backtrace},
+};
+
+const USAGE: &str = "\
+usage: mal [options] [file [args...]]
+
+With no file, starts the REPL. Otherwise runs the file, with the
+remaining arguments bound to *ARGV*. If file is -, the program is read
+from standard input. A #! line at the start of the program is skipped.
+
+options:
+  -e, --eval expr         evaluate expr and print the result
+  -h, --help              show this help
+  --nrepl-port port       start an nREPL server on 127.0.0.1:port instead
+                          of the REPL, for editors to connect to. Port 0
+                          picks a free port
+  --profile[=folded-file] profile the program and print the results
+                          to standard error, and optionally write the
+                          call stacks to folded-file for flamegraph tools
+  --vm                    compile forms to bytecode and run them on a VM,
+                          instead of walking them
+
+environment:
+  MAL_PATH                directories to find modules in for require,
+                          separated like PATH. Defaults to the current
+                          directory
+
+exit codes:
+  0   success
+  1   the program raised an error
+  64  invalid command line
+  66  the file could not be read
+  73  the profile could not be written";
+
+/// Exit code when the mal program raised an error
+const EXIT_ERROR: u8 = 1;
+/// Exit code for an invalid command line, from sysexits.h
+const EXIT_USAGE: u8 = 64;
+/// Exit code when the input could not be read, from sysexits.h
+const EXIT_NO_INPUT: u8 = 66;
+/// Exit code when an output file could not be written, from sysexits.h
+const EXIT_CANT_CREATE: u8 = 73;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() -> ExitCode {
+    // the main thread does not have enough stack for deeply nested forms
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_main)
+        .expect("Could not start the main thread")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn run_main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    // options that change how the program is run come before the others, in any order
+    let mut profile = None;
+    let mut start = 0;
+    for arg in &args {
+        if arg == "--vm" {
+            set_engine(Engine::Vm);
+        } else if let Some(folded_path) = profile_option(arg) {
+            profile = Some(folded_path);
+        } else {
+            break;
+        }
+        start += 1;
+    }
+    let res = match profile {
+        Some(folded_path) => run_profiled(&args[start..], folded_path),
+        None => run(&args[start..]),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+/// Returns `Some` if `option` is `--profile`, with the path to write the folded call stacks to,
+/// if any.
+fn profile_option(option: &str) -> Option<Option<&str>> {
+    if option == "--profile" {
+        Some(None)
+    } else {
+        option.strip_prefix("--profile=").map(Some)
+    }
+}
+
+/// Like [`run`], but with the profiler running.
+fn run_profiled(args: &[String], folded_path: Option<&str>) -> std::result::Result<(), u8> {
+    mal::profile::start();
+    let res = run(args);
+    let profile = mal::profile::stop().unwrap_or_default();
+    eprintln!("{}", profile.table());
+    if let Some(path) = folded_path {
+        if let Err(e) = std::fs::write(path, profile.folded()) {
+            eprintln!("mal: could not write {}: {}", path, e);
+            return res.and(Err(EXIT_CANT_CREATE));
+        }
+    }
+    res
+}
+
+/// Runs the command line, and returns the exit code on failure.
+fn run(args: &[String]) -> std::result::Result<(), u8> {
+    let mut env = default_env();
+    match args.first().map(|x| x.as_str()) {
+        None => {
+            set_argv(&mut env, &[]);
+            color_eyre::install().map_err(report)?;
+            mal::repl::run(env).map_err(report)
+        }
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some("-e" | "--eval") => {
+            let expr = args
+                .get(1)
+                .ok_or_else(|| usage("-e requires an expression"))?;
+            set_argv(&mut env, &args[2..]);
+            let res = eval_source(expr.clone(), &mut env).map_err(report)?;
+            println!("{}", mal::repl::print(res));
+            Ok(())
+        }
+        Some("--nrepl-port") => {
+            let port = args
+                .get(1)
+                .and_then(|x| x.parse::<u16>().ok())
+                .ok_or_else(|| usage("--nrepl-port requires a port number"))?;
+            set_argv(&mut env, &args[2..]);
+            mal::nrepl::serve(port, env).map_err(report)
+        }
+        Some("-") => {
+            let mut source = String::new();
+            std::io::stdin()
+                .read_to_string(&mut source)
+                .map_err(|e| no_input("standard input", e))?;
+            set_argv(&mut env, &args[1..]);
+            eval_named_source("-", source, &mut env)
+                .map(|_| ())
+                .map_err(report)
+        }
+        Some(option) if option.starts_with('-') => {
+            Err(usage(&format!("unknown option {}", option)))
+        }
+        Some(path) => {
+            let source = std::fs::read_to_string(path).map_err(|e| no_input(path, e))?;
+            set_argv(&mut env, &args[1..]);
+            eval_named_source(path, source, &mut env)
+                .map(|_| ())
+                .map_err(report)
+        }
+    }
+}
+
+fn set_argv(env: &mut Env, args: &[String]) {
+    env.insert(
+        String::from("*ARGV*"),
+        Atom::List(args.iter().map(|x| Atom::String(x.clone())).collect()),
+    );
+}
+
+fn report(e: color_eyre::Report) -> u8 {
+    eprintln!("error: {:#}", e);
+    if let Some(frames) = take_backtrace() {
+        eprintln!("{}", format_backtrace(&frames));
+    }
+    EXIT_ERROR
+}
+
+fn usage(message: &str) -> u8 {
+    eprintln!("mal: {}\ntry 'mal --help' for more information", message);
+    EXIT_USAGE
+}
+
+fn no_input(path: &str, e: std::io::Error) -> u8 {
+    eprintln!("mal: could not read {}: {}", path, e);
+    EXIT_NO_INPUT
 }
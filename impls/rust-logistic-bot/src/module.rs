@@ -0,0 +1,248 @@
+//! Modules: files of mal code that are loaded into their own environment, and whose definitions
+//! are then bound under a qualified name.
+//!
+//! `(require 'my.module :as m)` loads `my/module.mal` from the first directory of the load path
+//! that has it, and binds each of its definitions as `my.module/name` and `m/name`. The load
+//! path comes from `MAL_PATH`, a list of directories like `PATH`, and is the current directory if
+//! that is not set. A module is loaded once per [`Interpreter`](crate::interpreter::Interpreter),
+//! or per thread for code that is evaluated without one, and requiring it again from the same
+//! file uses the cached definitions, unless `:reload` is given. A module that requires itself,
+//! directly or through other modules, is an error.
+//!
+//! A module can start with `(ns my.module)`, which binds `*ns*` to its name. It must match the
+//! name that the module is required with.
+//!
+//! Modules start with the builtins of the environment that requires them. Module names are
+//! made of segments separated by `.`, which can not be empty or contain path separators or `:`, so
+//! modules are always loaded from inside of the load path. `require` reads files, so it is not
+//! allowed in a [sandbox](crate::limits::Limits::sandbox).
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+
+use crate::atom::Atom;
+use crate::env::Env;
+use crate::error::MalError;
+use crate::eval::eval_named_source;
+
+/// Where modules are loaded from, and the modules that were loaded
+#[derive(Clone, Debug, Default)]
+pub struct Modules {
+    /// The load path, if it was set instead of coming from `MAL_PATH`
+    load_path: Option<Vec<PathBuf>>,
+    /// The environments of the modules that were loaded, by the file they were loaded from
+    loaded: BTreeMap<PathBuf, Env>,
+}
+
+impl Modules {
+    /// Makes modules load from `path`, or from `MAL_PATH` again if it is `None`.
+    pub fn set_load_path(&mut self, path: Option<Vec<PathBuf>>) {
+        self.load_path = path;
+    }
+
+    fn load_path(&self) -> Vec<PathBuf> {
+        self.load_path
+            .clone()
+            .unwrap_or_else(|| match std::env::var_os("MAL_PATH") {
+                Some(path) => std::env::split_paths(&path).collect(),
+                None => vec![PathBuf::from(".")],
+            })
+    }
+
+    /// Forgets the modules that were loaded, so that they are loaded again.
+    pub fn clear_cache(&mut self) {
+        self.loaded.clear();
+    }
+}
+
+thread_local! {
+    /// The modules of the evaluation on this thread
+    static MODULES: RefCell<Modules> = RefCell::new(Modules::default());
+    /// The modules that are being loaded, outermost first
+    static LOADING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    /// The file of the module whose code is running, which is `None` outside of modules
+    static CURRENT: RefCell<Option<Arc<Path>>> = const { RefCell::new(None) };
+}
+
+/// Puts back the module that was running before [`enter`] when dropped.
+pub(crate) struct CurrentGuard(Option<Arc<Path>>);
+
+impl Drop for CurrentGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|x| *x.borrow_mut() = self.0.take());
+    }
+}
+
+/// Makes the code of the module loaded from `module`, or of no module, run until the returned
+/// guard is dropped.
+pub(crate) fn enter(module: Option<Arc<Path>>) -> CurrentGuard {
+    CurrentGuard(CURRENT.with(|x| x.replace(module)))
+}
+
+/// Returns the file of the module whose code is running.
+pub(crate) fn current() -> Option<Arc<Path>> {
+    CURRENT.with(|x| x.borrow().clone())
+}
+
+/// Looks up `sym` in the environment of the module whose code is running. Returns `None` if no
+/// module is running, or if it is still being loaded and its environment is the one that is
+/// being evaluated.
+pub(crate) fn global(sym: &str) -> Option<Option<Atom>> {
+    let path = CURRENT.with(|x| x.borrow().clone())?;
+    MODULES.with(|modules| Some(modules.borrow().loaded.get(&*path)?.get(sym).cloned()))
+}
+
+/// Puts back the modules that were used before [`with_modules`] when dropped.
+struct ModulesGuard<'a>(&'a mut Modules);
+
+impl Drop for ModulesGuard<'_> {
+    fn drop(&mut self) {
+        MODULES.with(|x| std::mem::swap(&mut *x.borrow_mut(), self.0));
+    }
+}
+
+/// Calls `f`, and loads the modules it requires with `modules`, which keeps the loaded modules
+/// afterwards.
+pub fn with_modules<T>(modules: &mut Modules, f: impl FnOnce() -> T) -> T {
+    MODULES.with(|x| std::mem::swap(&mut *x.borrow_mut(), modules));
+    let _guard = ModulesGuard(modules);
+    f()
+}
+
+/// Makes modules on this thread load from `path`, or from `MAL_PATH` again if it is `None`.
+pub fn set_load_path(path: Option<Vec<PathBuf>>) {
+    MODULES.with(|modules| modules.borrow_mut().set_load_path(path));
+}
+
+/// Forgets the modules that were loaded on this thread, so that they are loaded again.
+pub fn clear_cache() {
+    MODULES.with(|modules| modules.borrow_mut().clear_cache());
+}
+
+/// Returns the name in `atom`, which is a symbol that can be quoted.
+fn symbol_name(atom: &Atom) -> Result<&str> {
+    match atom {
+        Atom::Symbol(name) => Ok(name),
+        Atom::List(list) if list.len() == 2 && list[0] == Atom::Symbol(String::from("quote")) => {
+            symbol_name(&list[1])
+        }
+        a => Err(MalError::type_error("symbol", a).into()),
+    }
+}
+
+/// Binds `*ns*` to the name of the module. This implements `(ns)`.
+pub fn ns(args: &[Atom], env: &mut Env) -> Result<Atom> {
+    MalError::check_arity(args, 1..=1)?;
+    let name = Atom::Symbol(symbol_name(&args[0])?.to_string());
+    env.insert(String::from("*ns*"), name);
+    Ok(Atom::Nil)
+}
+
+/// Loads a module, and binds its definitions in `env`. This implements `(require)`.
+pub fn require(args: &[Atom], env: &mut Env) -> Result<Atom> {
+    MalError::check_arity(args, 1..=usize::MAX)?;
+    let module = symbol_name(&args[0])?;
+    let mut alias = None;
+    let mut reload = false;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match option {
+            Atom::Keyword(k) if k == "as" => {
+                alias = Some(symbol_name(options.next().unwrap_or(&Atom::Nil))?);
+            }
+            Atom::Keyword(k) if k == "reload" => reload = true,
+            a => return Err(MalError::type_error(":as or :reload", a).into()),
+        }
+    }
+    let module_env = load(module, env, reload)?;
+    // what the module required is bound under qualified names, which are not its own, and it
+    // starts with the builtins of `env`
+    let builtin = |k: &str, v: &Atom| {
+        matches!(v, Atom::Builtin(_) | Atom::NativeFn(_)) && env.get(k) == Some(v)
+    };
+    let definitions: Vec<_> = module_env
+        .into_iter()
+        .filter(|(k, v)| !k.contains('/') && !builtin(k, v))
+        .collect();
+    for (k, v) in definitions {
+        if let Some(alias) = alias {
+            env.insert(format!("{}/{}", alias, k), v.clone());
+        }
+        env.insert(format!("{}/{}", module, k), v);
+    }
+    Ok(Atom::Nil)
+}
+
+/// Returns the environment of the module `name`, and loads it if it is not cached.
+fn load(name: &str, env: &Env, reload: bool) -> Result<Env> {
+    let cycle = LOADING.with(|loading| {
+        let loading = loading.borrow();
+        let start = loading.iter().position(|x| x == name)?;
+        let mut cycle = loading[start..].to_vec();
+        cycle.push(name.to_string());
+        Some(cycle)
+    });
+    if let Some(cycle) = cycle {
+        return Err(MalError::CyclicRequire(cycle).into());
+    }
+
+    let segments: Vec<&str> = name.split('.').collect();
+    // each segment is a file or directory name, so that the path stays inside of the load path
+    let valid = |x: &&str| !x.is_empty() && !x.contains(['/', '\\', ':']);
+    if !segments.iter().all(valid) {
+        return Err(eyre!("invalid module name {}", name));
+    }
+    let relative = format!("{}.mal", segments.join("/"));
+    let path = MODULES
+        .with(|modules| modules.borrow().load_path())
+        .into_iter()
+        .map(|dir| dir.join(&relative))
+        .find(|path| path.is_file())
+        .ok_or_else(|| MalError::ModuleNotFound(name.to_string()))?;
+    if reload {
+        // while it is loaded again, its functions see the definitions that are being made
+        MODULES.with(|modules| modules.borrow_mut().loaded.remove(&path));
+    } else if let Some(module_env) = cached(&path) {
+        return Ok(module_env);
+    }
+    let source = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("could not read {}", path.display()))?;
+
+    let mut module_env: Env = env
+        .iter()
+        .filter(|(_, v)| matches!(v, Atom::Builtin(_) | Atom::NativeFn(_)))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    LOADING.with(|loading| loading.borrow_mut().push(name.to_string()));
+    let module = enter(Some(Arc::from(path.as_path())));
+    let res = eval_named_source(&path.display().to_string(), source, &mut module_env);
+    drop(module);
+    LOADING.with(|loading| loading.borrow_mut().pop());
+    res?;
+
+    match module_env.remove("*ns*") {
+        Some(Atom::Symbol(ns)) if ns != name => {
+            return Err(eyre!(
+                "{} declares namespace {} instead of {}",
+                path.display(),
+                ns,
+                name
+            ));
+        }
+        _ => {}
+    }
+    MODULES.with(|modules| modules.borrow_mut().loaded.insert(path, module_env.clone()));
+    Ok(module_env)
+}
+
+/// Returns the environment of the module that was loaded from `path`, if it was.
+fn cached(path: &Path) -> Option<Env> {
+    MODULES.with(|modules| modules.borrow().loaded.get(path).cloned())
+}
@@ -0,0 +1,416 @@
+//! An [nREPL](https://nrepl.org) server, so that editor integrations like CIDER and Calva can
+//! connect to a running mal process.
+//!
+//! Messages are bencoded dicts over TCP. The supported ops are `clone`, `close`, `completions`,
+//! `describe`, `eval`, `interrupt`, `load-file`, `ls-sessions` and `stdin`. Each session has its
+//! own environment, and requests without a session run in a new environment that is thrown away
+//! afterwards.
+//!
+//! Each session evaluates on its own thread, one request after the other, so that evaluations can
+//! be interrupted, and state like traced functions lasts from one request to the next. The output
+//! and error output are sent to the client in `out` and `err` messages. When the evaluation reads
+//! input, for example in the debugger, the client is asked for it with a `need-input` status, and
+//! sends it with the `stdin` op.
+
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use color_eyre::Result;
+
+use crate::atom::Atom;
+use crate::bencode::Bencode;
+use crate::env::Env;
+use crate::error::MalError;
+use crate::eval::SPECIAL_FORMS;
+use crate::eval::{engine, eval_toplevel, set_engine, Engine};
+use crate::interrupt::{self, with_interrupt_flag};
+use crate::io::{redirect_error_output, redirect_input, redirect_output};
+use crate::limits::STACK_SIZE;
+use crate::reader::read_all;
+use crate::stack::{format_backtrace, take_backtrace};
+
+/// Where editors look for the port of the server
+const PORT_FILE: &str = ".nrepl-port";
+/// How often an evaluation that waits for input checks whether it was interrupted
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const OPS: &[&str] = &[
+    "clone",
+    "close",
+    "completions",
+    "describe",
+    "eval",
+    "interrupt",
+    "load-file",
+    "ls-sessions",
+    "stdin",
+];
+
+/// Binds to `port` on 127.0.0.1, and serves nREPL clients forever. Port 0 picks a free port.
+///
+/// Like other nREPL servers, this announces the port on stdout and writes it to `.nrepl-port`,
+/// where editors look for it. The file is removed when the server stops, also with Ctrl-C.
+pub fn serve(port: u16, env: Env) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let port = listener.local_addr()?.port().to_string();
+    println!(
+        "nREPL server started on port {} on host 127.0.0.1 - nrepl://127.0.0.1:{}",
+        port, port
+    );
+    let _ = std::fs::write(PORT_FILE, &port);
+    let _port_file = PortFile(port.clone());
+    ctrlc::set_handler(move || {
+        remove_port_file(&port);
+        std::process::exit(130);
+    })?;
+    serve_listener(listener, env)
+}
+
+/// Removes `.nrepl-port` when dropped
+struct PortFile(String);
+
+impl Drop for PortFile {
+    fn drop(&mut self) {
+        remove_port_file(&self.0);
+    }
+}
+
+/// Removes `.nrepl-port` if it still has `port`, and not the port of a server started later.
+fn remove_port_file(port: &str) {
+    if std::fs::read_to_string(PORT_FILE).is_ok_and(|x| x == port) {
+        let _ = std::fs::remove_file(PORT_FILE);
+    }
+}
+
+/// Serves nREPL clients that connect to `listener` forever. New sessions start with a copy of
+/// `env`, and evaluate with the [`Engine`] of this thread.
+pub fn serve_listener(listener: TcpListener, env: Env) -> Result<()> {
+    let server = Arc::new(Server {
+        env,
+        engine: engine(),
+        sessions: Mutex::new(BTreeMap::new()),
+    });
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = server.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = server.connection(stream) {
+                eprintln!("nrepl: connection failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+struct Server {
+    env: Env,
+    /// The engine of the thread that started the server, which evaluations use too
+    engine: Engine,
+    sessions: Mutex<BTreeMap<String, Session>>,
+}
+
+#[derive(Clone)]
+struct Session {
+    id: String,
+    state: Arc<State>,
+    /// Sends evaluations to the thread of the session. The thread stops when the session is gone.
+    evals: Sender<Eval>,
+    /// Sends what the client sent with the `stdin` op to the running evaluation
+    stdin: Sender<String>,
+}
+
+/// What the handlers of requests and the thread of a session share
+struct State {
+    env: Mutex<Env>,
+    /// Set to interrupt the running evaluation
+    interrupt: Arc<AtomicBool>,
+    /// Id of the message of the running evaluation
+    running: Mutex<Option<Bencode>>,
+}
+
+/// An `eval` or `load-file` request
+struct Eval {
+    code: String,
+    reply: Reply,
+}
+
+impl Session {
+    /// Creates a session, and starts its thread, which evaluates with `engine`.
+    fn new(env: Env, engine: Engine) -> std::io::Result<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |x| x.as_nanos());
+        let id = format!("{:x}-{}", time, NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let state = Arc::new(State {
+            env: Mutex::new(env),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            running: Mutex::new(None),
+        });
+        let (evals, eval_receiver) = channel::<Eval>();
+        let (stdin, stdin_receiver) = channel();
+        let thread_state = state.clone();
+        std::thread::Builder::new()
+            .name(format!("nrepl session {}", id))
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                set_engine(engine);
+                let stdin = Rc::new(stdin_receiver);
+                for eval in eval_receiver {
+                    run_eval(&thread_state, &stdin, eval);
+                }
+            })?;
+        Ok(Self {
+            id,
+            state,
+            evals,
+            stdin,
+        })
+    }
+}
+
+/// Sends replies to one request
+#[derive(Clone)]
+struct Reply {
+    stream: Arc<Mutex<TcpStream>>,
+    id: Option<Bencode>,
+    session: Option<String>,
+}
+
+impl Reply {
+    fn send<'a>(&self, entries: impl IntoIterator<Item = (&'a str, Bencode)>) {
+        let mut message = Bencode::dict(entries);
+        if let Bencode::Dict(dict) = &mut message {
+            if let Some(id) = &self.id {
+                dict.insert(b"id".to_vec(), id.clone());
+            }
+            if let Some(session) = &self.session {
+                dict.insert(b"session".to_vec(), session.as_str().into());
+            }
+        }
+        let mut bytes = Vec::new();
+        let _ = message.write(&mut bytes);
+        // the client may be gone, which is not a reason to stop the evaluation
+        let _ = self.stream.lock().unwrap().write_all(&bytes);
+    }
+
+    fn done(&self, statuses: &[&str]) {
+        let mut statuses = statuses.to_vec();
+        statuses.push("done");
+        self.send([("status", statuses.into())]);
+    }
+}
+
+/// Sends everything written to it as messages with the key `0`, which is `out` or `err`
+struct MessageWriter(&'static str, Reply);
+
+impl Write for MessageWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.1
+            .send([(self.0, String::from_utf8_lossy(buf).into_owned().into())]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads what the client sends with the `stdin` op, and asks for it with a `need-input` status
+/// when there is nothing left to read
+struct InputReader {
+    reply: Reply,
+    stdin: Rc<Receiver<String>>,
+    interrupt: Arc<AtomicBool>,
+    /// What was received but not read yet
+    pending: Vec<u8>,
+}
+
+impl Read for InputReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            self.reply.send([("status", vec!["need-input"].into())]);
+            loop {
+                match self.stdin.recv_timeout(INPUT_POLL_INTERVAL) {
+                    // an empty string is the end of the input
+                    Ok(input) => {
+                        self.pending = input.into_bytes();
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout) if !self.interrupt.load(Ordering::Relaxed) => {}
+                    // an interrupted evaluation reads the end of the input, and then stops at the
+                    // next form it evaluates
+                    Err(_) => return Ok(0),
+                }
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Server {
+    fn connection(&self, stream: TcpStream) -> Result<()> {
+        let writer = Arc::new(Mutex::new(stream.try_clone()?));
+        let mut reader = BufReader::new(stream);
+        while let Some(message) = Bencode::read(&mut reader)? {
+            let reply = Reply {
+                stream: writer.clone(),
+                id: message.get("id").cloned(),
+                session: message.get_str("session").map(String::from),
+            };
+            self.handle(&message, reply);
+        }
+        Ok(())
+    }
+
+    fn handle(&self, message: &Bencode, reply: Reply) {
+        let session = match &reply.session {
+            Some(id) => match self.sessions.lock().unwrap().get(id) {
+                Some(session) => session.clone(),
+                None => return reply.done(&["error", "unknown-session"]),
+            },
+            None => match Session::new(self.env.clone(), self.engine) {
+                Ok(session) => session,
+                Err(e) => return session_failed(&reply, e),
+            },
+        };
+        match message.get_str("op").unwrap_or("") {
+            "clone" => {
+                let env = session.state.env.lock().unwrap().clone();
+                let new = match Session::new(env, self.engine) {
+                    Ok(new) => new,
+                    Err(e) => return session_failed(&reply, e),
+                };
+                let id = new.id.clone();
+                self.sessions.lock().unwrap().insert(id.clone(), new);
+                reply.send([("new-session", id.into())]);
+                reply.done(&[]);
+            }
+            "close" => {
+                self.sessions.lock().unwrap().remove(&session.id);
+                reply.done(&["session-closed"]);
+            }
+            "ls-sessions" => {
+                let ids: Vec<String> = self.sessions.lock().unwrap().keys().cloned().collect();
+                reply.send([("sessions", ids.into())]);
+                reply.done(&[]);
+            }
+            "describe" => {
+                let ops = Bencode::dict(OPS.iter().map(|op| (*op, Bencode::dict([]))));
+                let version = Bencode::dict([("version-string", env!("CARGO_PKG_VERSION").into())]);
+                reply.send([
+                    ("ops", ops),
+                    ("versions", Bencode::dict([("mal", version)])),
+                ]);
+                reply.done(&[]);
+            }
+            "completions" => {
+                let prefix = message.get_str("prefix").unwrap_or("");
+                let env = session.state.env.lock().unwrap();
+                let candidates = env
+                    .keys()
+                    .map(|x| (&**x, "function"))
+                    .chain(SPECIAL_FORMS.iter().map(|x| (*x, "special-form")))
+                    .filter(|(x, _)| x.starts_with(prefix))
+                    .map(|(x, kind)| {
+                        Bencode::dict([("candidate", x.into()), ("type", kind.into())])
+                    })
+                    .collect();
+                reply.send([("completions", Bencode::List(candidates))]);
+                reply.done(&[]);
+            }
+            "eval" | "load-file" => {
+                let code = message
+                    .get_str("code")
+                    .or_else(|| message.get_str("file"))
+                    .unwrap_or("")
+                    .to_string();
+                let _ = session.evals.send(Eval { code, reply });
+            }
+            "stdin" => {
+                let input = message.get_str("stdin").unwrap_or("").to_string();
+                let _ = session.stdin.send(input);
+                reply.done(&[]);
+            }
+            "interrupt" => {
+                let running = session.state.running.lock().unwrap().clone();
+                let interrupt_id = message.get("interrupt-id");
+                match running {
+                    Some(id) if interrupt_id.is_none() || interrupt_id == Some(&id) => {
+                        session.state.interrupt.store(true, Ordering::Relaxed);
+                        reply.done(&[]);
+                    }
+                    _ => reply.done(&["session-idle"]),
+                }
+            }
+            _ => reply.done(&["error", "unknown-op"]),
+        }
+    }
+}
+
+/// Replies that a session could not be started.
+fn session_failed(reply: &Reply, e: std::io::Error) {
+    reply.send([(
+        "err",
+        format!("could not start the session: {}\n", e).into(),
+    )]);
+    reply.done(&["error"]);
+}
+
+/// Evaluates all forms in the code in the session, and sends the value of each one.
+fn run_eval(state: &State, stdin: &Rc<Receiver<String>>, eval: Eval) {
+    let Eval { code, reply } = eval;
+    let mut env = state.env.lock().unwrap();
+    // cleared first, so that an interrupt for this evaluation is not lost
+    state.interrupt.store(false, Ordering::Relaxed);
+    *state.running.lock().unwrap() = reply.id.clone();
+    let input = InputReader {
+        reply: reply.clone(),
+        stdin: stdin.clone(),
+        interrupt: state.interrupt.clone(),
+        pending: Vec::new(),
+    };
+    let res = with_interrupt_flag(state.interrupt.clone(), || {
+        let _output = redirect_output(Box::new(MessageWriter("out", reply.clone())));
+        let _errors = redirect_error_output(Box::new(MessageWriter("err", reply.clone())));
+        let _input = redirect_input(Box::new(BufReader::new(input)));
+        for form in read_all(code)? {
+            let value = eval_toplevel(&form, &mut env)?;
+            // the evaluation may have been interrupted while it read input, after its last step
+            interrupt::check()?;
+            let ns = match env.get("*ns*") {
+                Some(Atom::Symbol(ns)) => ns.clone(),
+                _ => String::from("user"),
+            };
+            reply.send([("value", value.to_string().into()), ("ns", ns.into())]);
+        }
+        Ok::<_, color_eyre::Report>(())
+    });
+    *state.running.lock().unwrap() = None;
+    match res {
+        Ok(()) => reply.done(&[]),
+        Err(e) if e.downcast_ref::<MalError>() == Some(&MalError::Interrupted) => {
+            take_backtrace();
+            reply.done(&["interrupted"]);
+        }
+        Err(e) => {
+            let mut err = format!("error: {:#}\n", e);
+            if let Some(frames) = take_backtrace() {
+                err.push_str(&format_backtrace(&frames));
+                err.push('\n');
+            }
+            reply.send([("err", err.into())]);
+            reply.send([("ex", "MalError".into()), ("root-ex", "MalError".into())]);
+            reply.done(&["eval-error"]);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! An instrumenting profiler for mal programs.
+//!
+//! While profiling, every call made through a symbol is timed, and the results are collected
+//! per function name. `(profile expr)` profiles the evaluation of `expr`, and the `--profile`
+//! option of the `mal` binary profiles a whole program.
+//!
+//! Allocations are only counted if the program uses [`CountingAllocator`] as its global
+//! allocator. Otherwise, the allocation counts are all zero.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use color_eyre::{eyre::WrapErr, Result};
+
+use crate::atom::Atom;
+use crate::env::Env;
+use crate::error::MalError;
+use crate::eval::eval;
+use crate::io::write_error;
+
+/// Number of allocations made by [`CountingAllocator`]
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// A global allocator that counts allocations, so that the profiler can report them.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: mal::profile::CountingAllocator = mal::profile::CountingAllocator;
+/// ```
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// What was measured for one function
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub calls: u64,
+    /// Time spent in the function, including the functions it called. Time spent in recursive
+    /// calls is only counted once.
+    pub inclusive: Duration,
+    /// Time spent in the function itself
+    pub exclusive: Duration,
+    /// Allocations made by the function, including the functions it called
+    pub allocations: u64,
+}
+
+/// The results of profiling
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    /// Stats for each function, by name
+    pub functions: BTreeMap<String, Stats>,
+    /// Exclusive time for each call stack, with the function names separated by `;`
+    pub stacks: BTreeMap<String, Duration>,
+}
+
+impl Profile {
+    /// Formats the stats as a table, with the functions that took the most exclusive time first.
+    pub fn table(&self) -> String {
+        let mut functions = self.functions.iter().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.exclusive));
+        let mut res = format!(
+            "{:<24} {:>10} {:>14} {:>14} {:>12}",
+            "function", "calls", "inclusive", "exclusive", "allocations"
+        );
+        for (name, stats) in functions {
+            res.push_str(&format!(
+                "\n{:<24} {:>10} {:>14} {:>14} {:>12}",
+                name,
+                stats.calls,
+                format!("{:?}", stats.inclusive),
+                format!("{:?}", stats.exclusive),
+                stats.allocations
+            ));
+        }
+        res
+    }
+
+    /// Formats the call stacks in the folded format used by flamegraph tools, with one line per
+    /// stack. The value of each line is the exclusive time in microseconds.
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_micros()))
+            .collect()
+    }
+}
+
+/// A call that is being profiled
+struct Call {
+    name: String,
+    start: Instant,
+    allocations: u64,
+    /// Time spent in the calls made by this call
+    children: Duration,
+}
+
+#[derive(Default)]
+struct Profiler {
+    profile: Profile,
+    calls: Vec<Call>,
+}
+
+thread_local! {
+    /// The running profiler, if any
+    static PROFILER: RefCell<Option<Profiler>> = const { RefCell::new(None) };
+}
+
+/// Starts profiling, unless the profiler is already running. Returns whether it was started.
+pub fn start() -> bool {
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        if profiler.is_some() {
+            return false;
+        }
+        *profiler = Some(Profiler::default());
+        true
+    })
+}
+
+/// Returns whether the profiler is running.
+pub fn is_running() -> bool {
+    PROFILER.with(|profiler| profiler.borrow().is_some())
+}
+
+/// Stops profiling and returns the results, if the profiler was running.
+pub fn stop() -> Option<Profile> {
+    PROFILER.with(|profiler| profiler.borrow_mut().take().map(|x| x.profile))
+}
+
+/// Evaluates `args[0]` with the profiler running, and writes the results to the error output. If
+/// `args[1]` is given, it is evaluated to a path, and the call stacks are written there in the
+/// folded format. This implements `(profile)`.
+///
+/// If the profiler is already running, `args[0]` is only evaluated.
+pub fn profile(args: &[Atom], env: &Env) -> Result<Atom> {
+    let path = match args.get(1).map(|x| eval(x, env)).transpose()? {
+        Some(Atom::String(path)) => Some(path),
+        Some(a) => return Err(MalError::type_error("string", &a).into()),
+        None => None,
+    };
+    if !start() {
+        return eval(&args[0], env);
+    }
+    let res = eval(&args[0], env);
+    let profile = stop().unwrap_or_default();
+    write_error(&format!("{}\n", profile.table()))?;
+    if let Some(path) = path {
+        std::fs::write(&path, profile.folded())
+            .wrap_err_with(|| format!("could not write {}", path))?;
+    }
+    res
+}
+
+/// Calls `f`, and records it as a call to the function `head` if the profiler is running.
+pub fn call(head: &Atom, f: impl FnOnce() -> Result<Atom>) -> Result<Atom> {
+    let running = PROFILER.with(|profiler| match &mut *profiler.borrow_mut() {
+        Some(profiler) => {
+            let name = match head {
+                Atom::Symbol(name) => name.clone(),
+                _ => String::from("(anonymous)"),
+            };
+            profiler.calls.push(Call {
+                name,
+                start: Instant::now(),
+                allocations: ALLOCATIONS.load(Ordering::Relaxed),
+                children: Duration::ZERO,
+            });
+            true
+        }
+        None => false,
+    });
+    let res = f();
+    if running {
+        PROFILER.with(|profiler| {
+            if let Some(profiler) = &mut *profiler.borrow_mut() {
+                profiler.finish_call();
+            }
+        });
+    }
+    res
+}
+
+impl Profiler {
+    fn finish_call(&mut self) {
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let inclusive = call.start.elapsed();
+        let exclusive = inclusive.saturating_sub(call.children);
+        if let Some(parent) = self.calls.last_mut() {
+            parent.children += inclusive;
+        }
+
+        let mut stack = self
+            .calls
+            .iter()
+            .map(|x| &*x.name)
+            .collect::<Vec<_>>()
+            .join(";");
+        if !stack.is_empty() {
+            stack.push(';');
+        }
+        stack.push_str(&call.name);
+        *self.profile.stacks.entry(stack).or_default() += exclusive;
+
+        let recursive = self.calls.iter().any(|x| x.name == call.name);
+        let stats = self.profile.functions.entry(call.name).or_default();
+        stats.calls += 1;
+        stats.exclusive += exclusive;
+        if !recursive {
+            stats.inclusive += inclusive;
+            stats.allocations += ALLOCATIONS.load(Ordering::Relaxed) - call.allocations;
+        }
+    }
+}
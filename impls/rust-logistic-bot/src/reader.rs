@@ -1,15 +1,22 @@
-use color_eyre::{
-    eyre::{eyre, ContextCompat, WrapErr},
-    Result,
-};
+use color_eyre::{eyre::WrapErr, Result};
+use num_bigint::BigInt;
 use std::collections::BTreeMap;
+use std::ops::Range;
 
 use crate::atom::Atom;
+use crate::error::MalError;
+
+/// Lists that were read, with their byte ranges in the source
+pub type Spans = Vec<(Atom, Range<usize>)>;
 
 /// Stores the tokens and a position
 struct Reader {
-    tokens: Vec<String>,
+    tokens: Vec<(String, Range<usize>)>,
     position: usize,
+    /// Length of the source, used as the position of end of file errors
+    len: usize,
+    /// The lists that were read, if they are collected
+    spans: Option<Spans>,
 }
 
 impl Reader {
@@ -17,50 +24,100 @@ impl Reader {
     fn next(&mut self) -> Option<&str> {
         let ret = self.tokens.get(self.position);
         self.position += 1;
-        ret.map(|x| &**x)
+        ret.map(|x| &*x.0)
     }
 
     /// Returns the token at the current position
     fn peek(&self) -> Option<&str> {
-        self.tokens.get(self.position).map(|x| &**x)
+        self.tokens.get(self.position).map(|x| &*x.0)
+    }
+
+    /// Returns the span of the token that was last returned by `next`.
+    fn span(&self) -> Range<usize> {
+        self.position
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map(|x| x.1.clone())
+            .unwrap_or(self.len..self.len)
+    }
+
+    fn error(&self, message: &str) -> MalError {
+        MalError::reader(message, self.span())
     }
 }
 
 pub fn read_str(s: String) -> Result<Atom> {
-    let tokens = tokenize(s);
+    let tokens = tokenize(&s);
     let mut reader = Reader {
         tokens,
         position: 0,
+        len: s.len(),
+        spans: None,
     };
     read_form(&mut reader)
 }
 
-fn tokenize(haystack: String) -> Vec<String> {
+/// Reads all forms in `s`, for example the contents of a file.
+pub fn read_all(s: String) -> Result<Vec<Atom>> {
+    read_forms(s, None).map(|(forms, _)| forms)
+}
+
+/// Like [`read_all`], but also returns the lists that were read, with their byte ranges in `s`.
+pub fn read_all_with_spans(s: String) -> Result<(Vec<Atom>, Spans)> {
+    read_forms(s, Some(Vec::new())).map(|(forms, spans)| (forms, spans.unwrap_or_default()))
+}
+
+fn read_forms(s: String, spans: Option<Spans>) -> Result<(Vec<Atom>, Option<Spans>)> {
+    let tokens = tokenize(&s);
+    let mut reader = Reader {
+        tokens,
+        position: 0,
+        len: s.len(),
+        spans,
+    };
+    let mut res = Vec::new();
+    while reader.peek().is_some() {
+        res.push(read_form(&mut reader)?);
+    }
+    Ok((res, reader.spans))
+}
+
+/// Splits the source into tokens, each with its byte range in the source. Comments are skipped.
+fn tokenize(haystack: &str) -> Vec<(String, Range<usize>)> {
     let re = regex::Regex::new(
         r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"#,
     )
     .unwrap();
 
-    let tokens = haystack
-        .match_indices(&re)
-        // this works around the regex somehow not correctly trimming ',' or spaces sometimes
-        .map(|x| x.1.trim().trim_matches(|x| x == ',').trim().to_string())
-        // this filters out empty strings that may have been create in the previous step
-        .filter(|x| !x.is_empty())
-        .collect::<Vec<_>>();
-    tokens
+    re.captures_iter(haystack)
+        .filter_map(|cap| cap.get(1))
+        // the last alternative of the regex can match the empty string
+        .filter(|m| !m.as_str().is_empty() && !m.as_str().starts_with(';'))
+        .map(|m| (m.as_str().to_string(), m.range()))
+        .collect()
 }
 
 fn read_form(reader: &mut Reader) -> Result<Atom> {
-    let token = reader.next().context("unexpected end of file")?;
+    let _nesting = crate::limits::nest()?;
+    let token = match reader.next() {
+        Some(token) => token.to_string(),
+        None => return Err(reader.error("unexpected end of file").into()),
+    };
+    let token = token.as_str();
     match token
         .chars()
         .next()
         .expect("Tokens should always have at least one character")
     {
-        '(' => Ok(Atom::List(
-            read_list(reader, ")").context("while reading list")?,
-        )),
+        '(' => {
+            let start = reader.span().start;
+            let list = Atom::List(nested(read_list(reader, ")"), "while reading list")?);
+            let end = reader.span().end;
+            if let Some(spans) = &mut reader.spans {
+                spans.push((list.clone(), start..end));
+            }
+            Ok(list)
+        }
         '[' => Ok(Atom::Vector(read_list(reader, "]")?)),
         '{' => {
             let lst = read_list(reader, "}")?;
@@ -69,19 +126,21 @@ fn read_form(reader: &mut Reader) -> Result<Atom> {
             while let Some(key) = lst.next() {
                 map.insert(
                     key,
-                    lst.next()
-                        .context("invalid hashmap: got a key without a value")?,
+                    lst.next().ok_or_else(|| {
+                        reader.error("invalid hashmap: got a key without a value")
+                    })?,
                 );
             }
             Ok(Atom::HashMap(map))
         }
+        ')' | ']' | '}' => Err(reader.error(&format!("unexpected '{}'", token)).into()),
         '\"' => {
             let mut chars = token.chars();
             chars.next();
             if chars.next_back() != Some('"') {
-                Err(eyre!("unclosed string"))
+                Err(reader.error("unclosed string").into())
             } else {
-                let res = unescape(chars.as_str())?;
+                let res = unescape(chars.as_str()).map_err(|message| reader.error(message))?;
                 Ok(Atom::String(res))
             }
         }
@@ -112,20 +171,20 @@ fn read_form(reader: &mut Reader) -> Result<Atom> {
 }
 
 /// inpired by <https://docs.rs/snailquote/latest/src/snailquote/lib.rs.html#231-308/>
-fn unescape(s: &str) -> Result<String> {
+fn unescape(s: &str) -> std::result::Result<String, &'static str> {
     let mut chars = s.chars();
     let mut res = String::with_capacity(s.len());
 
     while let Some(c) = chars.next() {
         if c == '\\' {
             match chars.next() {
-                None => return Err(eyre!("unfinished escape sequence")),
+                None => return Err("unfinished escape sequence"),
                 Some(c2) => {
                     res.push(match c2 {
                         '"' => '"',
                         '\'' => '\'',
                         '\\' => '\\',
-                        _ => return Err(eyre!("unsuported escape sequence")),
+                        _ => return Err("unsuported escape sequence"),
                     });
                 }
             }
@@ -147,27 +206,59 @@ fn read_list(reader: &mut Reader, end_marker: &str) -> Result<Vec<Atom>> {
                 reader.next();
                 break;
             } else {
-                res.push(read_form(reader).context("while reading form inside of list")?);
+                res.push(nested(
+                    read_form(reader),
+                    "while reading form inside of list",
+                )?);
             }
         } else {
-            return Err(eyre!(
-                "unexpected end of file while reading list (missing '{}')",
-                end_marker
-            ));
+            return Err(MalError::reader(
+                &format!(
+                    "unexpected end of file while reading list (missing '{}')",
+                    end_marker
+                ),
+                reader.len..reader.len,
+            )
+            .into());
         }
     }
     Ok(res)
 }
 
-fn read_atom(token: &str) -> Atom {
+/// Adds `context` to the error of reading a nested form, unless the forms are nested too deep,
+/// which would add it once for every level.
+fn nested<T>(res: Result<T>, context: &'static str) -> Result<T> {
+    match res {
+        Err(e) if matches!(e.downcast_ref(), Some(MalError::LimitExceeded(_))) => Err(e),
+        res => res.context(context),
+    }
+}
+
+/// Reads `token` as an integer if it is ASCII digits with an optional sign. [`BigInt`] would
+/// also accept `_` between the digits.
+pub(crate) fn parse_integer(token: &str) -> Option<Atom> {
+    let digits = token.strip_prefix(['+', '-']).unwrap_or(token);
+    if digits.is_empty() || !digits.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
     match token.parse::<i64>() {
-        Ok(num) => Atom::Integer(num),
-        Err(_) => {
-            if token.starts_with(':') {
-                Atom::Keyword(token.chars().skip(1).collect())
-            } else {
-                Atom::Symbol(token.to_string())
-            }
-        }
+        Ok(num) => Some(Atom::Integer(num)),
+        Err(_) => token.parse::<BigInt>().ok().map(Atom::from_bigint),
+    }
+}
+
+fn read_atom(token: &str) -> Atom {
+    if token == "nil" {
+        Atom::Nil
+    } else if token == "true" {
+        Atom::Bool(true)
+    } else if token == "false" {
+        Atom::Bool(false)
+    } else if let Some(num) = parse_integer(token) {
+        num
+    } else if token.starts_with(':') {
+        Atom::Keyword(token.chars().skip(1).collect())
+    } else {
+        Atom::Symbol(token.to_string())
     }
 }
@@ -0,0 +1,458 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::IsTerminal;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use color_eyre::Result;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use crate::atom::Atom;
+use crate::env::{default_env, Env};
+use crate::eval::{eval_toplevel, load_file, EVAL_COUNT, SPECIAL_FORMS};
+use crate::highlight::{colorize, highlight_source};
+use crate::interrupt::{clear_sigint, install_sigint_handler};
+use crate::module;
+use crate::reader::read_str;
+use crate::stack::{format_backtrace, take_backtrace};
+
+/// REPL commands, with their argument and description
+const COMMANDS: &[(&str, &str, &str)] = &[
+    (":env", "", "list the bindings in the environment"),
+    (":type", "expr", "show the type of the value of expr"),
+    (":time", "expr", "evaluate expr and show how long it took"),
+    (":load", "path", "evaluate all forms in a file"),
+    (":debug", "expr", "step through the evaluation of expr"),
+    (
+        ":reset",
+        "",
+        "restore the default environment and forget loaded modules",
+    ),
+    (":help", "", "show this help"),
+];
+
+/// Runs the REPL until end of file.
+///
+/// Ctrl-C interrupts the form that is being evaluated, and clears the line at the prompt.
+pub fn run(mut env: Env) -> Result<()> {
+    install_sigint_handler()?;
+    let mut rl = rustyline::Editor::<MalHelper>::new()?;
+    rl.set_helper(Some(MalHelper::new(&env)));
+    let _ = rl.load_history(".lisphistory.txt");
+
+    loop {
+        let readline = rl.readline("user> ");
+        match readline {
+            Ok(line) => {
+                rl.add_history_entry(&line);
+                clear_sigint();
+                match run_command(&line, &mut env) {
+                    Some(output) => println!("{}", output),
+                    None => println!("{}", read_eval_print(line.clone(), &mut env)),
+                }
+                if let Some(helper) = rl.helper_mut() {
+                    helper.refresh(&env, &line);
+                }
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        }
+    }
+
+    let _ = rl.save_history(".lisphistory.txt");
+    Ok(())
+}
+
+/// Runs `line` if it is a REPL command, and returns its output.
+fn run_command(line: &str, env: &mut Env) -> Option<String> {
+    let line = line.trim();
+    let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let arg = arg.trim();
+    let output = match command {
+        ":env" => env
+            .iter()
+            .map(|(k, v)| format!("{} = {}", k, v))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ":type" => match read_str(arg.to_string()).and_then(|atom| eval_toplevel(&atom, env)) {
+            Ok(atom) => atom.type_name().to_string(),
+            Err(e) => e.to_string(),
+        },
+        ":time" => {
+            let evals = EVAL_COUNT.load(Ordering::Relaxed);
+            let start = Instant::now();
+            let res = read_eval_print(arg.to_string(), env);
+            format!(
+                "{}\nelapsed: {:?}, evaluations: {}",
+                res,
+                start.elapsed(),
+                EVAL_COUNT.load(Ordering::Relaxed) - evals
+            )
+        }
+        ":debug" => read_eval_print(format!("(debug {})", arg), env),
+        ":load" => match load_file(arg, env) {
+            Ok(atom) => print(atom),
+            Err(e) => e.to_string(),
+        },
+        ":reset" => {
+            *env = default_env();
+            module::clear_cache();
+            String::from("environment reset")
+        }
+        ":help" => COMMANDS
+            .iter()
+            .map(|(command, arg, description)| {
+                format!("{:<14}{}", format!("{} {}", command, arg), description)
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => return None,
+    };
+    Some(output)
+}
+
+fn read_eval_print(s: String, env: &mut Env) -> String {
+    let atom = read_str(s);
+    let atom = match atom {
+        Ok(atom) => atom,
+        Err(e) => return e.to_string(),
+    };
+    let result = eval_toplevel(&atom, env);
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            return match take_backtrace() {
+                Some(frames) => format!("{}\n{}", e, format_backtrace(&frames)),
+                None => e.to_string(),
+            };
+        }
+    };
+    print(result)
+}
+
+/// Prints an atom, colored if stdout is a terminal.
+pub fn print(atom: Atom) -> String {
+    if std::io::stdout().is_terminal() {
+        colorize(&atom)
+    } else {
+        atom.to_string()
+    }
+}
+
+/// Rustyline helper for the REPL.
+///
+/// Completes symbols bound in the environment, special forms, keywords that were used in
+/// previous lines, and the file path after `:load` or in the string after `(load-file`. Also highlights the line as it is typed.
+#[derive(Default)]
+pub struct MalHelper {
+    symbols: BTreeSet<String>,
+    keywords: BTreeSet<String>,
+}
+
+impl MalHelper {
+    pub fn new(env: &Env) -> Self {
+        let mut helper = Self::default();
+        helper.refresh(env, "");
+        helper
+    }
+
+    /// Updates the completions after `line` was evaluated in `env`.
+    pub fn refresh(&mut self, env: &Env, line: &str) {
+        self.symbols = env.keys().cloned().collect();
+        if let Ok(atom) = crate::reader::read_str(line.to_string()) {
+            collect_keywords(&atom, &mut self.keywords);
+        }
+    }
+}
+
+fn collect_keywords(atom: &Atom, keywords: &mut BTreeSet<String>) {
+    match atom {
+        Atom::Keyword(keyword) => {
+            keywords.insert(keyword.clone());
+        }
+        Atom::List(list) | Atom::Vector(list) => {
+            list.iter().for_each(|x| collect_keywords(x, keywords));
+        }
+        Atom::Set(set) => set.iter().for_each(|x| collect_keywords(x, keywords)),
+        Atom::HashMap(map) => map.iter().for_each(|(k, v)| {
+            collect_keywords(k, keywords);
+            collect_keywords(v, keywords);
+        }),
+        Atom::Tagged(_, value) => collect_keywords(value, keywords),
+        _ => {}
+    }
+}
+
+/// Characters that can not be part of a symbol or keyword
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}'\"`~@^,;".contains(c)
+}
+
+/// Returns the position right after the opening quote, if `pos` is inside of a string.
+fn string_start(line: &str, pos: usize) -> Option<usize> {
+    let mut start = None;
+    let mut escaped = false;
+    for (i, c) in line[..pos].char_indices() {
+        match (start, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(_), '"') => start = None,
+            (None, '"') => start = Some(i + 1),
+            (None, ';') => return None,
+            _ => {}
+        }
+    }
+    start
+}
+
+/// Completes a file path, which is relative to the current directory.
+fn complete_path(partial: &str) -> Vec<Pair> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => partial.split_at(i + 1),
+        None => ("", partial),
+    };
+    let path = if dir.is_empty() { "." } else { dir };
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut res = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(Pair {
+                display: format!("{}{}", name, suffix),
+                replacement: format!("{}{}{}", dir, name, suffix),
+            })
+        })
+        .collect::<Vec<_>>();
+    res.sort_by(|a, b| a.display.cmp(&b.display));
+    res
+}
+
+impl Completer for MalHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        if let Some(path) = line[..pos].strip_prefix(":load ") {
+            let path = path.trim_start();
+            return Ok((pos - path.len(), complete_path(path)));
+        }
+        if let Some(start) = string_start(line, pos) {
+            // the argument of load-file is a path, other strings are not completed
+            if line[..start - 1].trim_end().ends_with("(load-file") {
+                return Ok((start, complete_path(&line[start..pos])));
+            }
+            return Ok((pos, Vec::new()));
+        }
+
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| is_delimiter(*c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &line[start..pos];
+        let candidates: BTreeSet<String> = match word.strip_prefix(':') {
+            Some(keyword) => self
+                .keywords
+                .iter()
+                .filter(|x| x.starts_with(keyword))
+                .map(|x| format!(":{}", x))
+                .collect(),
+            None => self
+                .symbols
+                .iter()
+                .map(|x| &**x)
+                .chain(SPECIAL_FORMS.iter().copied())
+                .filter(|x| x.starts_with(word))
+                .map(|x| x.to_string())
+                .collect(),
+        };
+        let candidates = candidates
+            .into_iter()
+            .map(|x| Pair {
+                display: x.clone(),
+                replacement: x,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = String;
+}
+
+impl Highlighter for MalHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight_source(line, pos))
+    }
+
+    /// Always highlight again, since moving the cursor changes which brackets are highlighted.
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Validator for MalHelper {}
+
+impl Helper for MalHelper {}
+
+#[cfg(test)]
+mod tests {
+    use rustyline::history::History;
+
+    use super::*;
+
+    /// Returns where the completions of `line` with the cursor at its end start, and their
+    /// replacements.
+    fn complete(helper: &MalHelper, line: &str) -> (usize, Vec<String>) {
+        let history = History::new();
+        let (start, candidates) = helper
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap();
+        (
+            start,
+            candidates.into_iter().map(|x| x.replacement).collect(),
+        )
+    }
+
+    #[test]
+    fn commands() {
+        let mut env = default_env();
+        assert_eq!(run_command("(+ 1 2)", &mut env), None);
+        assert_eq!(run_command(":nothing", &mut env), None);
+
+        let help = run_command(":help", &mut env).unwrap();
+        assert_eq!(help.lines().count(), COMMANDS.len());
+        assert!(help.starts_with(":env          list the bindings"));
+
+        assert_eq!(run_command(":type 1", &mut env).unwrap(), "Integer");
+        // definitions are kept, also inside of forms
+        assert_eq!(
+            run_command(":type (do (def! f (fn* [] [])) f)", &mut env).unwrap(),
+            "Closure"
+        );
+        assert_eq!(run_command(":type (f)", &mut env).unwrap(), "Vector");
+        assert_eq!(
+            run_command(":type nothing", &mut env).unwrap(),
+            "symbol nothing is not bound to any value"
+        );
+        assert!(run_command(":env", &mut env)
+            .unwrap()
+            .lines()
+            .any(|x| x == "f = #<FN f>"));
+
+        let time = run_command(":time (+ 1 2)", &mut env).unwrap();
+        let (value, stats) = time.split_once('\n').unwrap();
+        assert_eq!(value, "3");
+        assert!(stats.starts_with("elapsed: "), "{}", time);
+        // other tests may evaluate at the same time, which is counted too
+        let (_, evaluations) = stats.split_once(", evaluations: ").unwrap();
+        assert!(evaluations.parse::<usize>().unwrap() >= 4, "{}", time);
+
+        let path = std::env::temp_dir().join(format!("mal-repl-{}.mal", std::process::id()));
+        std::fs::write(&path, "(def! loaded 1)\n(+ loaded 1)").unwrap();
+        let load = format!(":load {}", path.display());
+        assert_eq!(run_command(&load, &mut env).unwrap(), "2");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(env.get("loaded"), Some(&Atom::Integer(1)));
+        assert!(run_command(&load, &mut env)
+            .unwrap()
+            .starts_with("could not read"));
+
+        assert_eq!(
+            run_command(":reset", &mut env).unwrap(),
+            "environment reset"
+        );
+        assert_eq!(env, default_env());
+    }
+
+    #[test]
+    fn symbols_and_keywords_are_completed() {
+        let mut env = default_env();
+        env.insert(String::from("edn/write-str"), Atom::Nil);
+        let mut helper = MalHelper::new(&env);
+        helper.refresh(&env, "{:name 1 :nesting 2 :other 3}");
+        assert_eq!(
+            complete(&helper, "(edn/wr"),
+            (
+                1,
+                vec![
+                    String::from("edn/write-str"),
+                    String::from("edn/write-string")
+                ]
+            )
+        );
+        assert_eq!(complete(&helper, "(let"), (1, vec![String::from("let*")]));
+        assert_eq!(
+            complete(&helper, "(get m :n"),
+            (7, vec![String::from(":name"), String::from(":nesting")])
+        );
+        assert_eq!(complete(&helper, "(nothing-like-this"), (1, Vec::new()));
+    }
+
+    #[test]
+    fn paths_are_completed() {
+        let helper = MalHelper::default();
+        let modules = vec![
+            String::from("tests/modules.rs"),
+            String::from("tests/modules/"),
+        ];
+        assert_eq!(complete(&helper, ":load tests/modu"), (6, modules.clone()));
+        assert_eq!(complete(&helper, "(load-file \"tests/modu"), (12, modules));
+        // other strings are not completed
+        assert_eq!(complete(&helper, "(println \"tests/modu"), (20, Vec::new()));
+    }
+
+    #[test]
+    fn strings_are_found() {
+        let start = |line: &str| string_start(line, line.len());
+        assert_eq!(start("(f \"ab"), Some(4));
+        assert_eq!(start("(f \"ab\" c"), None);
+        assert_eq!(start(r#"(f "a\"b"#), Some(4));
+        assert_eq!(start("(f \"a\" \"b"), Some(8));
+        assert_eq!(start("(f) ; \"ab"), None);
+        assert_eq!(string_start("(f \"ab\"", 5), Some(4));
+    }
+
+    #[test]
+    fn complete_path_lists_a_directory() {
+        let names = |partial| {
+            complete_path(partial)
+                .into_iter()
+                .map(|x| (x.display, x.replacement))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("tests/modules/geo"),
+            [(
+                String::from("geometry/"),
+                String::from("tests/modules/geometry/")
+            )]
+        );
+        assert_eq!(
+            names("Cargo.to"),
+            [(String::from("Cargo.toml"), String::from("Cargo.toml"))]
+        );
+        assert!(names("no/such/dir/").is_empty());
+    }
+}
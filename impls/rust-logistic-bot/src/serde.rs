@@ -0,0 +1,595 @@
+//! Conversions between [`Atom`] and Rust types using serde.
+//!
+//! Hash maps become maps, lists, vectors and sets become sequences, and keywords and symbols
+//! become strings. Tagged values are converted without their tag. In the other direction,
+//! struct fields and enum variants become keywords, so a `#[derive(Deserialize)]` struct can be
+//! read from a map like `{:name "mal" :steps 10}`. Builtins can not be converted.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use ::serde::de::{
+    self, value::MapDeserializer, value::SeqDeserializer, DeserializeSeed, IntoDeserializer,
+    MapAccess, SeqAccess, Visitor,
+};
+use ::serde::ser::{self, Serialize};
+use ::serde::{forward_to_deserialize_any, Deserialize, Deserializer, Serializer};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use crate::atom::Atom;
+use crate::error::MalError;
+
+/// Converts a Rust value to an atom.
+pub fn to_atom<T: Serialize + ?Sized>(value: &T) -> Result<Atom, MalError> {
+    value.serialize(AtomSerializer)
+}
+
+/// Converts an atom to a Rust value.
+pub fn from_atom<'de, T: Deserialize<'de>>(atom: &'de Atom) -> Result<T, MalError> {
+    T::deserialize(atom)
+}
+
+impl ser::Error for MalError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        MalError::Conversion(msg.to_string())
+    }
+}
+
+impl de::Error for MalError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        MalError::Conversion(msg.to_string())
+    }
+}
+
+impl Serialize for Atom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Atom::Nil => serializer.serialize_unit(),
+            Atom::Bool(b) => serializer.serialize_bool(*b),
+            Atom::Integer(num) => serializer.serialize_i64(*num),
+            Atom::BigInteger(num) => match num.to_i128() {
+                Some(num) => serializer.serialize_i128(num),
+                None => Err(ser::Error::custom(format!(
+                    "integer {} does not fit in 128 bits",
+                    num
+                ))),
+            },
+            Atom::Symbol(s) | Atom::Keyword(s) | Atom::String(s) => serializer.serialize_str(s),
+            Atom::List(list) | Atom::Vector(list) => serializer.collect_seq(list),
+            Atom::HashMap(map) => serializer.collect_map(map),
+            Atom::Set(set) => serializer.collect_seq(set),
+            Atom::Char(c) => serializer.serialize_char(*c),
+            Atom::Tagged(_, value) => value.serialize(serializer),
+            Atom::Builtin(_) | Atom::NativeFn(_) | Atom::Closure(_) => {
+                Err(ser::Error::custom("functions can not be serialized"))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Atom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AtomVisitor)
+    }
+}
+
+struct AtomVisitor;
+
+impl<'de> Visitor<'de> for AtomVisitor {
+    type Value = Atom;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value that can be represented in mal")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Atom, E> {
+        Ok(Atom::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Atom, E> {
+        Ok(Atom::Integer(v))
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<Atom, E> {
+        Ok(Atom::from_bigint(BigInt::from(v)))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Atom, E> {
+        Ok(Atom::from_bigint(BigInt::from(v)))
+    }
+
+    fn visit_u128<E>(self, v: u128) -> Result<Atom, E> {
+        Ok(Atom::from_bigint(BigInt::from(v)))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Atom, E> {
+        Ok(Atom::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Atom, E> {
+        Ok(Atom::String(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<Atom, E> {
+        Ok(Atom::Nil)
+    }
+
+    fn visit_none<E>(self) -> Result<Atom, E> {
+        Ok(Atom::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Atom, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Atom, A::Error> {
+        let mut res = Vec::new();
+        while let Some(elem) = seq.next_element()? {
+            res.push(elem);
+        }
+        Ok(Atom::Vector(res))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Atom, A::Error> {
+        let mut res = BTreeMap::new();
+        while let Some((k, v)) = map.next_entry()? {
+            res.insert(k, v);
+        }
+        Ok(Atom::HashMap(res))
+    }
+}
+
+impl<'de> Deserializer<'de> for &'de Atom {
+    type Error = MalError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, MalError> {
+        match self {
+            Atom::Nil => visitor.visit_unit(),
+            Atom::Bool(b) => visitor.visit_bool(*b),
+            Atom::Integer(num) => visitor.visit_i64(*num),
+            // serde's visitors for the primitive types only take 128 bit integers for the 128
+            // bit types, so the ones that fit in 64 bits are passed as u64
+            Atom::BigInteger(num) => match (num.to_u64(), num.to_i128()) {
+                (Some(num), _) => visitor.visit_u64(num),
+                (None, Some(num)) => visitor.visit_i128(num),
+                (None, None) => Err(de::Error::custom(format!(
+                    "integer {} does not fit in 128 bits",
+                    num
+                ))),
+            },
+            Atom::Symbol(s) | Atom::Keyword(s) | Atom::String(s) => visitor.visit_borrowed_str(s),
+            Atom::List(list) | Atom::Vector(list) => {
+                let mut seq = SeqDeserializer::<_, MalError>::new(list.iter());
+                let res = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(res)
+            }
+            Atom::HashMap(map) => {
+                let mut map = MapDeserializer::<_, MalError>::new(map.iter());
+                let res = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(res)
+            }
+            Atom::Set(set) => {
+                let mut seq = SeqDeserializer::<_, MalError>::new(set.iter());
+                let res = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(res)
+            }
+            Atom::Char(c) => visitor.visit_char(*c),
+            Atom::Tagged(_, value) => value.deserialize_any(visitor),
+            Atom::Builtin(_) | Atom::NativeFn(_) | Atom::Closure(_) => {
+                Err(de::Error::custom("functions can not be deserialized"))
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, MalError> {
+        match self {
+            Atom::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, MalError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are read from a string or keyword, other variants from a map with a
+    /// single entry, like `{:Variant value}`.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, MalError> {
+        match self {
+            Atom::Keyword(s) | Atom::String(s) => {
+                visitor.visit_enum(s.as_str().into_deserializer())
+            }
+            Atom::HashMap(map) if map.len() == 1 => {
+                let (variant, value) = map.iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            a => Err(MalError::type_error("an enum variant", a)),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, MalError> for &'de Atom {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+struct EnumDeserializer<'de> {
+    variant: &'de Atom,
+    value: &'de Atom,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer<'de> {
+    type Error = MalError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), MalError> {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumDeserializer<'de> {
+    type Error = MalError;
+
+    fn unit_variant(self) -> Result<(), MalError> {
+        Deserialize::deserialize(self.value)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, MalError> {
+        seed.deserialize(self.value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, MalError> {
+        self.value.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, MalError> {
+        self.value.deserialize_map(visitor)
+    }
+}
+
+/// A serializer that turns Rust values into atoms.
+pub struct AtomSerializer;
+
+/// Wraps the value of an enum variant in a map like `{:Variant value}`.
+fn wrap_variant(variant: Option<&'static str>, value: Atom) -> Atom {
+    match variant {
+        Some(variant) => Atom::HashMap(BTreeMap::from([(
+            Atom::Keyword(variant.to_string()),
+            value,
+        )])),
+        None => value,
+    }
+}
+
+impl Serializer for AtomSerializer {
+    type Ok = Atom;
+    type Error = MalError;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<Atom, MalError> {
+        Ok(Atom::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Atom, MalError> {
+        Ok(Atom::Integer(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Atom, MalError> {
+        Ok(Atom::Integer(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Atom, MalError> {
+        Ok(Atom::Integer(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Atom, MalError> {
+        Ok(Atom::Integer(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Atom, MalError> {
+        Ok(Atom::from_bigint(BigInt::from(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Atom, MalError> {
+        Ok(Atom::Integer(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Atom, MalError> {
+        Ok(Atom::Integer(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Atom, MalError> {
+        Ok(Atom::Integer(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Atom, MalError> {
+        Ok(Atom::from_bigint(BigInt::from(v)))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Atom, MalError> {
+        Ok(Atom::from_bigint(BigInt::from(v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Atom, MalError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Atom, MalError> {
+        Err(ser::Error::custom(format!(
+            "can not serialize {}: floating point numbers are not supported",
+            v
+        )))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Atom, MalError> {
+        Ok(Atom::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Atom, MalError> {
+        Ok(Atom::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Atom, MalError> {
+        Ok(Atom::Vector(
+            v.iter().map(|b| Atom::Integer((*b).into())).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Atom, MalError> {
+        Ok(Atom::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Atom, MalError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Atom, MalError> {
+        Ok(Atom::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Atom, MalError> {
+        Ok(Atom::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Atom, MalError> {
+        Ok(Atom::Keyword(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Atom, MalError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Atom, MalError> {
+        Ok(wrap_variant(Some(variant), value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, MalError> {
+        Ok(SerializeVec {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, MalError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, MalError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, MalError> {
+        Ok(SerializeVec {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, MalError> {
+        Ok(SerializeMap {
+            variant: None,
+            map: BTreeMap::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap, MalError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeMap, MalError> {
+        Ok(SerializeMap {
+            variant: Some(variant),
+            map: BTreeMap::new(),
+            next_key: None,
+        })
+    }
+}
+
+pub struct SerializeVec {
+    variant: Option<&'static str>,
+    items: Vec<Atom>,
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = Atom;
+    type Error = MalError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        self.items.push(to_atom(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Atom, MalError> {
+        Ok(wrap_variant(self.variant, Atom::Vector(self.items)))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = Atom;
+    type Error = MalError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Atom, MalError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = Atom;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Atom, MalError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = Atom;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Atom, MalError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub struct SerializeMap {
+    variant: Option<&'static str>,
+    map: BTreeMap<Atom, Atom>,
+    next_key: Option<Atom>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Atom;
+    type Error = MalError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), MalError> {
+        self.next_key = Some(to_atom(key)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalError> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| ser::Error::custom("serialize_value called before serialize_key"))?;
+        self.map.insert(key, to_atom(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Atom, MalError> {
+        Ok(wrap_variant(self.variant, Atom::HashMap(self.map)))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Atom;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), MalError> {
+        self.map
+            .insert(Atom::Keyword(key.to_string()), to_atom(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Atom, MalError> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = Atom;
+    type Error = MalError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), MalError> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Atom, MalError> {
+        ser::SerializeMap::end(self)
+    }
+}
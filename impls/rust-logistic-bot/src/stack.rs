@@ -0,0 +1,197 @@
+//! The mal call stack, used to print backtraces when an error is not caught, and to give
+//! `catch*` the stack from where the error was raised.
+//!
+//! Frames know where their call is in the source if the call was read from a file evaluated
+//! with [`eval_named_source`](crate::eval::eval_named_source), and is not in that file more
+//! than once.
+
+use crate::atom::Atom;
+use crate::reader::Spans;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+/// Calls longer than this are shortened in backtraces.
+const MAX_FORM_LENGTH: usize = 80;
+
+/// A call that is being evaluated
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The whole call, like `(+ 1 2)`
+    pub form: Atom,
+}
+
+/// Where a form is in the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// The file the source was read from
+    pub source: String,
+    /// The line, starting at 1
+    pub line: usize,
+    /// The column in characters, starting at 1
+    pub column: usize,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.column)
+    }
+}
+
+impl Frame {
+    /// Where the call is in the source, if that is known.
+    pub fn location(&self) -> Option<Location> {
+        LOCATIONS.with(|locations| locations.borrow().get(&self.form).cloned().flatten())
+    }
+
+    /// The name of the called function, if it was called through a symbol.
+    pub fn name(&self) -> Option<&str> {
+        match &self.form {
+            Atom::List(list) => match list.first() {
+                Some(Atom::Symbol(sym)) => Some(sym),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let form = self.form.to_string();
+        let form = if form.chars().count() > MAX_FORM_LENGTH {
+            form.chars().take(MAX_FORM_LENGTH - 3).collect::<String>() + "..."
+        } else {
+            form
+        };
+        match self.name() {
+            Some(name) => write!(f, "{} in {}", name, form)?,
+            None => write!(f, "{}", form)?,
+        }
+        match self.location() {
+            Some(location) => write!(f, " at {}", location),
+            None => Ok(()),
+        }
+    }
+}
+
+thread_local! {
+    /// The calls that are being evaluated, innermost last
+    static CALL_STACK: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+    /// The call stack at the point where the last error happened
+    static ERROR_STACK: RefCell<Option<Vec<Frame>>> = const { RefCell::new(None) };
+    /// The call stack of the error that `catch*` is handling
+    static CAUGHT: RefCell<Option<Vec<Frame>>> = const { RefCell::new(None) };
+    /// Where the lists read from files are, or `None` for lists that are in more than one place
+    static LOCATIONS: RefCell<BTreeMap<Atom, Option<Location>>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Pops the frame pushed by [`push_frame`] when dropped.
+pub struct FrameGuard(());
+
+impl Drop for FrameGuard {
+    fn drop(&mut self) {
+        CALL_STACK.with(|stack| stack.borrow_mut().pop());
+    }
+}
+
+/// Pushes a call onto the call stack, until the returned guard is dropped.
+pub fn push_frame(form: &Atom) -> FrameGuard {
+    CALL_STACK.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        if stack.is_empty() {
+            // a new evaluation started, so the last error is not interesting anymore
+            ERROR_STACK.with(|error_stack| error_stack.borrow_mut().take());
+        }
+        stack.push(Frame { form: form.clone() });
+    });
+    FrameGuard(())
+}
+
+/// Remembers the current call stack as the place where an error happened, unless an error is
+/// already being propagated from a deeper call.
+pub fn record_error() {
+    ERROR_STACK.with(|error_stack| {
+        let mut error_stack = error_stack.borrow_mut();
+        if error_stack.is_none() {
+            *error_stack = Some(call_stack());
+        }
+    });
+}
+
+/// Returns the calls that are being evaluated, innermost last.
+pub fn call_stack() -> Vec<Frame> {
+    CALL_STACK.with(|stack| stack.borrow().clone())
+}
+
+/// Returns the number of calls that are being evaluated.
+pub fn depth() -> usize {
+    CALL_STACK.with(|stack| stack.borrow().len())
+}
+
+/// Returns the call stack at the point where the last error happened, and forgets it.
+pub fn take_backtrace() -> Option<Vec<Frame>> {
+    ERROR_STACK.with(|error_stack| error_stack.borrow_mut().take())
+}
+
+/// Formats a call stack for printing, innermost call first.
+pub fn format_backtrace(frames: &[Frame]) -> String {
+    let mut res = String::from("mal backtrace:");
+    for (i, frame) in frames.iter().rev().enumerate() {
+        res.push_str(&format!("\n  {}: {}", i, frame));
+    }
+    res
+}
+
+/// Forgets the call stack of the last error, when an evaluation starts that can catch errors.
+pub fn clear_backtrace() {
+    ERROR_STACK.with(|error_stack| error_stack.borrow_mut().take());
+}
+
+/// Restores the stack of the error that was being handled when dropped.
+pub struct CaughtGuard(Option<Vec<Frame>>);
+
+impl Drop for CaughtGuard {
+    fn drop(&mut self) {
+        CAUGHT.with(|caught| *caught.borrow_mut() = self.0.take());
+    }
+}
+
+/// Makes `frames` the stack of the error that is being handled, until the returned guard is
+/// dropped.
+pub fn set_caught(frames: Vec<Frame>) -> CaughtGuard {
+    CaughtGuard(CAUGHT.with(|caught| caught.replace(Some(frames))))
+}
+
+/// Returns the stack of the error that `catch*` is handling, if any.
+pub fn caught_stack() -> Option<Vec<Frame>> {
+    CAUGHT.with(|caught| caught.borrow().clone())
+}
+
+/// Remembers where the lists in `spans`, which are byte ranges in `source`, are. `name` is the
+/// file `source` was read from.
+pub fn add_locations(name: &str, source: &str, spans: Spans) {
+    let line_starts = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<_>>();
+    LOCATIONS.with(|locations| {
+        let mut locations = locations.borrow_mut();
+        for (form, span) in spans {
+            let line = line_starts.partition_point(|&start| start <= span.start);
+            let line_start = line_starts[line - 1];
+            let location = Location {
+                source: name.to_string(),
+                line,
+                column: source[line_start..span.start].chars().count() + 1,
+            };
+            locations
+                .entry(form)
+                .and_modify(|known| {
+                    if known.as_ref() != Some(&location) {
+                        *known = None;
+                    }
+                })
+                .or_insert(Some(location));
+        }
+    });
+}
@@ -0,0 +1,90 @@
+//! Tracing of function calls, for debugging without adding `println`s.
+//!
+//! `(trace 'f)` logs every call to the function named `f` with its arguments, and what it
+//! returned, to the [error output](crate::io::write_error). Functions are traced by identity, so
+//! calls are logged however the function is reached, also when it is passed to another function
+//! or calls itself. Nested traced calls are indented. `(trace-all expr)` traces every call to a
+//! function made with `fn*` while `expr` is evaluated.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+
+use color_eyre::Result;
+
+use crate::atom::Atom;
+use crate::env::Env;
+use crate::eval::{apply, eval};
+use crate::io::write_error;
+
+thread_local! {
+    /// The traced functions
+    static TRACED: RefCell<BTreeSet<Atom>> = const { RefCell::new(BTreeSet::new()) };
+    /// Whether all calls to functions made with `fn*` are traced
+    static TRACE_ALL: Cell<bool> = const { Cell::new(false) };
+    /// Number of traced calls that are being evaluated
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Starts tracing calls to the function `f`.
+pub fn trace(f: &Atom) {
+    TRACED.with(|traced| traced.borrow_mut().insert(f.clone()));
+}
+
+/// Stops tracing calls to the function `f`, or to all functions if `f` is `None`.
+pub fn untrace(f: Option<&Atom>) {
+    TRACED.with(|traced| match f {
+        Some(f) => {
+            traced.borrow_mut().remove(f);
+        }
+        None => traced.borrow_mut().clear(),
+    });
+}
+
+/// Returns whether calls to the function `f` are traced.
+pub(crate) fn is_traced(f: &Atom) -> bool {
+    (TRACE_ALL.with(|all| all.get()) && matches!(f, Atom::Closure(_)))
+        || TRACED.with(|traced| traced.borrow().contains(f))
+}
+
+/// Puts back whether all calls were traced before [`trace_all`] when dropped, also when the
+/// evaluation fails or panics.
+struct TraceAllGuard(bool);
+
+impl Drop for TraceAllGuard {
+    fn drop(&mut self) {
+        TRACE_ALL.with(|all| all.set(self.0));
+    }
+}
+
+/// Evaluates `ast` with every call to a function made with `fn*` traced. This implements
+/// `(trace-all)`.
+pub fn trace_all(ast: &Atom, env: &Env) -> Result<Atom> {
+    let _guard = TraceAllGuard(TRACE_ALL.with(|all| all.replace(true)));
+    eval(ast, env)
+}
+
+/// Like [`apply`], but logs the call if `f` is traced. `head` is the unevaluated first element of
+/// the call, which names the function in the log if it is a symbol.
+pub fn apply_traced(head: &Atom, f: &Atom, args: Vec<Atom>) -> Result<Atom> {
+    if !is_traced(f) {
+        return apply(f, args);
+    }
+    let name = match (head, f) {
+        (Atom::Symbol(_), _) => head.clone(),
+        (_, Atom::Closure(closure)) => closure.name.clone().map_or_else(|| f.clone(), Atom::Symbol),
+        _ => f.clone(),
+    };
+    let depth = DEPTH.with(|depth| depth.get());
+    let indent = "| ".repeat(depth);
+    let mut call = vec![name];
+    call.extend(args.iter().cloned());
+    write_error(&format!("{}{}\n", indent, Atom::List(call)))?;
+    DEPTH.with(|d| d.set(depth + 1));
+    let res = apply(f, args);
+    DEPTH.with(|d| d.set(depth));
+    match &res {
+        Ok(value) => write_error(&format!("{}=> {}\n", indent, value))?,
+        Err(e) => write_error(&format!("{}!! {}\n", indent, e))?,
+    }
+    res
+}
@@ -0,0 +1,389 @@
+//! A stack-based VM that runs the bytecode made by the [`compiler`](crate::compiler).
+//!
+//! It gives the same values and errors as the tree-walker, [`walk`]: it counts the same
+//! evaluation steps for [`limits`] and [`EVAL_COUNT`], checks for interrupts, keeps the call
+//! stack for backtraces, and calls functions through the profiler and the tracer. Only the
+//! debugger is different: it steps through forms as the tree-walker evaluates them, so after a
+//! `(break)`, stepping resumes at the next form that is walked.
+//!
+//! Locals are kept in slots, and a call in tail position to a closure returns to
+//! [`call_closure`], which runs the closure in place of the one that made the call, so that
+//! tail calls don't use the Rust stack. `try*` remembers how long the stacks were when its body
+//! started, and goes back to that when the body raises an error.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use color_eyre::Result;
+
+use crate::atom::{Atom, Closure};
+use crate::compiler::{compile_function, Binding, Chunk, Op};
+use crate::destructure;
+use crate::env::Env;
+use crate::error::MalError;
+use crate::eval::{capture, caught_value, global, stack_value, walk, EVAL_COUNT};
+use crate::interrupt;
+use crate::limits;
+use crate::module;
+use crate::profile;
+use crate::stack::{
+    call_stack, clear_backtrace, push_frame, record_error, set_caught, take_backtrace, CaughtGuard,
+    FrameGuard,
+};
+use crate::trace;
+
+/// How code stopped running
+enum Exit {
+    Value(Atom),
+    /// The closure should be called with the arguments, in place of the code
+    TailCall(Arc<Closure>, Vec<Atom>),
+}
+
+/// The state of running code
+struct Run<'a> {
+    chunk: &'a Chunk,
+    /// Where symbols that are not in slots are looked up, before the globals
+    env: &'a Env,
+    slots: Vec<Atom>,
+    stack: Vec<Atom>,
+    /// The calls that were entered and did not return yet
+    frames: Vec<FrameGuard>,
+    /// The `try*` forms whose bodies are running, innermost last
+    tries: Vec<Try>,
+    /// The errors that are being handled, innermost last
+    caught: Vec<CaughtGuard>,
+}
+
+/// A `try*` whose body is running
+struct Try {
+    /// The index in [`Chunk::handlers`]
+    handler: usize,
+    /// The lengths of the stack and of the frames when the body started
+    stack: usize,
+    frames: usize,
+    /// The number of errors that were being handled when the body started
+    caught: usize,
+}
+
+impl Drop for Run<'_> {
+    fn drop(&mut self) {
+        self.end_catches(0);
+    }
+}
+
+/// Runs `chunk`, and returns the value it computes.
+pub fn run(chunk: &Chunk, env: &Env) -> Result<Atom> {
+    let _nesting = limits::nest()?;
+    let run = Run::new(chunk, env);
+    match run.execute()? {
+        Exit::Value(value) => Ok(value),
+        Exit::TailCall(..) => {
+            panic!("Expected a value at the top level (this should never happen)")
+        }
+    }
+}
+
+/// Calls the closure `f` with `args`, and calls the closures it calls in tail position in a loop.
+pub fn call_closure(f: &Arc<Closure>, args: Vec<Atom>) -> Result<Atom> {
+    let _nesting = limits::nest()?;
+    let mut f = f.clone();
+    let mut args = args;
+    loop {
+        let _module = module::enter(f.module.clone());
+        let chunk = f
+            .code
+            .get_or_init(|| Arc::new(compile_function(&f)))
+            .clone();
+        let mut run = Run::new(&chunk, &f.env);
+        run.bind_args(&f, args)?;
+        (f, args) = match run.execute()? {
+            Exit::Value(value) => return Ok(value),
+            Exit::TailCall(g, args) => (g, args),
+        };
+    }
+}
+
+impl<'a> Run<'a> {
+    fn new(chunk: &'a Chunk, env: &'a Env) -> Self {
+        Self {
+            chunk,
+            env,
+            slots: vec![Atom::Nil; chunk.slots],
+            stack: Vec::new(),
+            frames: Vec::new(),
+            tries: Vec::new(),
+            caught: Vec::new(),
+        }
+    }
+
+    /// Binds the parameters of `f`, whose body is the code, to `args`.
+    fn bind_args(&mut self, f: &Arc<Closure>, mut args: Vec<Atom>) -> Result<()> {
+        let fixed = self.chunk.params.len();
+        let expected = match self.chunk.rest {
+            None => fixed..=fixed,
+            Some(_) => fixed..=usize::MAX,
+        };
+        MalError::check_arity(&args, expected)?;
+        if let Some(slot) = self.chunk.name {
+            self.slots[slot] = Atom::Closure(f.clone());
+        }
+        let rest = args.split_off(fixed);
+        for (&binding, arg) in self.chunk.params.iter().zip(args) {
+            self.bind(binding, arg)?;
+        }
+        if let Some(binding) = self.chunk.rest {
+            let rest = if rest.is_empty() {
+                Atom::Nil
+            } else {
+                Atom::List(rest)
+            };
+            self.bind(binding, rest)?;
+        }
+        Ok(())
+    }
+
+    /// Binds `value` with the binding at `index` in [`Chunk::bindings`].
+    fn bind(&mut self, index: usize, value: Atom) -> Result<()> {
+        match &self.chunk.bindings[index] {
+            Binding::Slot(slot) => self.slots[*slot] = value,
+            Binding::Destructure { form, slots, scope } => {
+                let scope = match scope {
+                    Some(scope) => self.scope(*scope).into_owned(),
+                    None => Env::new(),
+                };
+                let bound = destructure::bindings(form, value, &scope)?;
+                for (sym, slot) in slots {
+                    self.slots[*slot] = bound.get(sym).cloned().unwrap_or(Atom::Nil);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the environment with the locals of the scope at `index` in [`Chunk::scopes`].
+    fn scope(&self, index: usize) -> Cow<'a, Env> {
+        let locals = &self.chunk.scopes[index];
+        if locals.is_empty() {
+            return Cow::Borrowed(self.env);
+        }
+        let mut env = self.env.clone();
+        for (sym, slot) in locals {
+            env.insert(sym.clone(), self.slots[*slot].clone());
+        }
+        Cow::Owned(env)
+    }
+
+    fn execute(mut self) -> Result<Exit> {
+        let res = self.execute_code();
+        // the calls that were entered are still on the call stack, which is what the backtrace
+        // shows
+        if res.is_err() && !self.frames.is_empty() {
+            record_error();
+        }
+        res
+    }
+
+    fn execute_code(&mut self) -> Result<Exit> {
+        let mut res = self.execute_from(0);
+        while let Err(e) = res {
+            let Some(running) = self.tries.pop() else {
+                return Err(e);
+            };
+            res = self.catch(running, e).and_then(|pc| self.execute_from(pc));
+        }
+        res
+    }
+
+    /// Handles the error `e` with the handler of the `try*` that was `running`, and returns the
+    /// index in the code where the handler starts.
+    fn catch(&mut self, running: Try, e: color_eyre::Report) -> Result<usize> {
+        let Some(value) = caught_value(&e) else {
+            return Err(e);
+        };
+        if self.frames.len() > running.frames {
+            record_error();
+        }
+        self.frames.truncate(running.frames);
+        self.stack.truncate(running.stack);
+        self.end_catches(running.caught);
+        let frames = take_backtrace().unwrap_or_else(call_stack);
+        let handler = &self.chunk.handlers[running.handler];
+        self.slots[handler.stack] = stack_value(&frames);
+        self.bind(handler.binding, value)?;
+        self.caught.push(set_caught(frames));
+        Ok(handler.start)
+    }
+
+    /// Ends the handlers of errors, until `len` are left.
+    fn end_catches(&mut self, len: usize) {
+        // each handler restores the stack of the one before it
+        while self.caught.len() > len {
+            self.caught.pop();
+        }
+    }
+
+    fn execute_from(&mut self, mut pc: usize) -> Result<Exit> {
+        let chunk = self.chunk;
+        while let Some(op) = chunk.code.get(pc) {
+            pc += 1;
+            match *op {
+                Op::Step => step()?,
+                Op::Nil => self.stack.push(Atom::Nil),
+                Op::Const(index) => {
+                    step()?;
+                    self.stack.push(chunk.constants[index].clone());
+                }
+                Op::Global(index) => {
+                    step()?;
+                    let sym = match &chunk.constants[index] {
+                        Atom::Symbol(sym) => sym,
+                        a => panic!(
+                            "Expected a symbol, but got {} (this should never happen)",
+                            a
+                        ),
+                    };
+                    let value = self
+                        .env
+                        .get(sym)
+                        .cloned()
+                        .or_else(|| global(sym))
+                        .ok_or_else(|| MalError::UnboundSymbol(sym.clone()))?;
+                    self.stack.push(value);
+                }
+                Op::Local(slot) => {
+                    step()?;
+                    self.stack.push(self.slots[slot].clone());
+                }
+                Op::Collection => step()?,
+                Op::Vector(len) => {
+                    limits::allocate_collection(len)?;
+                    let items = self.stack.split_off(self.stack.len() - len);
+                    self.stack.push(Atom::Vector(items));
+                }
+                Op::HashMap(len) => {
+                    limits::allocate_collection(len)?;
+                    let mut map = BTreeMap::new();
+                    let mut items = self.stack.split_off(self.stack.len() - 2 * len).into_iter();
+                    while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                        map.insert(k, v);
+                    }
+                    self.stack.push(Atom::HashMap(map));
+                }
+                Op::Enter(index) => {
+                    step()?;
+                    self.frames.push(push_frame(&chunk.constants[index]));
+                    limits::check_depth()?;
+                }
+                Op::Call(argc, index) => {
+                    let value = self.call(argc, index)?;
+                    self.stack.push(value);
+                }
+                Op::TailCall(argc, index) => {
+                    let f = &self.stack[self.stack.len() - argc - 1];
+                    // traced and profiled calls have to return to be logged
+                    if matches!(f, Atom::Closure(_))
+                        && !profile::is_running()
+                        && !trace::is_traced(f)
+                    {
+                        let args = self.stack.split_off(self.stack.len() - argc);
+                        self.frames.pop();
+                        match self.stack.pop() {
+                            Some(Atom::Closure(f)) => return Ok(Exit::TailCall(f, args)),
+                            _ => panic!("Expected a closure (this should never happen)"),
+                        }
+                    }
+                    let value = self.call(argc, index)?;
+                    self.stack.push(value);
+                }
+                Op::Bind(index) => {
+                    let value = self.pop();
+                    self.bind(index, value)?;
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Jump(target) => pc = target,
+                Op::JumpIfFalse(target) => {
+                    if matches!(self.pop(), Atom::Nil | Atom::Bool(false)) {
+                        pc = target;
+                    }
+                }
+                Op::Closure(index) => {
+                    let function = &chunk.functions[index];
+                    let (params, rest) = destructure::params(&function.params)?;
+                    let mut captured = Env::new();
+                    capture(&function.params, self.env, &mut captured);
+                    capture(&function.body, self.env, &mut captured);
+                    for (sym, slot) in &chunk.scopes[function.captures] {
+                        captured.insert(sym.clone(), self.slots[*slot].clone());
+                    }
+                    let closure = Closure::new(params, rest, function.body.clone(), captured);
+                    self.stack.push(Atom::Closure(Arc::new(closure)));
+                }
+                Op::Recur(argc, index) => {
+                    let target = &chunk.loops[index];
+                    let values = self.stack.split_off(self.stack.len() - argc);
+                    let expected = target.bindings.len();
+                    MalError::check_arity(&values, expected..=expected)?;
+                    for (&binding, value) in target.bindings.iter().zip(values) {
+                        self.bind(binding, value)?;
+                    }
+                    pc = target.start;
+                }
+                Op::Walk(index, scope) => {
+                    let value = walk(&chunk.constants[index], &self.scope(scope))?;
+                    self.stack.push(value);
+                }
+                Op::Try(handler) => {
+                    // a backtrace from before is not of an error that this can catch
+                    clear_backtrace();
+                    self.tries.push(Try {
+                        handler,
+                        stack: self.stack.len(),
+                        frames: self.frames.len(),
+                        caught: self.caught.len(),
+                    });
+                }
+                Op::EndTry => {
+                    self.tries.pop();
+                }
+                Op::EndCatch => {
+                    self.caught.pop();
+                }
+            }
+        }
+        Ok(Exit::Value(self.pop()))
+    }
+
+    /// Calls the function under the top `argc` values with them, and pops the call stack.
+    fn call(&mut self, argc: usize, index: usize) -> Result<Atom> {
+        let head = head(self.chunk, index);
+        let args = self.stack.split_off(self.stack.len() - argc);
+        let f = self.pop();
+        let value = profile::call(head, || trace::apply_traced(head, &f, args))?;
+        self.frames.pop();
+        Ok(value)
+    }
+
+    fn pop(&mut self) -> Atom {
+        self.stack.pop().expect("the value should be on the stack")
+    }
+}
+
+/// Returns the first element of the call at `index` in [`Chunk::constants`].
+fn head(chunk: &Chunk, index: usize) -> &Atom {
+    match &chunk.constants[index] {
+        Atom::List(list) => &list[0],
+        a => panic!("Expected a list, but got {} (this should never happen)", a),
+    }
+}
+
+/// Counts an evaluation step, like the tree-walker does for each form.
+fn step() -> Result<()> {
+    EVAL_COUNT.fetch_add(1, Ordering::Relaxed);
+    interrupt::check()?;
+    limits::step()?;
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! Checks that bencode values are written and read back, and that malformed or oversized input is
+//! an error.
+
+use std::io::Cursor;
+
+use mal::bencode::{Bencode, MAX_DEPTH, MAX_LENGTH};
+
+fn read(bytes: &[u8]) -> color_eyre::Result<Option<Bencode>> {
+    Bencode::read(&mut Cursor::new(bytes))
+}
+
+fn write(value: &Bencode) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    value.write(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn values_round_trip() {
+    let message = Bencode::dict([
+        ("op", "eval".into()),
+        ("id", 42.into()),
+        ("status", vec!["done", "error"].into()),
+        ("empty", Bencode::dict([])),
+        ("negative", (-7).into()),
+        ("bytes", Bencode::Bytes(vec![0, 255, b':', b'e'])),
+    ]);
+    let bytes = write(&message);
+    assert_eq!(read(&bytes).unwrap(), Some(message));
+    assert_eq!(
+        write(&Bencode::dict([("b", 1.into()), ("a", "x".into())])),
+        b"d1:a1:x1:bi1ee"
+    );
+}
+
+#[test]
+fn messages_are_read_one_at_a_time() {
+    let mut input = Cursor::new(&b"i1e4:spaml1:ae"[..]);
+    assert_eq!(Bencode::read(&mut input).unwrap(), Some(1.into()));
+    assert_eq!(Bencode::read(&mut input).unwrap(), Some("spam".into()));
+    assert_eq!(Bencode::read(&mut input).unwrap(), Some(vec!["a"].into()));
+    assert_eq!(Bencode::read(&mut input).unwrap(), None);
+}
+
+#[test]
+fn malformed_input_is_an_error() {
+    for bytes in [
+        &b"i12"[..],
+        b"l1:a",
+        b"5:abc",
+        b"di1e1:ae",
+        b"x",
+        b"iabce",
+        b"123456789012345678901234567890:a",
+    ] {
+        assert!(read(bytes).is_err(), "{:?}", String::from_utf8_lossy(bytes));
+    }
+}
+
+#[test]
+fn limits_are_errors() {
+    let long = format!("{}:", MAX_LENGTH + 1);
+    assert!(read(long.as_bytes()).is_err());
+
+    let nested = |depth: usize| "l".repeat(depth) + &"e".repeat(depth);
+    assert!(read(nested(MAX_DEPTH).as_bytes()).unwrap().is_some());
+    assert!(read(nested(MAX_DEPTH + 1).as_bytes()).is_err());
+    assert!(read("d1:a".repeat(MAX_DEPTH + 1).as_bytes()).is_err());
+}
@@ -0,0 +1,117 @@
+//! Checks the `mal` command line: running the REPL, a script, an expression or standard input,
+//! `*ARGV*`, `#!` lines, `--help`, and the exit codes.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `mal` with `args` and `stdin`.
+fn mal(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mal"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Returns the exit code, standard output and standard error of `output`.
+fn result(output: Output) -> (Option<i32>, String, String) {
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+/// Returns a path in the temporary directory that is unique to this test and `name`.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mal-cli-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn repl_reads_standard_input() {
+    let (code, stdout, _) = result(mal(&[], "(+ 1 2)\n(def! x 4)\n(* x x)\n"));
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "3\n4\n16\n");
+}
+
+#[test]
+fn expressions_are_evaluated() {
+    assert_eq!(
+        result(mal(&["-e", "(+ 1 2)"], "")),
+        (Some(0), String::from("3\n"), String::new())
+    );
+    let (code, stdout, _) = result(mal(&["--eval", "(println *ARGV*) *ARGV*", "a", "b"], ""));
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "(a b)\n(\"a\" \"b\")\n");
+    let (code, stdout, _) = result(mal(&["--vm", "-e", "((fn* [x] (* x x)) 5)"], ""));
+    assert_eq!((code, stdout.as_str()), (Some(0), "25\n"));
+}
+
+#[test]
+fn scripts_are_run_with_their_arguments() {
+    let path = temp_path("script.mal");
+    std::fs::write(
+        &path,
+        "#!/usr/bin/env mal\n(println \"args:\" *ARGV*)\n(+ 1 2)\n",
+    )
+    .unwrap();
+    let res = result(mal(&[path.to_str().unwrap(), "x", "-e"], ""));
+    std::fs::remove_file(&path).unwrap();
+    // the value of the last form is not printed
+    assert_eq!(
+        res,
+        (Some(0), String::from("args: (x -e)\n"), String::new())
+    );
+
+    let (code, stdout, _) = result(mal(&["-", "y"], "#!mal\n(println *ARGV*)"));
+    assert_eq!((code, stdout.as_str()), (Some(0), "(y)\n"));
+}
+
+#[test]
+fn help_is_printed() {
+    for option in ["-h", "--help"] {
+        let (code, stdout, _) = result(mal(&[option], ""));
+        assert_eq!(code, Some(0));
+        assert!(stdout.starts_with("usage: mal [options]"), "{}", stdout);
+        assert!(stdout.contains("exit codes:"), "{}", stdout);
+    }
+}
+
+#[test]
+fn exit_codes() {
+    let (code, stdout, stderr) = result(mal(&["-e", "(println 1) (throw 2)"], ""));
+    assert_eq!((code, stdout.as_str()), (Some(1), "1\n"));
+    assert!(
+        stderr.starts_with("error: uncaught exception: 2"),
+        "{}",
+        stderr
+    );
+    let (code, _, stderr) = result(mal(&["-e", "(+ 1"], ""));
+    assert_eq!(code, Some(1));
+    assert!(stderr.starts_with("error: "), "{}", stderr);
+
+    let (code, _, stderr) = result(mal(&["--frobnicate"], ""));
+    assert_eq!(code, Some(64));
+    assert!(
+        stderr.starts_with("mal: unknown option --frobnicate"),
+        "{}",
+        stderr
+    );
+    assert_eq!(result(mal(&["-e"], "")).0, Some(64));
+    assert_eq!(result(mal(&["--nrepl-port", "x"], "")).0, Some(64));
+
+    let path = temp_path("missing.mal");
+    let (code, _, stderr) = result(mal(&[path.to_str().unwrap()], ""));
+    assert_eq!(code, Some(66));
+    assert!(stderr.starts_with("mal: could not read"), "{}", stderr);
+}
@@ -0,0 +1,70 @@
+//! Drives the debugger through the input of the program, and checks what it writes to the error
+//! output.
+
+mod common;
+
+use std::io::Cursor;
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::interpreter::Interpreter;
+
+use common::Output;
+
+/// Evaluates `source` with `commands` as the input, and returns the value and what the debugger
+/// wrote.
+fn debug(source: &str, commands: &str) -> (Result<Atom, MalError>, String) {
+    let errors = Output::default();
+    let mut mal = Interpreter::new();
+    mal.set_stdin(Cursor::new(commands.to_string()));
+    mal.set_stderr(errors.clone());
+    let res = mal
+        .eval_str(source)
+        .map_err(|e| e.downcast_ref::<MalError>().cloned().expect("a mal error"));
+    (res, errors.take())
+}
+
+#[test]
+fn steps_through_forms() {
+    let (res, log) = debug("(debug (+ 1 (* 2 3)))", "s\n\nn\nc\n");
+    assert_eq!(res, Ok(Atom::Integer(7)));
+    assert_eq!(
+        log,
+        "[2] (+ 1 (* 2 3))\ndebug> \
+         [3] +\ndebug> \
+         [3] 1\ndebug> \
+         [3] (* 2 3)\ndebug> "
+    );
+}
+
+#[test]
+fn inspects_the_environment() {
+    let (res, log) = debug(
+        "((fn* [x] (do (break) (+ x 1))) 2)",
+        "p (+ x 10)\np (+ x nil)\nenv\nbt\nfoo\nc\n",
+    );
+    assert_eq!(res, Ok(Atom::Integer(3)));
+    assert_eq!(
+        log,
+        "break\n[3] (break)\ndebug> \
+         12\ndebug> \
+         type error: expected integer but got nil, which is the wrong type\ndebug> \
+         x = 2\ndebug> \
+         mal backtrace:\n  0: ((fn* [x] (do (break) (+ x 1))) 2)\ndebug> \
+         unknown command foo, type help for a list of commands\ndebug> "
+    );
+}
+
+#[test]
+fn quit_aborts_the_evaluation() {
+    let (res, log) = debug("(debug (+ 1 2))", "q\n");
+    assert_eq!(res, Err(MalError::Interrupted));
+    assert_eq!(log, "[2] (+ 1 2)\ndebug> ");
+}
+
+#[test]
+fn runs_at_end_of_input() {
+    let (res, log) = debug("[(debug (+ 1 2)) (break)]", "");
+    assert_eq!(res, Ok(Atom::Vector(vec![Atom::Integer(3), Atom::Nil])));
+    assert_eq!(log, "[3] (+ 1 2)\ndebug> break\n[2] (break)\ndebug> ");
+}
@@ -0,0 +1,142 @@
+//! Checks binding forms in `let*`, `loop` and the parameters of `fn*`.
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::interpreter::Interpreter;
+use mal::reader::read_str;
+
+fn eval(source: &str) -> Atom {
+    Interpreter::new()
+        .eval_str(source)
+        .unwrap_or_else(|e| panic!("{}: {:#}", source, e))
+}
+
+fn read(source: &str) -> Atom {
+    read_str(source.to_string()).unwrap()
+}
+
+fn error(source: &str) -> String {
+    let e = Interpreter::new().eval_str(source).expect_err(source);
+    match e.downcast_ref::<MalError>() {
+        Some(e @ MalError::Destructure { .. }) => e.to_string(),
+        _ => panic!("{}: not a destructuring error: {:#}", source, e),
+    }
+}
+
+#[test]
+fn sequential() {
+    assert_eq!(
+        eval("(let* [[a b & rest :as all] [1 2 3 4]] [a b rest all])"),
+        read("[1 2 (3 4) [1 2 3 4]]")
+    );
+    assert_eq!(
+        eval("(let* [[a b & rest] '(1)] [a b rest])"),
+        read("[1 nil nil]")
+    );
+    assert_eq!(
+        eval("(let* [[a [b [c]]] [1 [2 [3]]]] [a b c])"),
+        read("[1 2 3]")
+    );
+    assert_eq!(eval("(let* [[a b] nil] [a b])"), read("[nil nil]"));
+}
+
+#[test]
+fn associative() {
+    assert_eq!(
+        eval("(let* [{:keys [x y] :or {y 0} :as m} {:x 1}] [x y m])"),
+        read("[1 0 {:x 1}]")
+    );
+    assert_eq!(
+        eval("(let* [{:strs [s] :syms [t]} {\"s\" 1 't 2}] [s t])"),
+        read("[1 2]")
+    );
+    assert_eq!(
+        eval("(let* [{[x y] :pos {:keys [name]} :meta} {:pos [1 2] :meta {:name \"a\"}}] [x y name])"),
+        read("[1 2 \"a\"]")
+    );
+    // a value that is there, even nil, is used instead of the default
+    assert_eq!(eval("(let* [{:keys [x] :or {x 1}} {:x nil}] x)"), Atom::Nil);
+    assert_eq!(
+        eval("(let* [n 5 {:keys [x] :or {x n}} nil] x)"),
+        Atom::Integer(5)
+    );
+}
+
+#[test]
+fn defaults_are_evaluated_before_the_binding_form() {
+    // y is bound before x, because symbols sort before keywords, but its default still sees the
+    // x from before the binding form
+    assert_eq!(
+        eval("(let* [x 1 {:keys [x] y :y :or {y x}} {:x 2}] [x y])"),
+        read("[2 1]")
+    );
+    let e = Interpreter::new()
+        .eval_str("(let* [{:keys [x] y :y :or {y x}} {:x 2}] y)")
+        .unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<MalError>(),
+        Some(&MalError::UnboundSymbol(String::from("x")))
+    );
+}
+
+#[test]
+fn function_parameters() {
+    assert_eq!(
+        eval("((fn* [[a b] {:keys [c]} & [d & more]] [a b c d more]) [1 2] {:c 3} 4 5 6)"),
+        read("[1 2 3 4 (5 6)]")
+    );
+    assert_eq!(eval("((fn* [& rest] rest))"), Atom::Nil);
+    assert_eq!(
+        eval("((fn* [n & [{:keys [step] :or {step 1}}]] (+ n step)) 1 {:step 2})"),
+        Atom::Integer(3)
+    );
+    // recur gives the rest parameter its value directly
+    assert_eq!(
+        eval("((fn* [n & xs] (if (= n 0) xs (recur (- n 1) [n xs]))) 2)"),
+        read("[1 [2 nil]]")
+    );
+}
+
+#[test]
+fn loop_bindings() {
+    assert_eq!(
+        eval("(loop [[x & xs] [1 2 3] acc 0] (if x (recur xs (+ acc x)) acc))"),
+        Atom::Integer(6)
+    );
+    assert_eq!(
+        eval("(loop [{:keys [n]} {:n 3} acc []] (if (= n 0) acc (recur {:n (- n 1)} [acc n])))"),
+        read("[[[[] 3] 2] 1]")
+    );
+}
+
+#[test]
+fn errors_name_the_binding() {
+    assert_eq!(
+        error("(let* [[a b] 5] a)"),
+        "can not bind [a b]: expected a sequence but got 5"
+    );
+    assert_eq!(
+        error("(let* [[x {:keys [y]}] [1 [2]]] y)"),
+        "can not bind {:keys [y]}: expected a map but got [2]"
+    );
+    assert_eq!(
+        error("(let* [[a &] [1]] a)"),
+        "can not bind [a &]: & needs a binding"
+    );
+    assert_eq!(
+        error("(let* [[& r x] [1]] x)"),
+        "can not bind [& r x]: only :as can follow & and its binding"
+    );
+    assert_eq!(
+        error("(let* [{:keys x} {}] x)"),
+        "can not bind {:keys x}: :keys needs a vector"
+    );
+    assert_eq!(
+        error("((fn* [a & r s] a) 1)"),
+        "can not bind [a & r s]: only one binding can follow &"
+    );
+    assert_eq!(
+        error("((fn* [[a]] a) 1)"),
+        "can not bind [a]: expected a sequence but got 1"
+    );
+}
@@ -0,0 +1,233 @@
+//! Checks `mal::edn` against the examples in the [EDN spec](https://github.com/edn-format/edn):
+//! what each one reads as, that writing it reads back the same value, and the errors.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use mal::atom::Atom;
+use mal::edn::{read_edn, write_edn};
+use mal::error::MalError;
+use num_bigint::BigInt;
+
+fn read(s: &str) -> Atom {
+    read_edn(s).unwrap_or_else(|e| panic!("{}: {:#}", s, e))
+}
+
+fn symbol(s: &str) -> Atom {
+    Atom::Symbol(s.to_string())
+}
+
+fn keyword(s: &str) -> Atom {
+    Atom::Keyword(s.to_string())
+}
+
+fn string(s: &str) -> Atom {
+    Atom::String(s.to_string())
+}
+
+/// Checks that `source` reads as `expected`, and that it is written so that it reads back the same.
+fn check(source: &str, expected: Atom) {
+    let value = read(source);
+    assert_eq!(value, expected, "{}", source);
+    let written = write_edn(&value).unwrap();
+    assert_eq!(
+        read(&written),
+        value,
+        "{} was written as {}",
+        source,
+        written
+    );
+}
+
+#[test]
+fn scalars() {
+    check("nil", Atom::Nil);
+    check("true", Atom::Bool(true));
+    check("false", Atom::Bool(false));
+    check(
+        r#""a \"quoted\" \\ string\n\t\r\u00e9""#,
+        string("a \"quoted\" \\ string\n\t\ré"),
+    );
+    check("\"multi\nline\"", string("multi\nline"));
+    check(r"\c", Atom::Char('c'));
+    check(r"\newline", Atom::Char('\n'));
+    check(r"\return", Atom::Char('\r'));
+    check(r"\space", Atom::Char(' '));
+    check(r"\tab", Atom::Char('\t'));
+    check(r"\u0041", Atom::Char('A'));
+    check(r"\\", Atom::Char('\\'));
+    check("42", Atom::Integer(42));
+    check("+42", Atom::Integer(42));
+    check("-0", Atom::Integer(0));
+    check("42N", Atom::Integer(42));
+    let big = BigInt::from(u64::MAX) * BigInt::from(10);
+    check(&format!("{}0", u64::MAX), Atom::from_bigint(big.clone()));
+    check(&format!("-{}0N", u64::MAX), Atom::from_bigint(-big));
+}
+
+#[test]
+fn symbols_and_keywords() {
+    for name in [
+        "foo",
+        "my-app/foo",
+        "+",
+        "-",
+        ".",
+        "/",
+        "*x*",
+        "a.b.c",
+        "-a",
+        "+a",
+        ".a",
+        "<=>",
+        "a#b",
+        "a:b",
+        "é",
+    ] {
+        check(name, symbol(name));
+    }
+    for name in ["fred", "my/fred", "a:b", "-", "-a"] {
+        check(&format!(":{}", name), keyword(name));
+    }
+}
+
+#[test]
+fn collections() {
+    check(
+        "(a b 42)",
+        Atom::List(vec![symbol("a"), symbol("b"), Atom::Integer(42)]),
+    );
+    check(
+        "[a, b,, 42]",
+        Atom::Vector(vec![symbol("a"), symbol("b"), Atom::Integer(42)]),
+    );
+    check(
+        "{:a 1, \"foo\" :bar, [1 2 3] four}",
+        Atom::HashMap(BTreeMap::from([
+            (keyword("a"), Atom::Integer(1)),
+            (string("foo"), keyword("bar")),
+            (
+                Atom::Vector(vec![Atom::Integer(1), Atom::Integer(2), Atom::Integer(3)]),
+                symbol("four"),
+            ),
+        ])),
+    );
+    check(
+        "#{a b [1 2 3]}",
+        Atom::Set(BTreeSet::from([
+            symbol("a"),
+            symbol("b"),
+            Atom::Vector(vec![Atom::Integer(1), Atom::Integer(2), Atom::Integer(3)]),
+        ])),
+    );
+    check("()", Atom::List(Vec::new()));
+    check("#{}", Atom::Set(BTreeSet::new()));
+    check("{}", Atom::HashMap(BTreeMap::new()));
+}
+
+#[test]
+fn tagged_values() {
+    check(
+        "#inst \"1985-04-12T23:20:50.52Z\"",
+        Atom::Tagged(
+            String::from("inst"),
+            Box::new(string("1985-04-12T23:20:50.52Z")),
+        ),
+    );
+    check(
+        "#uuid \"f81d4fae-7dec-11d0-a765-00a0c91e6bf6\"",
+        Atom::Tagged(
+            String::from("uuid"),
+            Box::new(string("f81d4fae-7dec-11d0-a765-00a0c91e6bf6")),
+        ),
+    );
+    check(
+        "#myapp/Person {:first \"Fred\" :last \"Mertz\"}",
+        Atom::Tagged(
+            String::from("myapp/Person"),
+            Box::new(read("{:first \"Fred\" :last \"Mertz\"}")),
+        ),
+    );
+}
+
+#[test]
+fn comments_and_discards() {
+    check(
+        "; a comment\n[a #_foo 42 #_ #_ 1 2 ; the end\n]",
+        Atom::Vector(vec![symbol("a"), Atom::Integer(42)]),
+    );
+    // only the first value is read
+    check("1 2", Atom::Integer(1));
+}
+
+#[test]
+fn invalid_input_is_an_error() {
+    for source in [
+        "",
+        "(1 2",
+        "[1 2)",
+        ")",
+        "{:a}",
+        "{:a 1 :a 2}",
+        "#{1 1}",
+        "\"unclosed",
+        "\"\\x\"",
+        r"\foo",
+        "1.5",
+        "1e3",
+        "1.5M",
+        "12abc",
+        ":",
+        "::a",
+        ":+1a",
+        ":/",
+        "a/b/c",
+        "#inst \"yesterday\"",
+        "#uuid 1",
+        "#1 2",
+        "#foo",
+        "#_",
+        "'a",
+        "@a",
+    ] {
+        let e = read_edn(source).expect_err(source);
+        assert!(
+            matches!(e.downcast_ref::<MalError>(), Some(MalError::Reader { .. })),
+            "{}: {:#}",
+            source,
+            e
+        );
+    }
+}
+
+#[test]
+fn values_that_edn_can_not_read_are_not_written() {
+    let tagged = |tag: &str, value: Atom| Atom::Tagged(tag.to_string(), Box::new(value));
+    for value in [
+        symbol(""),
+        symbol("nil"),
+        symbol("true"),
+        symbol("1a"),
+        symbol("a b"),
+        symbol("a/b/c"),
+        symbol("a/"),
+        symbol("'a"),
+        keyword(""),
+        keyword("/"),
+        keyword(":a"),
+        keyword("a b"),
+        Atom::List(vec![Atom::Integer(1), symbol("(")]),
+        tagged("1", Atom::Nil),
+        tagged("_", Atom::Nil),
+        tagged("inst", string("yesterday")),
+        tagged("uuid", Atom::Integer(1)),
+        Atom::Builtin(|_| Ok(Atom::Nil)),
+    ] {
+        let e = write_edn(&value).expect_err(&format!("{:?}", value));
+        assert!(
+            matches!(e.downcast_ref::<MalError>(), Some(MalError::Conversion(_))),
+            "{:?}: {:#}",
+            value,
+            e
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Checks the [`MalError`]s of reading and evaluating bad code with both engines: which variant
+//! it is, its message, and for reader errors where in the source it was found.
+
+use std::ops::Range;
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::reader::read_all;
+
+/// Reads `source`, and returns the error with its message and span.
+fn reader_error(source: &str) -> (String, Range<usize>) {
+    let e = read_all(source.to_string()).expect_err(source);
+    match e.downcast_ref::<MalError>() {
+        Some(MalError::Reader { message, span }) => (message.clone(), span.clone()),
+        _ => panic!("{}: {:#}", source, e),
+    }
+}
+
+/// Evaluates `source` with each engine, checks that both fail with the same error, and returns
+/// it.
+fn eval_error(source: &str) -> MalError {
+    let [walked, compiled] = [Engine::TreeWalker, Engine::Vm].map(|engine| {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        let e = mal.eval_str(source).expect_err(source);
+        e.downcast_ref::<MalError>()
+            .cloned()
+            .unwrap_or_else(|| panic!("{}: {:#}", source, e))
+    });
+    assert_eq!(walked, compiled, "{}", source);
+    walked
+}
+
+#[test]
+fn unbalanced_input() {
+    let missing = |end| {
+        format!(
+            "unexpected end of file while reading list (missing '{}')",
+            end
+        )
+    };
+    assert_eq!(reader_error("(+ 1"), (missing(')'), 4..4));
+    assert_eq!(reader_error("[1 (2)"), (missing(']'), 6..6));
+    assert_eq!(reader_error("{:a 1"), (missing('}'), 5..5));
+    assert_eq!(
+        reader_error("(+ 1 2))"),
+        (String::from("unexpected ')'"), 7..8)
+    );
+    assert_eq!(
+        reader_error("[1 2)"),
+        (String::from("unexpected ')'"), 4..5)
+    );
+    assert_eq!(reader_error("}"), (String::from("unexpected '}'"), 0..1));
+    assert_eq!(
+        MalError::reader("unexpected ')'", 7..8).to_string(),
+        "unexpected ')' (at position 7)"
+    );
+}
+
+#[test]
+fn other_reader_errors() {
+    assert_eq!(
+        reader_error("(a \"bc"),
+        (String::from("unclosed string"), 3..6)
+    );
+    assert_eq!(
+        reader_error("\"\\q\""),
+        (String::from("unsuported escape sequence"), 0..4)
+    );
+    assert_eq!(
+        reader_error("{:a}"),
+        (
+            String::from("invalid hashmap: got a key without a value"),
+            3..4
+        )
+    );
+    assert_eq!(
+        reader_error("'"),
+        (String::from("unexpected end of file"), 1..1)
+    );
+}
+
+#[test]
+fn unbound_symbol() {
+    let error = eval_error("(abc 1 2 3)");
+    assert_eq!(error, MalError::UnboundSymbol(String::from("abc")));
+    assert_eq!(error.to_string(), "symbol abc is not bound to any value");
+    assert_eq!(
+        eval_error("(let* [x 1] y)"),
+        MalError::UnboundSymbol(String::from("y"))
+    );
+}
+
+#[test]
+fn arity() {
+    let error = eval_error("((fn* [a] a))");
+    assert_eq!(
+        error,
+        MalError::Arity {
+            expected: 1..=1,
+            got: 0
+        }
+    );
+    assert_eq!(error.to_string(), "expected exactly 1 arguments, got 0");
+    let error = eval_error("((fn* [a & more] a))");
+    assert_eq!(
+        error,
+        MalError::Arity {
+            expected: 1..=usize::MAX,
+            got: 0
+        }
+    );
+    assert_eq!(error.to_string(), "expected at least 1 arguments, got 0");
+    assert_eq!(
+        MalError::Arity {
+            expected: 1..=2,
+            got: 3
+        }
+        .to_string(),
+        "expected between 1 and 2 arguments, got 3"
+    );
+}
+
+#[test]
+fn type_errors() {
+    let error = eval_error("(+ 1 \"a\")");
+    assert_eq!(
+        error,
+        MalError::type_error("integer", &Atom::String(String::from("a")))
+    );
+    assert_eq!(
+        error.to_string(),
+        "type error: expected integer but got \"a\", which is the wrong type"
+    );
+    assert_eq!(
+        eval_error("(1 2)"),
+        MalError::type_error("a function or builtin", &Atom::Integer(1))
+    );
+}
+
+#[test]
+fn errors_can_be_caught() {
+    let mut mal = Interpreter::new();
+    assert_eq!(
+        mal.eval_str("(try* (abc) (catch* e e))").unwrap(),
+        Atom::String(String::from("symbol abc is not bound to any value"))
+    );
+    assert_eq!(
+        mal.eval_str("(try* (throw [1]) (catch* e e))").unwrap(),
+        Atom::Vector(vec![Atom::Integer(1)])
+    );
+    let e = mal.eval_str("(throw :oops)").unwrap_err();
+    let error = e.downcast_ref::<MalError>().unwrap();
+    assert_eq!(
+        error,
+        &MalError::Thrown(Atom::Keyword(String::from("oops")))
+    );
+    assert_eq!(error.to_string(), "uncaught exception: :oops");
+}
@@ -0,0 +1,204 @@
+//! Checks `mal::cst` and `mal::format`: printing the syntax tree gives back the source, and
+//! formatting keeps the forms and comments of the source and does nothing the second time.
+
+use mal::cst::{parse, Node, NodeKind};
+use mal::format::format_source;
+use mal::reader::read_all;
+use proptest::prelude::*;
+
+fn format(source: &str) -> String {
+    format_source(source).unwrap_or_else(|e| panic!("{}: {:#}", source, e))
+}
+
+/// Returns the comments of `nodes`, in order.
+fn comments(nodes: &[Node]) -> Vec<String> {
+    let mut res = Vec::new();
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Comment(comment) => res.push(comment.trim_end().to_string()),
+            NodeKind::Seq { children, .. } => res.extend(comments(children)),
+            NodeKind::Prefix { trivia, form, .. } => {
+                res.extend(comments(trivia));
+                res.extend(comments(std::slice::from_ref(&**form)));
+            }
+            NodeKind::Whitespace(_) | NodeKind::Token(_) => {}
+        }
+    }
+    res
+}
+
+/// Checks what the formatter promises for `source`: the output is formatted, it reads as the
+/// same forms, and it has the same comments.
+fn check_format(source: &str) -> String {
+    let formatted = format(source);
+    assert_eq!(
+        format(&formatted),
+        formatted,
+        "not idempotent: {:?}",
+        source
+    );
+    assert_eq!(
+        read_all(formatted.clone()).unwrap(),
+        read_all(source.to_string()).unwrap(),
+        "forms changed: {:?}",
+        source
+    );
+    assert_eq!(
+        comments(&parse(&formatted).unwrap()),
+        comments(&parse(source).unwrap()),
+        "comments changed: {:?}",
+        source
+    );
+    formatted
+}
+
+#[test]
+fn lossless() {
+    let source =
+        " ;; header\n(def! f (fn* [a, b]\t; args\n  `(~a ~@ ; splice\n b)))\n\n\n'x @y ^{:a 1} z";
+    let nodes = parse(source).unwrap();
+    let printed: String = nodes.iter().map(|x| x.to_string()).collect();
+    assert_eq!(printed, source);
+    for node in &nodes {
+        assert_eq!(node.to_string(), source[node.span.clone()]);
+    }
+}
+
+#[test]
+fn indentation() {
+    let cases = [
+        // bodies of special forms and def forms are indented by two spaces
+        ("(if x\ny\nz)", "(if x\n  y\n  z)\n"),
+        ("(let* [a 1\nb 2]\na)", "(let* [a 1\n       b 2]\n  a)\n"),
+        ("(defn f [x]\nx)", "(defn f [x]\n  x)\n"),
+        (
+            "(try* (f)\n(catch* e\ne))",
+            "(try* (f)\n  (catch* e\n    e))\n",
+        ),
+        // calls are aligned with the first argument
+        ("(foo a\nb)", "(foo a\n     b)\n"),
+        ("(foo\na\nb)", "(foo\n a\n b)\n"),
+        // forms that are not special are not body forms
+        ("(cond a\nb)", "(cond a\n      b)\n"),
+        ("{:a 1\n:b 2}", "{:a 1\n :b 2}\n"),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(check_format(source), expected, "{:?}", source);
+    }
+}
+
+#[test]
+fn whitespace() {
+    let cases = [
+        ("(  a   b  )", "(a b)\n"),
+        ("a\n\n\n\nb   \n\n", "a\n\nb\n"),
+        ("[1,2 , 3]", "[1 2 3]\n"),
+        ("", ""),
+        ("\n  \n", ""),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(check_format(source), expected, "{:?}", source);
+    }
+}
+
+#[test]
+fn comments_are_kept() {
+    let cases = [
+        ("(a  ; first\n b)", "(a  ; first\n b)\n"),
+        ("(a ; last\n)", "(a ; last\n )\n"),
+        ("(do\n; own line\nx)", "(do\n  ; own line\n  x)\n"),
+        ("' ; quoted\nx", "'; quoted\n x\n"),
+        (";; end of file   ", ";; end of file\n"),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(check_format(source), expected, "{:?}", source);
+    }
+}
+
+#[test]
+fn errors() {
+    for source in ["(a", "a)", "(a]", "\"abc", "'", "(')"] {
+        assert!(parse(source).is_err(), "{:?}", source);
+        assert!(format_source(source).is_err(), "{:?}", source);
+    }
+}
+
+/// Whitespace and comments that can go between forms
+fn trivia() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        prop_oneof![
+            Just(" ".to_string()),
+            Just("  ".to_string()),
+            Just(",".to_string()),
+            Just("\n".to_string()),
+            Just("\n\n\n".to_string()),
+            Just("\t".to_string()),
+            "; [a-z ]{0,6}\n",
+        ],
+        0..3,
+    )
+    .prop_map(|parts| parts.concat())
+}
+
+/// A list or vector of forms from `form`, with random trivia in between
+fn seq(form: BoxedStrategy<String>, open: char, close: char) -> impl Strategy<Value = String> {
+    (prop::collection::vec((trivia(), form), 0..6), trivia()).prop_map(move |(items, end)| {
+        let items: String = items
+            .into_iter()
+            .map(|(trivia, form)| trivia + &form)
+            .collect();
+        format!("{}{}{}{}", open, items, end, close)
+    })
+}
+
+/// Sources made of lists, vectors, reader macros and tokens, with random trivia in between
+fn source() -> impl Strategy<Value = String> {
+    let token = prop_oneof![
+        Just("if".to_string()),
+        Just("do".to_string()),
+        Just("let*".to_string()),
+        Just("def!".to_string()),
+        "[a-z][a-z0-9-]{0,5}",
+        ":[a-z]{1,4}",
+        "-?[0-9]{1,4}",
+        "\"[a-z ;()]{0,5}\"",
+    ];
+    let form = token.prop_recursive(4, 48, 6, |inner| {
+        prop_oneof![
+            seq(inner.clone(), '(', ')'),
+            seq(inner.clone(), '[', ']'),
+            (
+                prop_oneof![Just("'"), Just("`"), Just("~"), Just("~@"), Just("@")],
+                inner
+            )
+                .prop_map(|(prefix, form)| format!("{}{}", prefix, form)),
+        ]
+    });
+    prop::collection::vec((trivia(), form), 0..4).prop_map(|items| {
+        items
+            .into_iter()
+            .map(|(trivia, form)| trivia + &form)
+            .collect()
+    })
+}
+
+proptest! {
+    #[test]
+    fn parse_then_print(s in source()) {
+        let printed: String = parse(&s).unwrap().iter().map(|x| x.to_string()).collect();
+        prop_assert_eq!(printed, s);
+    }
+
+    #[test]
+    fn parse_arbitrary_then_print(s in "[ -~\n]{0,64}") {
+        if let Ok(nodes) = parse(&s) {
+            let printed: String = nodes.iter().map(|x| x.to_string()).collect();
+            prop_assert_eq!(printed, s);
+        }
+    }
+
+    #[test]
+    fn format_keeps_forms_and_comments(s in source()) {
+        check_format(&s);
+    }
+}
@@ -0,0 +1,157 @@
+//! Checks `if`, `do`, `fn*`, `loop` and `recur`, and that calls in tail position don't use the
+//! stack.
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::reader::read_str;
+
+fn eval(source: &str) -> Atom {
+    Interpreter::new()
+        .eval_str(source)
+        .unwrap_or_else(|e| panic!("{}: {:#}", source, e))
+}
+
+fn read(source: &str) -> Atom {
+    read_str(source.to_string()).unwrap()
+}
+
+fn error(source: &str) -> MalError {
+    let e = Interpreter::new().eval_str(source).expect_err(source);
+    e.downcast_ref::<MalError>()
+        .unwrap_or_else(|| panic!("{}: not a mal error: {:#}", source, e))
+        .clone()
+}
+
+#[test]
+fn conditionals() {
+    assert_eq!(
+        eval("[(if nil 1 2) (if false 1 2) (if 0 1 2) (if () 1)]"),
+        read("[2 2 1 1]")
+    );
+    assert_eq!(eval("(if false 1)"), Atom::Nil);
+    assert_eq!(eval("(do)"), Atom::Nil);
+    assert_eq!(eval("(do 1 2 3)"), Atom::Integer(3));
+    assert_eq!(
+        eval("[(= [1 (quote (2))] (quote (1 [2]))) (= {:a [1]} {:a (quote (1))}) (= 1 2)]"),
+        read("[true true false]")
+    );
+    assert_eq!(
+        eval("[(< 1 2) (<= 2 2) (> 1 99999999999999999999) (>= 2 3)]"),
+        read("[true true false false]")
+    );
+}
+
+#[test]
+fn closures() {
+    assert_eq!(eval("((fn* [x y] (- x y)) 5 3)"), Atom::Integer(2));
+    assert_eq!(
+        eval("(let* [add (fn* [x] (fn* [y] (+ x y)))] ((add 3) 4))"),
+        Atom::Integer(7)
+    );
+    assert_eq!(eval("(let* [f (fn* [] 1)] (= f f))"), Atom::Bool(true));
+    assert_eq!(eval("(= (fn* [] 1) (fn* [] 1))"), Atom::Bool(false));
+    assert_eq!(
+        Interpreter::new()
+            .eval_str("(def! f (fn* [] 1))")
+            .unwrap()
+            .to_string(),
+        "#<FN f>"
+    );
+    assert_eq!(
+        error("((fn* [x] x))"),
+        MalError::Arity {
+            expected: 1..=1,
+            got: 0
+        }
+    );
+    assert_eq!(
+        error("((fn* [x & r] x))"),
+        MalError::Arity {
+            expected: 1..=usize::MAX,
+            got: 0
+        }
+    );
+}
+
+#[test]
+fn recursion() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(def! fib (fn* [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))")
+        .unwrap();
+    assert_eq!(
+        interpreter.eval_str("(fib 15)").unwrap(),
+        Atom::Integer(610)
+    );
+}
+
+#[test]
+fn functions_see_later_definitions() {
+    for engine in [Engine::TreeWalker, Engine::Vm] {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.eval_str("(def! f (fn* [] (g)))").unwrap();
+        mal.eval_str("(def! g (fn* [] 1))").unwrap();
+        assert_eq!(mal.eval_str("(f)").unwrap(), Atom::Integer(1));
+        mal.eval_str("(def! g (fn* [] 2))").unwrap();
+        assert_eq!(mal.eval_str("(f)").unwrap(), Atom::Integer(2));
+
+        mal.eval_str("(def! even? (fn* [n] (if (= n 0) true (odd? (- n 1)))))")
+            .unwrap();
+        mal.eval_str("(def! odd? (fn* [n] (if (= n 0) false (even? (- n 1)))))")
+            .unwrap();
+        assert_eq!(mal.eval_str("(even? 10000)").unwrap(), Atom::Bool(true));
+        assert_eq!(mal.eval_str("(odd? 7)").unwrap(), Atom::Bool(true));
+
+        // locals are still the ones from where the function was made
+        mal.eval_str("(let* [x 1] (def! h (fn* [] x)))").unwrap();
+        mal.eval_str("(def! x 2)").unwrap();
+        assert_eq!(mal.eval_str("(h)").unwrap(), Atom::Integer(1));
+        assert_eq!(
+            mal.eval_str("(let* [g (fn* [] 3)] (f))").unwrap(),
+            Atom::Integer(2)
+        );
+    }
+}
+
+#[test]
+fn tail_calls_do_not_grow_the_stack() {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .eval_str("(def! count-down (fn* [n] (if (= n 0) :done (do (count-down (- n 1))))))")
+        .unwrap();
+    assert_eq!(
+        interpreter.eval_str("(count-down 10000)").unwrap(),
+        read(":done")
+    );
+    assert_eq!(
+        eval("((fn* [n acc] (if (= n 0) acc (recur (- n 1) (+ acc n)))) 10000 0)"),
+        Atom::Integer(50005000)
+    );
+    assert_eq!(
+        eval("(loop [i 0] (if (< i 10000) (recur (+ i 1)) i))"),
+        Atom::Integer(10000)
+    );
+}
+
+#[test]
+fn recur_must_be_in_tail_position() {
+    assert_eq!(error("(recur 1)"), MalError::RecurNotInTailPosition);
+    assert_eq!(
+        error("(loop [i 0] (+ 1 (recur i)))"),
+        MalError::RecurNotInTailPosition
+    );
+    assert_eq!(
+        error("(loop [i 0] (if (recur i) 1 2))"),
+        MalError::RecurNotInTailPosition
+    );
+    assert_eq!(
+        error("(loop [i 0 j 0] (recur 1))"),
+        MalError::Arity {
+            expected: 2..=2,
+            got: 1
+        }
+    );
+}
@@ -0,0 +1,120 @@
+//! Checks the highlighting of source in the REPL: the colors of tokens, matching brackets and
+//! unbalanced brackets, and the colors of printed values.
+
+use mal::atom::Atom;
+use mal::highlight::{colorize, highlight_source};
+use mal::reader::read_str;
+
+const RESET: &str = "\x1b[0m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[36m";
+const KEYWORD: &str = "\x1b[35m";
+const CONSTANT: &str = "\x1b[34m";
+const COMMENT: &str = "\x1b[90m";
+const SPECIAL_FORM: &str = "\x1b[1;33m";
+const MATCHING_BRACKET: &str = "\x1b[1;4m";
+const UNBALANCED: &str = "\x1b[1;31m";
+
+fn colored(color: &str, s: &str) -> String {
+    format!("{}{}{}", color, s, RESET)
+}
+
+#[test]
+fn tokens_are_colored() {
+    assert_eq!(
+        highlight_source("(if nil :k \"s\" 12 1_000) ; done", 3),
+        format!(
+            "({} {} {} {} {} 1_000) {}",
+            colored(SPECIAL_FORM, "if"),
+            colored(CONSTANT, "nil"),
+            colored(KEYWORD, ":k"),
+            colored(STRING, "\"s\""),
+            colored(NUMBER, "12"),
+            colored(COMMENT, "; done"),
+        )
+    );
+}
+
+#[test]
+fn brackets_at_the_cursor_are_matched() {
+    let open = colored(MATCHING_BRACKET, "(");
+    let close = colored(MATCHING_BRACKET, ")");
+    let one = colored(NUMBER, "1");
+    let matched = format!("{}f {}{}", open, one, close);
+    // on the opening bracket, and right after the closing one
+    assert_eq!(highlight_source("(f 1)", 0), matched);
+    assert_eq!(highlight_source("(f 1)", 5), matched);
+    assert_eq!(highlight_source("(f 1)", 2), format!("(f {})", one));
+    // the bracket before the cursor comes first
+    assert_eq!(
+        highlight_source("(f [a])", 6),
+        format!(
+            "(f {}a{})",
+            colored(MATCHING_BRACKET, "["),
+            colored(MATCHING_BRACKET, "]")
+        )
+    );
+    assert_eq!(
+        highlight_source("(f [a])", 7),
+        format!("{}f [a]{}", open, close)
+    );
+    // brackets in strings and comments don't count
+    assert_eq!(
+        highlight_source("(\")\" ;)", 0),
+        format!("({} {}", colored(STRING, "\")\""), colored(COMMENT, ";)"))
+    );
+}
+
+#[test]
+fn unbalanced_brackets_are_red() {
+    assert_eq!(
+        highlight_source("(a))", 0),
+        format!(
+            "{}a{}{}",
+            colored(MATCHING_BRACKET, "("),
+            colored(MATCHING_BRACKET, ")"),
+            colored(UNBALANCED, ")")
+        )
+    );
+    assert_eq!(
+        highlight_source("[a)", 0),
+        format!("[a{}", colored(UNBALANCED, ")"))
+    );
+    // opening brackets may still be closed later in the form
+    assert_eq!(highlight_source("(a [b", 0), "(a [b");
+}
+
+#[test]
+fn values_are_colorized() {
+    let value = read_str(String::from(
+        "(1 :k \"s\" nil sym [true] {:a 100000000000000000000})",
+    ))
+    .unwrap();
+    assert_eq!(
+        colorize(&value),
+        format!(
+            "({} {} {} {} sym [{}] {{{} {}}})",
+            colored(NUMBER, "1"),
+            colored(KEYWORD, ":k"),
+            colored(STRING, "\"s\""),
+            colored(CONSTANT, "nil"),
+            colored(CONSTANT, "true"),
+            colored(KEYWORD, ":a"),
+            colored(NUMBER, "100000000000000000000"),
+        )
+    );
+    assert_eq!(
+        colorize(&Atom::Tagged(
+            String::from("inst"),
+            Box::new(Atom::from("2024"))
+        )),
+        format!(
+            "{} {}",
+            colored(CONSTANT, "#inst"),
+            colored(STRING, "\"2024\"")
+        )
+    );
+    let function = colorize(&Atom::Builtin(|_| Ok(Atom::Nil)));
+    assert!(function.starts_with(&format!("{}#<BUILTIN", COMMENT)));
+    assert!(function.ends_with(RESET));
+}
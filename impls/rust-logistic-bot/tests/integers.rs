@@ -0,0 +1,119 @@
+//! Checks that integers which don't fit in 64 bits are read and computed with exactly, with both
+//! engines, and the errors of dividing.
+
+use mal::atom::Atom;
+use mal::edn::read_edn;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::reader::read_str;
+use num_bigint::BigInt;
+
+/// Evaluates `source` with each engine, and returns the value, which should be the same.
+fn eval(source: &str) -> Atom {
+    let [walked, compiled] = [Engine::TreeWalker, Engine::Vm].map(|engine| {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.eval_str(source)
+            .unwrap_or_else(|e| panic!("{}: {:#}", source, e))
+    });
+    assert_eq!(walked, compiled, "{}", source);
+    walked
+}
+
+fn error(source: &str) -> MalError {
+    let e = Interpreter::new().eval_str(source).expect_err(source);
+    e.downcast_ref::<MalError>()
+        .unwrap_or_else(|| panic!("{}: not a mal error: {:#}", source, e))
+        .clone()
+}
+
+fn big(s: &str) -> Atom {
+    Atom::BigInteger(s.parse::<BigInt>().unwrap())
+}
+
+#[test]
+fn results_that_overflow_are_promoted() {
+    assert_eq!(
+        eval("(+ 9223372036854775807 1)"),
+        big("9223372036854775808")
+    );
+    assert_eq!(
+        eval("(- -9223372036854775808 1)"),
+        big("-9223372036854775809")
+    );
+    assert_eq!(
+        eval("(* 4294967296 4294967296)"),
+        big("18446744073709551616")
+    );
+    // i64::MIN / -1 is the one division that overflows
+    assert_eq!(
+        eval("(/ -9223372036854775808 -1)"),
+        big("9223372036854775808")
+    );
+}
+
+#[test]
+fn results_that_fit_are_demoted() {
+    assert_eq!(eval("(- 9223372036854775808 1)"), Atom::Integer(i64::MAX));
+    assert_eq!(
+        eval("(/ 18446744073709551616 4294967296)"),
+        Atom::Integer(4294967296)
+    );
+    assert_eq!(
+        eval("(% 18446744073709551617 4294967296)"),
+        Atom::Integer(1)
+    );
+    assert_eq!(
+        eval("(- 100000000000000000000 100000000000000000000)"),
+        Atom::Integer(0)
+    );
+}
+
+#[test]
+fn big_literals_are_read() {
+    for read in [
+        |s: &str| read_str(s.to_string()).unwrap(),
+        |s: &str| read_edn(s).unwrap(),
+    ] {
+        assert_eq!(
+            read("123456789012345678901234567890"),
+            big("123456789012345678901234567890")
+        );
+        assert_eq!(read("-9223372036854775809"), big("-9223372036854775809"));
+        assert_eq!(read("-9223372036854775808"), Atom::Integer(i64::MIN));
+        assert_eq!(read("+42"), Atom::Integer(42));
+    }
+    // only digits make an integer
+    assert_eq!(
+        read_str(String::from("1_000")).unwrap(),
+        Atom::Symbol(String::from("1_000"))
+    );
+    assert!(read_edn("1_000").is_err());
+    assert!(read_edn("100000000000000000000_0").is_err());
+}
+
+#[test]
+fn division_checks_the_arguments_first() {
+    assert_eq!(error("(/ 1 0)"), MalError::DivisionByZero);
+    assert_eq!(
+        error("(% 100000000000000000000 0)"),
+        MalError::DivisionByZero
+    );
+    for source in ["(/ 1 0 5)", "(% 1 0 5)"] {
+        assert_eq!(
+            error(source),
+            MalError::Arity {
+                expected: 2..=2,
+                got: 3
+            }
+        );
+    }
+    assert_eq!(
+        error("(/ 1)"),
+        MalError::Arity {
+            expected: 2..=2,
+            got: 1
+        }
+    );
+}
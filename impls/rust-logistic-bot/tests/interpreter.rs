@@ -0,0 +1,139 @@
+//! Checks the [`Interpreter`] API for applications that embed mal: evaluating source and files,
+//! defining values and builtins, getting values back, and its input and output, with both
+//! engines.
+
+mod common;
+
+use std::io::Cursor;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+
+use common::Output;
+
+fn interpreters() -> [Interpreter; 2] {
+    [Engine::TreeWalker, Engine::Vm].map(|engine| {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal
+    })
+}
+
+/// Returns a path in the temporary directory that is unique to this test and `name`.
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("mal-interpreter-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn definitions_are_kept_between_evaluations() {
+    for mut mal in interpreters() {
+        assert_eq!(
+            mal.eval_str("(def! square (fn* [x] (* x x)))\n(def! n 3)")
+                .unwrap(),
+            Atom::Integer(3)
+        );
+        assert_eq!(mal.eval_str("(square n)").unwrap(), Atom::Integer(9));
+        assert_eq!(mal.get("n"), Some(&Atom::Integer(3)));
+        assert_eq!(mal.get("m"), None);
+        // a failed evaluation keeps what was defined before it failed
+        assert!(mal.eval_str("(def! m 1) (undefined)").is_err());
+        assert_eq!(mal.get("m"), Some(&Atom::Integer(1)));
+    }
+    // each interpreter has its own environment
+    let [mut a, b] = interpreters();
+    a.eval_str("(def! only-a 1)").unwrap();
+    assert_eq!(b.get("only-a"), None);
+}
+
+#[test]
+fn values_are_defined() {
+    for mut mal in interpreters() {
+        mal.define("n", 2);
+        mal.define("name", "mal");
+        mal.define("enabled", true);
+        mal.define("list", vec![Atom::Integer(1), Atom::Nil]);
+        assert_eq!(
+            mal.eval_str("[n name enabled list]").unwrap(),
+            Atom::Vector(vec![
+                Atom::Integer(2),
+                Atom::from("mal"),
+                Atom::Bool(true),
+                Atom::List(vec![Atom::Integer(1), Atom::Nil]),
+            ])
+        );
+        // a definition replaces the one before it, also of a builtin
+        mal.define("+", 0);
+        assert_eq!(mal.eval_str("+").unwrap(), Atom::Integer(0));
+    }
+}
+
+#[test]
+fn builtins_can_capture_state() {
+    for mut mal in interpreters() {
+        let total = Arc::new(AtomicI64::new(0));
+        let counter = total.clone();
+        mal.register_builtin("add!", move |args| {
+            MalError::check_arity(&args, 1..=1)?;
+            let n = args[0].as_integer()?;
+            Ok(Atom::Integer(counter.fetch_add(n, Ordering::SeqCst) + n))
+        });
+        assert_eq!(mal.eval_str("(add! 2) (add! 3)").unwrap(), Atom::Integer(5));
+        assert_eq!(total.load(Ordering::SeqCst), 5);
+        // errors of builtins are mal errors, which can be caught
+        let e = mal.eval_str("(add! :a)").unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<MalError>(),
+            Some(&MalError::type_error(
+                "integer",
+                &Atom::Keyword(String::from("a"))
+            ))
+        );
+        assert_eq!(
+            mal.eval_str("(try* (add!) (catch* e :caught))").unwrap(),
+            Atom::Keyword(String::from("caught"))
+        );
+        // and they can be passed around like other functions
+        assert_eq!(
+            mal.eval_str("((fn* [f] (f 1)) add!)").unwrap(),
+            Atom::Integer(6)
+        );
+    }
+}
+
+#[test]
+fn files_are_evaluated() {
+    let path = temp_path("config.mal");
+    std::fs::write(&path, "#!/usr/bin/env mal\n(def! port 8080)\n(+ port 1)\n").unwrap();
+    for mut mal in interpreters() {
+        assert_eq!(mal.eval_file(&path).unwrap(), Atom::Integer(8081));
+        assert_eq!(mal.get("port"), Some(&Atom::Integer(8080)));
+    }
+    std::fs::remove_file(&path).unwrap();
+    let e = Interpreter::new().eval_file(&path).unwrap_err();
+    assert!(e.to_string().starts_with("could not read"), "{:#}", e);
+}
+
+#[test]
+fn input_and_output_are_pluggable() {
+    for mut mal in interpreters() {
+        let output = Output::default();
+        mal.set_stdout(output.clone());
+        mal.set_stdin(Cursor::new("ann\nbob\n"));
+        assert_eq!(
+            mal.eval_str("(println \"hello\" (readline \"name? \"))")
+                .unwrap(),
+            Atom::Nil
+        );
+        assert_eq!(output.take(), "name? hello ann\n");
+        // the input and output are kept between evaluations
+        assert_eq!(
+            mal.eval_str("[(readline \"\") (readline \"\")]").unwrap(),
+            Atom::Vector(vec![Atom::from("bob"), Atom::Nil])
+        );
+        assert_eq!(output.take(), "");
+    }
+}
@@ -0,0 +1,82 @@
+//! Checks that a running evaluation can be interrupted, from another thread or with Ctrl-C in
+//! the REPL, and that the environment survives it.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::interrupt::with_interrupt_flag;
+
+const FOREVER: &str = "(loop [n 0] (recur (+ n 1)))";
+
+#[test]
+fn evaluations_are_interrupted_by_the_flag() {
+    for engine in [Engine::TreeWalker, Engine::Vm] {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.eval_str("(def! x 5)").unwrap();
+        let flag = Arc::new(AtomicBool::new(false));
+        let setter = {
+            let flag = flag.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                flag.store(true, Ordering::Relaxed);
+            })
+        };
+        let e = with_interrupt_flag(flag.clone(), || mal.eval_str(FOREVER)).unwrap_err();
+        setter.join().unwrap();
+        assert_eq!(e.downcast_ref::<MalError>(), Some(&MalError::Interrupted));
+        // the flag is cleared, so the next evaluation runs, with the same environment
+        assert!(!flag.load(Ordering::Relaxed));
+        let res = with_interrupt_flag(flag, || mal.eval_str("(+ x 1)"));
+        assert_eq!(res.unwrap(), Atom::Integer(6));
+    }
+}
+
+#[test]
+fn interrupts_can_not_be_caught() {
+    let mut mal = Interpreter::new();
+    let flag = Arc::new(AtomicBool::new(true));
+    let e = with_interrupt_flag(flag, || {
+        mal.eval_str(&format!("(try* {} (catch* e :caught))", FOREVER))
+    })
+    .unwrap_err();
+    assert_eq!(e.downcast_ref::<MalError>(), Some(&MalError::Interrupted));
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_interrupts_the_form_but_not_the_repl() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mal"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    writeln!(stdin, "(def! x 5)\n(do (println \"started\") {})", FOREVER).unwrap();
+    let mut line = String::new();
+    for expected in ["5\n", "started\n"] {
+        line.clear();
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, expected);
+    }
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    writeln!(stdin, "x").unwrap();
+    drop(stdin);
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    assert!(child.wait().unwrap().success());
+    assert!(rest.contains("interrupted"), "{}", rest);
+    assert!(rest.ends_with("5\n"), "{}", rest);
+}
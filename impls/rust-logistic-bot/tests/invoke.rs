@@ -0,0 +1,96 @@
+//! Checks that keywords, maps and vectors can be called like functions, as in Clojure, and their
+//! errors, with both engines.
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::reader::read_str;
+
+/// Evaluates `source` with each engine, and returns the results, which should be the same.
+fn eval(source: &str) -> Result<Atom, MalError> {
+    let [walked, compiled] = [Engine::TreeWalker, Engine::Vm].map(|engine| {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.eval_str(source).map_err(|e| {
+            e.downcast_ref::<MalError>()
+                .cloned()
+                .unwrap_or_else(|| panic!("{}: not a mal error: {:#}", source, e))
+        })
+    });
+    assert_eq!(walked, compiled, "{}", source);
+    walked
+}
+
+fn read(source: &str) -> Atom {
+    read_str(source.to_string()).unwrap()
+}
+
+fn arity(expected: std::ops::RangeInclusive<usize>, got: usize) -> MalError {
+    MalError::Arity { expected, got }
+}
+
+#[test]
+fn keywords_look_up_themselves() {
+    assert_eq!(eval("(:name {:name \"ann\"})"), Ok(read("\"ann\"")));
+    assert_eq!(eval("(:age {:name \"ann\"})"), Ok(Atom::Nil));
+    assert_eq!(eval("(:age {:name \"ann\"} 30)"), Ok(Atom::Integer(30)));
+    // anything that is not a map has no keys
+    assert_eq!(eval("(:a [1 2] 0)"), Ok(Atom::Integer(0)));
+    assert_eq!(eval("(:a nil)"), Ok(Atom::Nil));
+}
+
+#[test]
+fn maps_look_up_keys() {
+    assert_eq!(eval("({:a 1 \"b\" 2} \"b\")"), Ok(Atom::Integer(2)));
+    assert_eq!(eval("({:a 1} :c)"), Ok(Atom::Nil));
+    assert_eq!(eval("({:a 1} :c 3)"), Ok(Atom::Integer(3)));
+    assert_eq!(eval("({[1] :v} [1])"), Ok(read(":v")));
+}
+
+#[test]
+fn vectors_are_indexed() {
+    assert_eq!(eval("([10 20 30] 1)"), Ok(Atom::Integer(20)));
+    assert_eq!(eval("([10 20 30] (+ 1 1))"), Ok(Atom::Integer(30)));
+    assert_eq!(
+        eval("([10 20 30] 3)"),
+        Err(MalError::IndexOutOfRange { index: 3, len: 3 })
+    );
+    assert_eq!(
+        eval("([] -1)"),
+        Err(MalError::IndexOutOfRange { index: -1, len: 0 })
+    );
+    assert_eq!(
+        eval("([10] :a)"),
+        Err(MalError::type_error("integer", &read(":a")))
+    );
+}
+
+#[test]
+fn they_are_called_like_functions() {
+    // from a binding, as an argument, and in tail position
+    assert_eq!(
+        eval("(let* [user {:name \"ann\"} f :name] (f user))"),
+        Ok(read("\"ann\""))
+    );
+    assert_eq!(eval("((fn* [f x] (f x)) [7 8] 1)"), Ok(Atom::Integer(8)));
+    assert_eq!(
+        eval("(def! get-a (fn* [m] (:a m)))\n(get-a {:a 5})"),
+        Ok(Atom::Integer(5))
+    );
+}
+
+#[test]
+fn arity_errors_are_those_of_builtins() {
+    assert_eq!(eval("(:a)"), Err(arity(1..=2, 0)));
+    assert_eq!(eval("(:a {} 1 2)"), Err(arity(1..=2, 3)));
+    assert_eq!(eval("({:a 1})"), Err(arity(1..=2, 0)));
+    assert_eq!(eval("([1] 0 0)"), Err(arity(1..=1, 2)));
+    assert_eq!(
+        eval("(\"s\" 1)"),
+        Err(MalError::type_error(
+            "a function or builtin",
+            &read("\"s\"")
+        ))
+    );
+}
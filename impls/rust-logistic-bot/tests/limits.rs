@@ -0,0 +1,224 @@
+//! Checks that deeply nested forms fail instead of overflowing the stack, that values are counted
+//! against the allocation budget where they are built, that a sandbox rejects the special forms
+//! that do IO, and that the `with_*` functions restore the thread's state even if they panic.
+
+mod common;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use mal::atom::Atom;
+use mal::env::{default_env, sandbox_env};
+use mal::error::{Limit, MalError};
+use mal::eval::{engine, eval, with_engine, Engine, IO_SPECIAL_FORMS, SPECIAL_FORMS};
+use mal::interpreter::Interpreter;
+use mal::interrupt::with_interrupt_flag;
+use mal::io::{set_output, with_output};
+use mal::limits::{with_limits, Limits, MAX_NESTING, STACK_SIZE};
+use mal::reader::read_str;
+
+use common::Output;
+
+/// Evaluates `source` with `engine` on a thread with the stack that mal needs, and returns the
+/// error, if any.
+fn error_with_stack(source: String, engine: Engine) -> Option<MalError> {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut mal = Interpreter::new();
+            mal.set_engine(engine);
+            mal.eval_str(&source)
+                .err()
+                .map(|e| e.downcast_ref::<MalError>().cloned().expect("a mal error"))
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+fn nested_vectors(n: usize) -> String {
+    "[".repeat(n) + &"]".repeat(n)
+}
+
+#[test]
+fn deep_nesting_fails() {
+    let depth = Some(MalError::LimitExceeded(Limit::Depth));
+    for engine in [Engine::TreeWalker, Engine::Vm] {
+        assert_eq!(
+            error_with_stack(nested_vectors(MAX_NESTING / 2), engine),
+            None
+        );
+        assert_eq!(
+            error_with_stack(nested_vectors(MAX_NESTING + 1), engine),
+            depth
+        );
+        let calls = "(+ 1 ".repeat(MAX_NESTING) + "1" + &")".repeat(MAX_NESTING);
+        assert_eq!(error_with_stack(calls, engine), depth);
+        let recursion = "(def! f (fn* [n] (if (= n 0) 0 (+ 1 (f (- n 1))))))\n(f 100000)";
+        assert_eq!(error_with_stack(recursion.to_string(), engine), depth);
+        let edn = format!("(edn/read-string \"{}\")", "[".repeat(MAX_NESTING + 1));
+        assert_eq!(error_with_stack(edn, engine), depth);
+    }
+}
+
+#[test]
+fn nesting_is_counted_again_after_an_error() {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            let mut mal = Interpreter::new();
+            for _ in 0..3 {
+                let e = read_str(nested_vectors(MAX_NESTING + 1)).unwrap_err();
+                assert_eq!(
+                    e.downcast_ref::<MalError>(),
+                    Some(&MalError::LimitExceeded(Limit::Depth))
+                );
+                assert!(mal.eval_str("(loop [i 0] (+ 1 (recur i)))").is_err());
+                mal.eval_str(&nested_vectors(MAX_NESTING)).unwrap();
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn allocation_is_counted_where_values_are_built() {
+    let limits = Limits {
+        max_allocation: Some(100_000),
+        ..Limits::default()
+    };
+    // the vectors are only returned from calls in tail position, and by recur
+    let sources = [
+        "(def! grow (fn* [n acc] (if (= n 0) acc (grow (- n 1) [acc n]))))\n(grow 100000 [])",
+        "(loop [n 100000 acc []] (if (= n 0) acc (recur (- n 1) {:acc acc})))",
+        "((fn* [n] (if (= n 0) nil (do (edn/read-string \"[1 2 3]\") (recur (- n 1))))) 100000)",
+    ];
+    for engine in [Engine::TreeWalker, Engine::Vm] {
+        for source in sources {
+            let mut mal = Interpreter::new();
+            mal.set_engine(engine);
+            mal.set_limits(limits.clone());
+            let e = mal.eval_str(source).expect_err(source);
+            assert_eq!(
+                e.downcast_ref::<MalError>(),
+                Some(&MalError::LimitExceeded(Limit::Allocation)),
+                "{}",
+                source
+            );
+        }
+        // values are not counted again when they are returned
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.set_limits(limits.clone());
+        mal.eval_str("(def! v (edn/read-string \"[1 2 3 4 5 6 7 8 9 10]\"))")
+            .unwrap();
+        mal.eval_str("(def! f (fn* [x] x))").unwrap();
+        let source = "(loop [n 500] (if (= n 0) (f v) (do (f v) (recur (- n 1)))))";
+        assert!(mal.eval_str(source).is_ok(), "{}", source);
+    }
+}
+
+#[test]
+fn sandbox_rejects_io_special_forms() {
+    let sources = [
+        ("debug", "(debug (+ 1 2))"),
+        ("break", "(do (break) 1)"),
+        ("profile", "(profile (+ 1 2) \"profile.folded\")"),
+        ("require", "(require foo)"),
+    ];
+    assert_eq!(
+        sources.map(|(form, _)| form).as_slice(),
+        IO_SPECIAL_FORMS,
+        "every special form that does IO is checked"
+    );
+    assert!(IO_SPECIAL_FORMS.iter().all(|x| SPECIAL_FORMS.contains(x)));
+    for engine in [Engine::TreeWalker, Engine::Vm] {
+        for (form, source) in sources {
+            let mut mal = Interpreter::with_env(sandbox_env());
+            mal.set_engine(engine);
+            mal.set_limits(Limits {
+                sandbox: true,
+                ..Limits::default()
+            });
+            let e = mal.eval_str(source).unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<MalError>(),
+                Some(&MalError::NotAllowed(form.to_string())),
+                "{}",
+                source
+            );
+            // in a function, the VM leaves the form to the tree-walker
+            let e = mal.eval_str(&format!("((fn* [] {}))", source)).unwrap_err();
+            assert_eq!(
+                e.downcast_ref::<MalError>(),
+                Some(&MalError::NotAllowed(form.to_string())),
+                "{}",
+                source
+            );
+        }
+    }
+}
+
+#[test]
+fn sandbox_allows_the_other_special_forms() {
+    let mut mal = Interpreter::with_env(sandbox_env());
+    mal.set_limits(Limits {
+        sandbox: true,
+        ..Limits::default()
+    });
+    mal.eval_str("(def! x (let* [y 1] (if y (do y) 2)))")
+        .unwrap();
+    assert_eq!(
+        mal.eval_str("(try* (throw x) (catch* e e))").unwrap(),
+        Atom::Integer(1)
+    );
+}
+
+#[test]
+fn with_functions_restore_after_a_panic() {
+    let env = default_env();
+    let add = read_str(String::from("(+ 1 2)")).unwrap();
+
+    let res = catch_unwind(|| with_engine(Engine::Vm, || panic!("in the engine")));
+    assert!(res.is_err());
+    assert_eq!(engine(), Engine::TreeWalker);
+
+    let limits = Limits {
+        fuel: Some(0),
+        ..Limits::default()
+    };
+    let res = catch_unwind(|| with_limits(limits, || panic!("with limits")));
+    assert!(res.is_err());
+    assert_eq!(eval(&add, &env).unwrap(), Atom::Integer(3));
+
+    let flag = Arc::new(AtomicBool::new(false));
+    let res = catch_unwind(|| with_interrupt_flag(flag.clone(), || panic!("with a flag")));
+    assert!(res.is_err());
+    flag.store(true, std::sync::atomic::Ordering::Relaxed);
+    assert_eq!(eval(&add, &env).unwrap(), Atom::Integer(3));
+
+    let output = Output::default();
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        with_output(Box::new(output.clone()), || panic!("with output"))
+    }));
+    assert!(res.is_err());
+    assert!(set_output(None).is_none(), "the output is stdout again");
+}
+
+#[test]
+fn interpreters_without_output_use_the_redirected_output() {
+    let output = Output::default();
+    let value = with_output(Box::new(output.clone()), || {
+        Interpreter::new().eval_str("(println 1)").unwrap();
+        let mut mal = Interpreter::new();
+        let own = Output::default();
+        mal.set_stdout(own.clone());
+        mal.eval_str("(println 2)").unwrap();
+        assert_eq!(own.take(), "2\n");
+        Interpreter::new().eval_str("(println 3)")
+    });
+    assert_eq!(value.unwrap(), Atom::Nil);
+    assert_eq!(output.take(), "1\n3\n");
+}
@@ -0,0 +1,161 @@
+//! Scripts sessions with the `mal-lsp` binary over stdio.
+
+use std::io::{BufReader, Write};
+use std::process::{Command, Stdio};
+
+use mal::lsp::{read_message, write_message};
+use serde_json::{json, Value};
+
+#[test]
+fn scripted_session() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mal-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut request = |message: Value| {
+        write_message(&mut stdin, &message).unwrap();
+        read_message(&mut stdout).unwrap().unwrap()
+    };
+    let uri = "file:///test.mal";
+    let document = |position: (u64, u64)| {
+        json!({
+            "textDocument": {"uri": uri},
+            "position": {"line": position.0, "character": position.1},
+        })
+    };
+
+    let reply = request(json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}));
+    assert_eq!(reply["result"]["capabilities"]["hoverProvider"], true);
+
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": uri, "text": "(def! x (+ 1"}},
+    }));
+    assert_eq!(reply["method"], "textDocument/publishDiagnostics");
+    assert_eq!(reply["params"]["diagnostics"].as_array().unwrap().len(), 1);
+
+    let text = "(def! inc (fn* [n] (+ n 1)))\n(inc x)\n(un";
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {"textDocument": {"uri": uri}, "contentChanges": [{"text": "(def! x 1)"}]},
+    }));
+    assert_eq!(reply["params"]["diagnostics"], json!([]));
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {"textDocument": {"uri": uri}, "contentChanges": [{"text": text}]},
+    }));
+    assert_eq!(reply["params"]["diagnostics"].as_array().unwrap().len(), 1);
+
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/documentSymbol",
+        "params": document((0, 0)),
+    }));
+    // the document can not be parsed, so there are no symbols
+    assert_eq!(reply["result"], json!([]));
+
+    let text = "(def! inc (fn* [n] (+ n 1)))\n(inc x)\n(un)";
+    request(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {"textDocument": {"uri": uri}, "contentChanges": [{"text": text}]},
+    }));
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "textDocument/documentSymbol",
+        "params": document((0, 0)),
+    }));
+    assert_eq!(reply["result"][0]["name"], "inc");
+    assert_eq!(reply["result"][0]["kind"], 12);
+
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "id": 4,
+        "method": "textDocument/definition",
+        "params": document((1, 2)),
+    }));
+    assert_eq!(
+        reply["result"]["range"]["start"],
+        json!({"line": 0, "character": 6})
+    );
+
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "id": 5,
+        "method": "textDocument/definition",
+        "params": document((0, 20)),
+    }));
+    // builtins are not defined in mal
+    assert_eq!(reply["result"], Value::Null);
+
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "id": 6,
+        "method": "textDocument/hover",
+        "params": document((0, 20)),
+    }));
+    assert!(reply["result"]["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .contains("(+ a b)"));
+
+    let reply = request(json!({
+        "jsonrpc": "2.0",
+        "id": 7,
+        "method": "textDocument/completion",
+        "params": document((2, 3)),
+    }));
+    assert_eq!(
+        reply["result"],
+        json!([{"label": "untrace", "kind": 3, "detail": "(untrace 'f) (untrace)"}])
+    );
+
+    let reply = request(json!({"jsonrpc": "2.0", "id": 8, "method": "shutdown"}));
+    assert_eq!(reply["result"], Value::Null);
+    write_message(&mut stdin, &json!({"jsonrpc": "2.0", "method": "exit"})).unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn malformed_messages_get_errors() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mal-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    stdin.write_all(b"Content-Length: 5\r\n\r\n{bad}").unwrap();
+    let reply = read_message(&mut stdout).unwrap().unwrap();
+    assert_eq!(reply["error"]["code"], -32700);
+    assert_eq!(reply["id"], Value::Null);
+    stdin.write_all(b"Content-Type: x\r\n\r\n").unwrap();
+    let reply = read_message(&mut stdout).unwrap().unwrap();
+    assert_eq!(reply["error"]["code"], -32700);
+
+    // responses from the client get no reply, so the next reply is to the next request
+    write_message(
+        &mut stdin,
+        &json!({"jsonrpc": "2.0", "id": 1, "result": null}),
+    )
+    .unwrap();
+    write_message(
+        &mut stdin,
+        &json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+    )
+    .unwrap();
+    let reply = read_message(&mut stdout).unwrap().unwrap();
+    assert_eq!(reply["id"], 2);
+
+    write_message(&mut stdin, &json!({"jsonrpc": "2.0", "method": "exit"})).unwrap();
+    assert!(child.wait().unwrap().success());
+}
@@ -0,0 +1,136 @@
+//! Checks requiring modules from `tests/modules`.
+
+mod common;
+
+use std::path::PathBuf;
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::interpreter::Interpreter;
+
+use common::Output;
+
+/// Creates an interpreter that loads modules from `tests/modules`, and the output it writes.
+fn interpreter() -> (Interpreter, Output) {
+    let output = Output::default();
+    let mut mal = Interpreter::new();
+    mal.set_load_path(vec![PathBuf::from("tests/modules")]);
+    mal.set_stdout(output.clone());
+    (mal, output)
+}
+
+fn mal_error(res: color_eyre::Result<Atom>) -> MalError {
+    let e = res.expect_err("should fail");
+    e.downcast_ref::<MalError>()
+        .unwrap_or_else(|| panic!("not a MalError: {:#}", e))
+        .clone()
+}
+
+#[test]
+fn qualified_names() {
+    let (mut mal, _) = interpreter();
+    mal.eval_str("(require 'geometry.shapes :as s)").unwrap();
+    assert_eq!(mal.eval_str("s/square").unwrap(), Atom::Integer(4));
+    assert_eq!(
+        mal.eval_str("geometry.shapes/square").unwrap(),
+        Atom::Integer(4)
+    );
+    assert!(mal.get("square").is_none());
+    assert!(mal.get("*ns*").is_none());
+}
+
+#[test]
+fn modules_are_loaded_once() {
+    let (mut mal, output) = interpreter();
+    mal.eval_str("(require 'geometry.shapes)").unwrap();
+    mal.eval_str("(require 'geometry.table :as t)").unwrap();
+    assert_eq!(mal.eval_str("t/legs").unwrap(), Atom::Integer(4));
+    assert_eq!(output.take(), "loading geometry.shapes\n");
+    // definitions that a module got from requiring another one are not its own
+    assert!(mal.get("t/shapes/square").is_none());
+
+    mal.eval_str("(require 'geometry.shapes)").unwrap();
+    assert_eq!(output.take(), "");
+    mal.eval_str("(require 'geometry.shapes :reload)").unwrap();
+    assert_eq!(output.take(), "loading geometry.shapes\n");
+
+    // each interpreter loads the modules it requires
+    let (mut mal, output) = interpreter();
+    mal.eval_str("(require 'geometry.shapes)").unwrap();
+    assert_eq!(output.take(), "loading geometry.shapes\n");
+}
+
+#[test]
+fn modules_are_loaded_again_from_a_new_load_path() {
+    let dir = std::env::temp_dir().join(format!("mal-modules-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("geometry")).unwrap();
+    std::fs::write(
+        dir.join("geometry/shapes.mal"),
+        "(ns geometry.shapes)\n(println \"loading other shapes\")\n(def! square 5)\n",
+    )
+    .unwrap();
+
+    let (mut mal, output) = interpreter();
+    mal.eval_str("(require 'geometry.shapes)").unwrap();
+    mal.set_load_path(vec![dir.clone()]);
+    mal.eval_str("(require 'geometry.shapes)").unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        output.take(),
+        "loading geometry.shapes\nloading other shapes\n"
+    );
+    assert_eq!(
+        mal.eval_str("geometry.shapes/square").unwrap(),
+        Atom::Integer(5)
+    );
+}
+
+#[test]
+fn errors() {
+    let (mut mal, _) = interpreter();
+    let cycle = MalError::CyclicRequire(vec!["cycle.a".into(), "cycle.b".into(), "cycle.a".into()]);
+    assert_eq!(mal_error(mal.eval_str("(require 'cycle.a)")), cycle);
+    // the modules that failed to load are not still loading
+    assert_eq!(mal_error(mal.eval_str("(require 'cycle.a)")), cycle);
+    assert_eq!(
+        mal_error(mal.eval_str("(require 'nowhere)")),
+        MalError::ModuleNotFound("nowhere".into())
+    );
+    assert_eq!(
+        mal_error(mal.eval_str("[(require 'geometry.shapes)]")),
+        MalError::TopLevelOnly("require".into())
+    );
+    let e = mal.eval_str("(require 'misnamed)").unwrap_err();
+    assert!(e.to_string().contains("declares namespace something.else"));
+}
+
+#[test]
+fn names_stay_in_the_load_path() {
+    let (mut mal, _) = interpreter();
+    for name in [
+        ".etc.passwd",
+        "geometry..shapes",
+        "geometry.",
+        "../modules/cycle.a",
+    ] {
+        let e = mal
+            .eval_str(&format!("(require '{})", name))
+            .expect_err(name);
+        assert_eq!(e.to_string(), format!("invalid module name {}", name));
+    }
+}
+
+#[test]
+fn def_inside_of_forms() {
+    let (mut mal, _) = interpreter();
+    assert_eq!(mal.eval_str("(do (def! a 1) a)").unwrap(), Atom::Integer(1));
+    assert_eq!(
+        mal.eval_str("(let* [x 2] (def! b x))").unwrap(),
+        Atom::Integer(2)
+    );
+    assert_eq!(mal.get("b"), Some(&Atom::Integer(2)));
+    assert_eq!(
+        mal_error(mal.eval_str("(do (ns other))")),
+        MalError::TopLevelOnly("ns".into())
+    );
+}
@@ -0,0 +1,257 @@
+//! Drives an nREPL server over TCP, and checks the replies to each op.
+
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use mal::bencode::Bencode;
+use mal::env::default_env;
+use mal::nrepl::serve_listener;
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: i64,
+    /// Replies that were read while waiting for the replies to another request
+    pending: Vec<Bencode>,
+}
+
+impl Client {
+    /// Starts a server on a free port, and connects to it.
+    fn connect() -> Self {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve_listener(listener, default_env()));
+        let stream = TcpStream::connect(address).unwrap();
+        Self {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            next_id: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Sends a request, and returns its id.
+    fn send(&mut self, entries: &[(&str, &str)]) -> Bencode {
+        self.next_id += 1;
+        let id = Bencode::from(self.next_id);
+        let mut message = Bencode::dict(entries.iter().map(|(k, v)| (*k, (*v).into())));
+        if let Bencode::Dict(dict) = &mut message {
+            dict.insert(b"id".to_vec(), id.clone());
+        }
+        let mut bytes = Vec::new();
+        message.write(&mut bytes).unwrap();
+        self.writer.write_all(&bytes).unwrap();
+        id
+    }
+
+    /// Returns the replies to the request `id` up to one with `status`. Replies to other requests
+    /// are kept for later.
+    fn receive(&mut self, id: &Bencode, status: &str) -> Vec<Bencode> {
+        let mut replies = Vec::new();
+        loop {
+            let reply = match self.pending.iter().position(|x| x.get("id") == Some(id)) {
+                Some(i) => self.pending.remove(i),
+                None => {
+                    let reply = Bencode::read(&mut self.reader).unwrap().expect("a reply");
+                    if reply.get("id") != Some(id) {
+                        self.pending.push(reply);
+                        continue;
+                    }
+                    reply
+                }
+            };
+            let last = statuses(&reply).contains(&status.to_string());
+            replies.push(reply);
+            if last {
+                return replies;
+            }
+        }
+    }
+
+    /// Sends a request, and returns all the replies to it.
+    fn request(&mut self, entries: &[(&str, &str)]) -> Vec<Bencode> {
+        let id = self.send(entries);
+        self.receive(&id, "done")
+    }
+
+    fn clone_session(&mut self) -> String {
+        let replies = self.request(&[("op", "clone")]);
+        strings(&replies, "new-session").concat()
+    }
+}
+
+fn statuses(reply: &Bencode) -> Vec<String> {
+    match reply.get("status") {
+        Some(Bencode::List(list)) => list
+            .iter()
+            .map(|x| match x {
+                Bencode::Bytes(x) => String::from_utf8(x.clone()).unwrap(),
+                _ => panic!("a status that is not a string"),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the values of `key` in the replies that have it.
+fn strings(replies: &[Bencode], key: &str) -> Vec<String> {
+    replies
+        .iter()
+        .filter_map(|x| x.get_str(key))
+        .map(String::from)
+        .collect()
+}
+
+fn last_statuses(replies: &[Bencode]) -> Vec<String> {
+    statuses(replies.last().unwrap())
+}
+
+#[test]
+fn describe_lists_the_ops() {
+    let mut client = Client::connect();
+    let replies = client.request(&[("op", "describe")]);
+    let ops = replies[0].get("ops").unwrap();
+    for op in ["clone", "eval", "interrupt", "stdin"] {
+        assert!(ops.get(op).is_some(), "{}", op);
+    }
+    let replies = client.request(&[("op", "foo")]);
+    assert_eq!(last_statuses(&replies), ["error", "unknown-op", "done"]);
+}
+
+#[test]
+fn sessions_keep_their_environment() {
+    let mut client = Client::connect();
+    let session = client.clone_session();
+    let replies = client.request(&[
+        ("op", "eval"),
+        ("code", "(def! x 1) (+ x 1)"),
+        ("session", &session),
+    ]);
+    assert_eq!(strings(&replies, "value"), ["1", "2"]);
+    assert_eq!(strings(&replies, "ns"), ["user", "user"]);
+    let replies = client.request(&[("op", "eval"), ("code", "x"), ("session", &session)]);
+    assert_eq!(strings(&replies, "value"), ["1"]);
+
+    // without a session, the environment is new
+    let replies = client.request(&[("op", "eval"), ("code", "x")]);
+    assert_eq!(last_statuses(&replies), ["eval-error", "done"]);
+    assert!(strings(&replies, "err")[0].contains("symbol x is not bound"));
+
+    let replies = client.request(&[
+        ("op", "completions"),
+        ("prefix", "edn/"),
+        ("session", &session),
+    ]);
+    let completions = replies[0].get("completions").unwrap();
+    assert!(matches!(completions, Bencode::List(list) if list.len() == 2));
+
+    let replies = client.request(&[("op", "ls-sessions")]);
+    assert_eq!(
+        replies[0].get("sessions"),
+        Some(&vec![session.as_str()].into())
+    );
+    let replies = client.request(&[("op", "close"), ("session", &session)]);
+    assert_eq!(last_statuses(&replies), ["session-closed", "done"]);
+    let replies = client.request(&[("op", "eval"), ("code", "x"), ("session", &session)]);
+    assert_eq!(
+        last_statuses(&replies),
+        ["error", "unknown-session", "done"]
+    );
+}
+
+#[test]
+fn output_and_error_output_are_sent() {
+    let mut client = Client::connect();
+    let session = client.clone_session();
+    let replies = client.request(&[
+        ("op", "eval"),
+        ("code", "(println \"hi\")"),
+        ("session", &session),
+    ]);
+    assert_eq!(strings(&replies, "out").concat(), "hi\n");
+    assert_eq!(strings(&replies, "value"), ["nil"]);
+
+    // the session evaluates on the same thread each time, so functions stay traced
+    client.request(&[
+        ("op", "eval"),
+        ("code", "(trace '+)"),
+        ("session", &session),
+    ]);
+    let replies = client.request(&[("op", "eval"), ("code", "(+ 1 2)"), ("session", &session)]);
+    assert_eq!(strings(&replies, "err").concat(), "(+ 1 2)\n=> 3\n");
+    client.request(&[("op", "eval"), ("code", "(untrace)"), ("session", &session)]);
+
+    let replies = client.request(&[
+        ("op", "eval"),
+        ("code", "(profile (+ 1 2))"),
+        ("session", &session),
+    ]);
+    assert!(!strings(&replies, "err").is_empty());
+    assert_eq!(strings(&replies, "value"), ["3"]);
+}
+
+#[test]
+fn input_is_asked_for() {
+    let mut client = Client::connect();
+    let session = client.clone_session();
+    let id = client.send(&[
+        ("op", "eval"),
+        ("code", "(readline \"? \")"),
+        ("session", &session),
+    ]);
+    let replies = client.receive(&id, "need-input");
+    assert_eq!(strings(&replies, "out").concat(), "? ");
+    client.request(&[("op", "stdin"), ("stdin", "abc\n"), ("session", &session)]);
+    let replies = client.receive(&id, "done");
+    assert_eq!(strings(&replies, "value"), ["\"abc\""]);
+
+    // the debugger reads its commands from the client too
+    let id = client.send(&[
+        ("op", "eval"),
+        ("code", "(debug (+ 1 2))"),
+        ("session", &session),
+    ]);
+    let replies = client.receive(&id, "need-input");
+    assert!(strings(&replies, "err")
+        .concat()
+        .contains("(+ 1 2)\ndebug> "));
+    client.request(&[("op", "stdin"), ("stdin", "c\n"), ("session", &session)]);
+    let replies = client.receive(&id, "done");
+    assert_eq!(strings(&replies, "value"), ["3"]);
+}
+
+#[test]
+fn evaluations_can_be_interrupted() {
+    let mut client = Client::connect();
+    let session = client.clone_session();
+    let id = client.send(&[
+        ("op", "eval"),
+        ("code", "(loop [] (recur))"),
+        ("session", &session),
+    ]);
+    // wait until the evaluation runs, since an idle session is not interrupted
+    while client
+        .request(&[("op", "interrupt"), ("session", &session)])
+        .last()
+        .map(statuses)
+        != Some(vec![String::from("done")])
+    {}
+    let replies = client.receive(&id, "done");
+    assert_eq!(last_statuses(&replies), ["interrupted", "done"]);
+
+    // also while waiting for input
+    let id = client.send(&[
+        ("op", "eval"),
+        ("code", "(readline \"\")"),
+        ("session", &session),
+    ]);
+    client.receive(&id, "need-input");
+    client.request(&[("op", "interrupt"), ("session", &session)]);
+    let replies = client.receive(&id, "done");
+    assert_eq!(last_statuses(&replies), ["interrupted", "done"]);
+
+    let replies = client.request(&[("op", "interrupt"), ("session", &session)]);
+    assert_eq!(last_statuses(&replies), ["session-idle", "done"]);
+    let replies = client.request(&[("op", "eval"), ("code", "(+ 1 2)"), ("session", &session)]);
+    assert_eq!(strings(&replies, "value"), ["3"]);
+}
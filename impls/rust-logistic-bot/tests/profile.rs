@@ -0,0 +1,150 @@
+//! Checks the profiler: the `(profile)` form and the `--profile` option of `mal`, and the table
+//! and folded call stacks that they write.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+use mal::atom::Atom;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::profile::{Profile, Stats};
+
+use common::Output;
+
+const COUNT_DOWN: &str = "(def! f (fn* [n] (if (= n 0) 0 (f (- n 1)))))";
+
+/// Returns a path in the temporary directory that is unique to this test and `name`.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mal-profile-{}-{}", std::process::id(), name))
+}
+
+/// Returns the number of calls of each function in `table`.
+fn calls(table: &str) -> BTreeMap<String, u64> {
+    let mut lines = table.lines();
+    assert!(lines.next().unwrap().starts_with("function"), "{}", table);
+    lines
+        .map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            (columns[0].to_string(), columns[1].parse().unwrap())
+        })
+        .collect()
+}
+
+/// Returns the call stacks in `folded`, and checks that each has a time.
+fn stacks(folded: &str) -> Vec<String> {
+    folded
+        .lines()
+        .map(|line| {
+            let (stack, time) = line.rsplit_once(' ').unwrap();
+            time.parse::<u128>()
+                .unwrap_or_else(|_| panic!("{} has no time", line));
+            stack.to_string()
+        })
+        .collect()
+}
+
+fn expected_calls() -> BTreeMap<String, u64> {
+    [("f", 3), ("=", 3), ("-", 2)]
+        .map(|(name, calls)| (name.to_string(), calls))
+        .into()
+}
+
+fn expected_stacks() -> Vec<String> {
+    // the calls in tail position are not tail calls while profiling, so f calls itself
+    [
+        "f", "f;-", "f;=", "f;f", "f;f;-", "f;f;=", "f;f;f", "f;f;f;=",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+#[test]
+fn profile_form() {
+    for engine in [Engine::TreeWalker, Engine::Vm] {
+        let errors = Output::default();
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.set_stderr(errors.clone());
+        let path = temp_path(&format!("{:?}.folded", engine));
+        mal.eval_str(COUNT_DOWN).unwrap();
+        let source = format!("(profile (f 2) {:?})", path.display().to_string());
+        assert_eq!(mal.eval_str(&source).unwrap(), Atom::Integer(0));
+        assert_eq!(calls(&errors.take()), expected_calls());
+        let folded = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(stacks(&folded), expected_stacks());
+
+        // the profiler is stopped, and profiling inside of a profile only evaluates
+        assert_eq!(mal.eval_str("(f 2)").unwrap(), Atom::Integer(0));
+        assert_eq!(errors.take(), "");
+        assert_eq!(
+            mal.eval_str("(profile (profile (f 2)))").unwrap(),
+            Atom::Integer(0)
+        );
+        assert_eq!(calls(&errors.take()), expected_calls());
+
+        let e = mal.eval_str("(profile (f 2) 1)").unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<MalError>(),
+            Some(&MalError::type_error("string", &Atom::Integer(1)))
+        );
+    }
+}
+
+#[test]
+fn profile_option() {
+    let path = temp_path("option.folded");
+    let output = Command::new(env!("CARGO_BIN_EXE_mal"))
+        .arg(format!("--profile={}", path.display()))
+        .args(["-e", &format!("{} (f 2)", COUNT_DOWN)])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0\n");
+    let table = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(calls(&table), expected_calls());
+    let folded = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(stacks(&folded), expected_stacks());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mal"))
+        .args(["--profile=no/such/dir/x.folded", "-e", "1"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(73));
+}
+
+#[test]
+fn table_and_folded_output() {
+    let stats = |calls, inclusive, exclusive| Stats {
+        calls,
+        inclusive: Duration::from_micros(inclusive),
+        exclusive: Duration::from_micros(exclusive),
+        allocations: 0,
+    };
+    let profile = Profile {
+        functions: [
+            (String::from("f"), stats(1, 30, 10)),
+            (String::from("g"), stats(2, 20, 20)),
+        ]
+        .into(),
+        stacks: [
+            (String::from("f"), Duration::from_micros(10)),
+            (String::from("f;g"), Duration::from_micros(20)),
+        ]
+        .into(),
+    };
+    // the functions that took the most exclusive time come first
+    assert_eq!(
+        profile.table(),
+        "function                      calls      inclusive      exclusive  allocations\n\
+         g                                 2           20µs           20µs            0\n\
+         f                                 1           30µs           10µs            0"
+    );
+    assert_eq!(profile.folded(), "f 10\nf;g 20\n");
+}
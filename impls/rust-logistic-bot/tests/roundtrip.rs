@@ -0,0 +1,101 @@
+//! Property tests for the reader and the printer: printing a value and reading it back gives the
+//! same value, and the reader does not panic, whatever the input.
+
+use mal::atom::Atom;
+use mal::reader::{read_all, read_str};
+use num_bigint::BigInt;
+use proptest::prelude::*;
+
+/// Characters that end a symbol or keyword
+const DELIMITERS: &str = "()[]{}'\"`,;";
+
+/// Characters that are read as reader macros at the start of a token
+const MACRO_CHARS: &str = "'`~@^";
+
+/// Returns whether `c` can be part of a symbol or keyword.
+fn is_symbol_char(c: char) -> bool {
+    !c.is_whitespace() && !DELIMITERS.contains(c)
+}
+
+/// Names of keywords, which can be empty and can start with any character a symbol can have
+fn keyword_name() -> impl Strategy<Value = String> {
+    prop::collection::vec(
+        any::<char>().prop_filter("delimiter", |c| is_symbol_char(*c)),
+        0..8,
+    )
+    .prop_map(|chars| chars.into_iter().collect())
+}
+
+/// Names of symbols, which must not be read as something else
+fn symbol_name() -> impl Strategy<Value = String> {
+    prop_oneof![
+        // mostly ASCII, for readable failures
+        "[a-zA-Z*+!?<>=/_-][a-zA-Z0-9*+!?<>=/_:#.-]{0,8}",
+        keyword_name(),
+    ]
+    .prop_filter("not a symbol", |name| {
+        let first = match name.chars().next() {
+            Some(c) => c,
+            None => return false,
+        };
+        !MACRO_CHARS.contains(first)
+            && first != ':'
+            && !matches!(name.as_str(), "nil" | "true" | "false")
+            && name.parse::<BigInt>().is_err()
+    })
+}
+
+/// Atoms without children that the reader can read
+fn leaf() -> impl Strategy<Value = Atom> {
+    prop_oneof![
+        Just(Atom::Nil),
+        any::<bool>().prop_map(Atom::Bool),
+        any::<i64>().prop_map(Atom::Integer),
+        (any::<i128>(), any::<bool>()).prop_map(|(num, negative)| {
+            let big = BigInt::from(num) * BigInt::from(u64::MAX);
+            Atom::from_bigint(if negative { -big } else { big })
+        }),
+        symbol_name().prop_map(Atom::Symbol),
+        keyword_name().prop_map(Atom::Keyword),
+        any::<String>().prop_map(Atom::String),
+        // the characters that must be escaped
+        "[\"\\\\a\n]{0,8}".prop_map(Atom::String),
+    ]
+}
+
+/// Trees of atoms that the reader can read. Sets, characters and tagged values are only read by
+/// [`mal::edn`], and builtins can not be printed readably.
+fn atom() -> impl Strategy<Value = Atom> {
+    leaf().prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Atom::List),
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Atom::Vector),
+            prop::collection::btree_map(inner.clone(), inner, 0..8).prop_map(Atom::HashMap),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn print_then_read(a in atom()) {
+        let printed = a.to_string();
+        let read = read_str(printed.clone())
+            .map_err(|e| TestCaseError::fail(format!("{:?} could not be read: {:#}", printed, e)))?;
+        prop_assert_eq!(read, a, "printed as {:?}", printed);
+    }
+
+    #[test]
+    fn read_str_does_not_panic(s in any::<String>()) {
+        let _ = read_str(s);
+    }
+
+    #[test]
+    fn read_all_does_not_panic(s in any::<String>()) {
+        let _ = read_all(s);
+    }
+
+    #[test]
+    fn read_str_does_not_panic_on_syntax(s in "[()\\[\\]{}\"\\\\'`~@^;:, \na-z0-9-]{0,64}") {
+        let _ = read_str(s);
+    }
+}
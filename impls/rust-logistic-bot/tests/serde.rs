@@ -0,0 +1,186 @@
+//! Checks `mal::serde` with derived types: that values convert to the atoms described in the
+//! module docs and back, and the errors for values that have no conversion.
+
+#![cfg(feature = "serde")]
+
+use std::collections::BTreeMap;
+
+use mal::atom::Atom;
+use mal::env::builtin;
+use mal::error::MalError;
+use mal::reader::read_str;
+use mal::serde::{from_atom, to_atom};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Config {
+    name: String,
+    steps: i64,
+    verbose: bool,
+    limit: Option<u32>,
+    tags: Vec<String>,
+    weights: BTreeMap<String, i64>,
+    shape: Shape,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Point,
+    Circle(u64),
+    Line(i64, i64),
+    Rect { width: u8, height: u8 },
+}
+
+fn read(s: &str) -> Atom {
+    read_str(s.to_string()).unwrap_or_else(|e| panic!("{}: {:#}", s, e))
+}
+
+fn keyword(s: &str) -> Atom {
+    Atom::Keyword(s.to_string())
+}
+
+/// Converts `value` to an atom, checks that it is `expected`, and converts it back.
+fn round_trip<T>(value: T, expected: &str)
+where
+    T: Serialize + for<'de> Deserialize<'de> + PartialEq + std::fmt::Debug,
+{
+    let atom = to_atom(&value).unwrap();
+    assert_eq!(atom, read(expected));
+    assert_eq!(from_atom::<T>(&atom).unwrap(), value);
+}
+
+/// Returns the message of a conversion error.
+fn conversion_error<T: std::fmt::Debug>(res: Result<T, MalError>) -> String {
+    match res {
+        Err(MalError::Conversion(msg)) => msg,
+        res => panic!("not a conversion error: {:?}", res),
+    }
+}
+
+#[test]
+fn structs() {
+    let config = Config {
+        name: "mal".to_string(),
+        steps: 10,
+        verbose: false,
+        limit: None,
+        tags: vec!["a".to_string(), "b".to_string()],
+        weights: BTreeMap::from([("x".to_string(), -1)]),
+        shape: Shape::Point,
+    };
+    round_trip(
+        config,
+        r#"{:name "mal" :steps 10 :verbose false :limit nil :tags ["a" "b"]
+            :weights {"x" -1} :shape :Point}"#,
+    );
+
+    // structs can be read from what a mal program writes, keywords and lists included
+    let atom = read(
+        "{:name :mal :steps 3 :verbose true :limit 7 :tags (\"c\") :weights {} \
+         :shape {:Line [1 2]}}",
+    );
+    assert_eq!(
+        from_atom::<Config>(&atom).unwrap(),
+        Config {
+            name: "mal".to_string(),
+            steps: 3,
+            verbose: true,
+            limit: Some(7),
+            tags: vec!["c".to_string()],
+            weights: BTreeMap::new(),
+            shape: Shape::Line(1, 2),
+        }
+    );
+}
+
+#[test]
+fn enums() {
+    round_trip(Shape::Point, ":Point");
+    round_trip(Shape::Circle(3), "{:Circle 3}");
+    round_trip(Shape::Line(-1, 2), "{:Line [-1 2]}");
+    round_trip(
+        Shape::Rect {
+            width: 2,
+            height: 3,
+        },
+        "{:Rect {:width 2 :height 3}}",
+    );
+    // unit variants can also be named with a string
+    assert_eq!(
+        from_atom::<Shape>(&read("\"Point\"")).unwrap(),
+        Shape::Point
+    );
+}
+
+#[test]
+fn primitives() {
+    round_trip(u64::MAX, "18446744073709551615");
+    round_trip(i128::MIN, "-170141183460469231731687303715884105728");
+    round_trip('λ', "\"λ\"");
+    round_trip((), "nil");
+    round_trip(Some(1), "1");
+    round_trip(vec![Some(true), None], "[true nil]");
+    round_trip(("a".to_string(), 1), "[\"a\" 1]");
+    // sets and tagged values are read as their contents
+    let set = Atom::Set([Atom::Integer(1), Atom::Integer(2)].into());
+    assert_eq!(from_atom::<Vec<i64>>(&set).unwrap(), vec![1, 2]);
+    assert_eq!(
+        Atom::deserialize(&Atom::Tagged("inst".to_string(), Box::new(keyword("a")))).unwrap(),
+        Atom::String("a".to_string())
+    );
+}
+
+#[test]
+fn serialize_errors() {
+    assert_eq!(
+        conversion_error(to_atom(&1.5)),
+        "can not serialize 1.5: floating point numbers are not supported"
+    );
+    assert_eq!(
+        conversion_error(to_atom(&vec![0.0f32])),
+        "can not serialize 0: floating point numbers are not supported"
+    );
+    let function = Atom::Builtin(builtin("+").unwrap().function);
+    assert_eq!(
+        conversion_error(to_atom(&function)),
+        "functions can not be serialized"
+    );
+}
+
+#[test]
+fn deserialize_errors() {
+    assert_eq!(
+        from_atom::<i64>(&read("\"10\"")).unwrap_err(),
+        MalError::Conversion("invalid type: string \"10\", expected i64".to_string())
+    );
+    assert_eq!(
+        conversion_error(from_atom::<u8>(&read("256"))),
+        "invalid value: integer `256`, expected u8"
+    );
+    assert_eq!(
+        conversion_error(from_atom::<i64>(&read("100000000000000000000"))),
+        "invalid type: integer `100000000000000000000` as i128, expected i64"
+    );
+    assert_eq!(
+        conversion_error(from_atom::<Config>(&read("{:name \"mal\"}"))),
+        "missing field `steps`"
+    );
+    assert_eq!(
+        conversion_error(from_atom::<Shape>(&read(":Square"))),
+        "unknown variant `Square`, expected one of `Point`, `Circle`, `Line`, `Rect`"
+    );
+    let atom = read("{:Circle 1 :Point nil}");
+    assert_eq!(
+        from_atom::<Shape>(&atom).unwrap_err(),
+        MalError::type_error("an enum variant", &atom)
+    );
+    assert_eq!(
+        conversion_error(from_atom::<Shape>(&read("{:Rect [1 2 3]}"))),
+        "invalid length 3, expected 2 elements in sequence"
+    );
+    let function = Atom::Builtin(builtin("+").unwrap().function);
+    assert_eq!(
+        conversion_error(from_atom::<Atom>(&function)),
+        "functions can not be deserialized"
+    );
+}
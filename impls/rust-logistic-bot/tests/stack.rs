@@ -0,0 +1,128 @@
+//! Checks `try*` and `catch*`, the stack that a handler gets in `*stack*` and from
+//! `(stacktrace)`, and the locations of calls in backtraces.
+
+use mal::atom::Atom;
+use mal::error::{Limit, MalError};
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::limits::Limits;
+use mal::reader::read_str;
+use mal::stack::take_backtrace;
+
+/// Evaluates `source` with the tree-walker and with the VM, which should give the same value.
+fn eval(source: &str) -> Atom {
+    let [walked, compiled] = [Engine::TreeWalker, Engine::Vm].map(|engine| {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.eval_str(source)
+            .unwrap_or_else(|e| panic!("{}: {:#}", source, e))
+    });
+    assert_eq!(walked, compiled, "{}", source);
+    walked
+}
+
+fn read(source: &str) -> Atom {
+    read_str(source.to_string()).unwrap()
+}
+
+#[test]
+fn thrown_values_are_caught() {
+    assert_eq!(eval("(try* (throw {:a 1}) (catch* e e))"), read("{:a 1}"));
+    assert_eq!(eval("(try* (+ 1 2) (catch* e e))"), Atom::Integer(3));
+    assert_eq!(eval("(try* (+ 1 2))"), Atom::Integer(3));
+    assert_eq!(
+        eval("(try* (throw [1 2]) (catch* [a b] (+ a b)))"),
+        Atom::Integer(3)
+    );
+    assert_eq!(
+        eval("(try* (try* (throw 1) (catch* e (throw (+ e 1)))) (catch* e e))"),
+        Atom::Integer(2)
+    );
+}
+
+#[test]
+fn errors_are_caught_as_their_message() {
+    assert_eq!(
+        eval("(try* (/ 1 0) (catch* e e))"),
+        Atom::String(String::from("division by zero"))
+    );
+    assert_eq!(
+        eval("(try* undefined (catch* e e))"),
+        Atom::String(String::from("symbol undefined is not bound to any value"))
+    );
+}
+
+#[test]
+fn handlers_get_the_stack_where_the_error_was_raised() {
+    let source = "(let* [f (fn* [x] (+ 1 (throw x)))] \
+                  (try* (f 2) (catch* e [e *stack* (stacktrace)])))";
+    let stack = read("((throw x) (+ 1 (throw x)) (f 2))");
+    assert_eq!(
+        eval(source),
+        Atom::Vector(vec![Atom::Integer(2), stack.clone(), stack])
+    );
+    // calls made by the handler also get the stack of the error
+    assert_eq!(
+        eval("(let* [f (fn* [] (stacktrace))] (try* (throw 1) (catch* e (f))))"),
+        read("((throw 1))")
+    );
+    // after the handler, it is the calls that are being evaluated again
+    assert_eq!(
+        eval("[(try* (throw 1) (catch* e e)) (stacktrace)]"),
+        read("[1 ((stacktrace))]")
+    );
+}
+
+#[test]
+fn caught_errors_leave_no_backtrace() {
+    let mut mal = Interpreter::new();
+    mal.eval_str("(try* (+ 1 (throw 1)) (catch* e e))").unwrap();
+    assert!(take_backtrace().is_none());
+}
+
+#[test]
+fn limits_are_not_caught() {
+    for engine in [Engine::TreeWalker, Engine::Vm] {
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.set_limits(Limits {
+            fuel: Some(100),
+            ..Limits::default()
+        });
+        let e = mal
+            .eval_str("(try* (loop [i 0] (recur (+ i 1))) (catch* e :caught))")
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<MalError>(),
+            Some(&MalError::LimitExceeded(Limit::Fuel))
+        );
+    }
+}
+
+#[test]
+fn backtraces_have_locations_in_files() {
+    let path = std::env::temp_dir().join(format!("mal-stack-{}.mal", std::process::id()));
+    std::fs::write(
+        &path,
+        "(def! f (fn* [x]\n  (+ 1 (throw x))))\n\n(def! g (fn* [] [(f 1)]))\n(g)\n",
+    )
+    .unwrap();
+    let e = Interpreter::new().eval_file(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        e.downcast_ref::<MalError>(),
+        Some(&MalError::Thrown(Atom::Integer(1)))
+    );
+    let frames = take_backtrace().expect("a backtrace");
+    let locations = frames
+        .iter()
+        .rev()
+        .map(|frame| frame.location().map(|l| (l.line, l.column)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        locations,
+        [Some((2, 8)), Some((2, 3)), Some((4, 18)), Some((5, 1))]
+    );
+    let name = path.display().to_string();
+    assert_eq!(frames[0].to_string(), format!("g in (g) at {}:5:1", name));
+}
@@ -0,0 +1,237 @@
+//! Runs the shared `tests/step*.mal` files against the library, like `runtest.py` does against
+//! the step binaries, but in process.
+//!
+//! Each line of a test file is a form, followed by `;/` lines with regexes for its output and a
+//! `;=>` line with its printed value. Forms without either are run but not checked. Failures
+//! after `;>>> soft=True`, `;>>> deferrable=True` or `;>>> optional=True` are reported but do not
+//! fail the test. Forms whose output regex the regex crate can not compile are skipped.
+//!
+//! Each step is run with both engines, the tree-walker and the bytecode VM in the `vm` module.
+//! Steps that still fail are ignored, with the first form that fails as the reason. Run them
+//! with `cargo test --test step_files -- --ignored --nocapture` to see every failure.
+
+mod common;
+
+use std::time::Duration;
+
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::limits::Limits;
+use mal::reader::read_str;
+use regex::Regex;
+
+use common::Output;
+
+/// The directory with the test files, relative to the crate. Forms like `load-file` use paths
+/// relative to the crate too, because that is where `runtest.py` runs the implementation.
+const TESTS_DIR: &str = "../tests";
+
+/// How long a form may run before it fails
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a step evaluates a line, like the binary of that step
+#[derive(Clone, Copy)]
+enum Mode {
+    /// Prints the line back
+    Echo,
+    /// Reads and prints the line
+    ReadPrint,
+    /// Evaluates the line in an interpreter that is kept for the whole file, which reads from an
+    /// empty input
+    Eval,
+}
+
+struct Case {
+    line: usize,
+    form: String,
+    /// Regex for the output, made of the `;/` lines
+    output: String,
+    /// The printed value, or `None` if it is not checked
+    value: Option<String>,
+    soft: bool,
+}
+
+/// Parses a test file, in the format that `runtest.py` reads.
+fn parse(source: &str) -> Vec<Case> {
+    let mut cases = Vec::new();
+    let mut soft = false;
+    let mut lines = source.lines().enumerate().peekable();
+    while let Some((i, line)) = lines.next() {
+        if line.trim().is_empty() || line.starts_with(";;") {
+            continue;
+        }
+        if let Some(settings) = line.strip_prefix(";>>> ") {
+            // deferrable and optional tests are soft from here to the end of the file
+            if settings.contains("=True") {
+                soft = true;
+            } else if settings.contains("soft=False") {
+                soft = false;
+            }
+            continue;
+        }
+        assert!(
+            !line.starts_with(';'),
+            "unexpected comment at line {}: {}",
+            i + 1,
+            line
+        );
+        let mut output = Vec::new();
+        let mut value = None;
+        while let Some((_, next)) = lines.peek() {
+            if let Some(out) = next.strip_prefix(";/") {
+                output.push(out.to_string());
+                lines.next();
+            } else {
+                if let Some(ret) = next.strip_prefix(";=>") {
+                    value = Some(ret.to_string());
+                    lines.next();
+                }
+                break;
+            }
+        }
+        let mut output = output.join("\n");
+        if value.is_some() && !output.is_empty() {
+            output.push('\n');
+        }
+        cases.push(Case {
+            line: i + 1,
+            form: line.to_string(),
+            output,
+            value,
+            soft,
+        });
+    }
+    cases
+}
+
+/// Runs `form`, and returns what the REPL would print: the output of the form, then its value or
+/// error.
+fn run(form: &str, mode: Mode, mal: &mut Interpreter, output: &Output) -> String {
+    let res = match mode {
+        Mode::Echo => return form.to_string(),
+        Mode::ReadPrint => read_str(form.to_string()),
+        Mode::Eval => mal.eval_str(form),
+    };
+    let printed = match res {
+        Ok(atom) => atom.to_string(),
+        Err(e) => e.to_string(),
+    };
+    let output = output.take();
+    output + &printed
+}
+
+enum Outcome {
+    Pass,
+    Fail(String),
+    /// The expected output can not be checked
+    Skip(String),
+}
+
+/// Checks `got` like `runtest.py`: the output regex followed by the exact value, at the start of a
+/// line.
+fn check(case: &Case, got: &str) -> Outcome {
+    let value = match &case.value {
+        Some(value) => value,
+        None if case.output.is_empty() => return Outcome::Pass,
+        None => "",
+    };
+    // Python allows escaping quotes, the regex crate does not
+    let pattern = format!(
+        "(?s)(^|\n){}{}",
+        case.output.replace("\\'", "'"),
+        regex::escape(value)
+    );
+    match Regex::new(&pattern) {
+        Ok(regex) if regex.is_match(got) => Outcome::Pass,
+        Ok(_) => Outcome::Fail(format!("expected {:?}, got {:?}", pattern, got)),
+        Err(e) => Outcome::Skip(format!("unsupported pattern: {}", e)),
+    }
+}
+
+/// Runs the test file `name`, prints a report, and panics if there were hard failures.
+fn run_file(name: &str, mode: Mode, engine: Engine) {
+    let path = format!("{}/{}.mal", TESTS_DIR, name);
+    let source = std::fs::read_to_string(&path).unwrap();
+    let output = Output::default();
+    let mut mal = Interpreter::new();
+    mal.set_engine(engine);
+    mal.set_stdout(output.clone());
+    mal.set_stdin(std::io::empty());
+    mal.set_limits(Limits {
+        timeout: Some(TIMEOUT),
+        ..Limits::default()
+    });
+    let (mut passed, mut failed, mut soft_failed, mut skipped) = (0, 0, 0, 0);
+    for case in parse(&source) {
+        let got = run(&case.form, mode, &mut mal, &output);
+        match check(&case, &got) {
+            Outcome::Pass => passed += 1,
+            Outcome::Skip(e) => {
+                skipped += 1;
+                println!("SKIP {}:{}: {}\n    {}", path, case.line, case.form, e);
+            }
+            Outcome::Fail(e) => {
+                let kind = if case.soft {
+                    soft_failed += 1;
+                    "SOFT FAIL"
+                } else {
+                    failed += 1;
+                    "FAIL"
+                };
+                println!("{} {}:{}: {}\n    {}", kind, path, case.line, case.form, e);
+            }
+        }
+    }
+    println!(
+        "{}: {} passed, {} failed, {} soft failed, {} skipped",
+        name, passed, failed, soft_failed, skipped
+    );
+    assert_eq!(failed, 0, "{} of the tests in {} failed", failed, path);
+}
+
+macro_rules! step_tests {
+    ($($(#[$attr:meta])* $name:ident: $mode:expr,)*) => {
+        $(
+            #[test]
+            $(#[$attr])*
+            fn $name() {
+                run_file(stringify!($name), $mode, Engine::TreeWalker);
+            }
+        )*
+
+        /// The same tests, evaluated by the bytecode VM
+        mod vm {
+            use super::*;
+
+            $(
+                #[test]
+                $(#[$attr])*
+                fn $name() {
+                    run_file(stringify!($name), $mode, Engine::Vm);
+                }
+            )*
+        }
+    };
+}
+
+step_tests! {
+    step0_repl: Mode::Echo,
+    step1_read_print: Mode::ReadPrint,
+    step2_eval: Mode::Eval,
+    #[ignore = "(abc 1 2 3) fails with \"symbol abc is not bound\" instead of \"'abc' not found\""]
+    step3_env: Mode::Eval,
+    #[ignore = "(list) fails, there is no list, count or prn"]
+    step4_if_fn_do: Mode::Eval,
+    step5_tco: Mode::Eval,
+    #[ignore = "(read-string \"(1 2 (3 4) nil)\") fails, there is no read-string"]
+    step6_file: Mode::Eval,
+    #[ignore = "(cons 1 (list)) fails, there is no cons or list"]
+    step7_quote: Mode::Eval,
+    #[ignore = "(defmacro! one (fn* () 1)) fails, there is no defmacro!"]
+    step8_macros: Mode::Eval,
+    #[ignore = "(try* abc (catch* exc (prn \"exc is:\" exc))) fails, there is no prn"]
+    step9_try: Mode::Eval,
+    #[ignore = "(readline \"mal-user> \") doesn't read the next line of the file, and there is no *host-language*"]
+    #[allow(non_snake_case)]
+    stepA_mal: Mode::Eval,
+}
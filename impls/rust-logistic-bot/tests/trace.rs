@@ -0,0 +1,125 @@
+//! Checks that `trace` logs the calls to a function however it is called, with both engines.
+
+mod common;
+
+use mal::atom::Atom;
+use mal::env::sandbox_env;
+use mal::error::MalError;
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::limits::Limits;
+
+use common::Output;
+
+/// Evaluates `source` with each engine, and returns what was logged, which should be the same.
+fn trace_log(source: &str) -> String {
+    let [walked, compiled] = [Engine::TreeWalker, Engine::Vm].map(|engine| {
+        let errors = Output::default();
+        let mut mal = Interpreter::new();
+        mal.set_engine(engine);
+        mal.set_stderr(errors.clone());
+        let res = mal.eval_str(source);
+        mal.eval_str("(untrace)").unwrap();
+        res.unwrap_or_else(|e| panic!("{}: {:#}", source, e));
+        errors.take()
+    });
+    assert_eq!(walked, compiled, "{}", source);
+    walked
+}
+
+#[test]
+fn calls_are_traced_by_identity() {
+    let source = "(def! f (fn* [n] (if (= n 0) 0 (+ 1 (f (- n 1))))))\n\
+                  (trace 'f)\n\
+                  (let* [g f] (g 2))";
+    assert_eq!(
+        trace_log(source),
+        "(g 2)\n| (f 1)\n| | (f 0)\n| | => 0\n| => 1\n=> 2\n"
+    );
+    // a function that is passed to another one
+    assert_eq!(
+        trace_log("(def! h (fn* [x] x))\n(trace 'h)\n((fn* [k] (k 1)) h)"),
+        "(k 1)\n=> 1\n"
+    );
+    assert_eq!(
+        trace_log("(do (trace '+) (+ 1 (* 2 3)))"),
+        "(+ 1 6)\n=> 7\n"
+    );
+}
+
+#[test]
+fn calls_that_are_not_by_name_are_traced() {
+    assert_eq!(
+        trace_log("(def! f (fn* [x] x))\n(trace 'f)\n((do f) 0)\n((trace 'f) 2)"),
+        "(f 0)\n=> 0\n(f 2)\n=> 2\n"
+    );
+}
+
+#[test]
+fn trace_all_traces_user_functions() {
+    let source = "(def! inc (fn* [x] (+ x 1)))\n\
+                  (trace-all (inc (inc 1)))\n\
+                  (try* (trace-all (inc (throw 1))) (catch* e e))\n\
+                  (inc 3)";
+    assert_eq!(trace_log(source), "(inc 1)\n=> 2\n(inc 2)\n=> 3\n");
+}
+
+#[test]
+fn errors_are_traced() {
+    let source = "(def! inc (fn* [x] (+ x 1)))\n(trace '/)\n(trace-all (inc 2))\n(/ 1 0)";
+    let errors = Output::default();
+    let mut mal = Interpreter::new();
+    mal.set_stderr(errors.clone());
+    assert!(mal.eval_str(source).is_err());
+    mal.eval_str("(untrace)").unwrap();
+    assert_eq!(
+        errors.take(),
+        "(inc 2)\n=> 3\n(/ 1 0)\n!! division by zero\n"
+    );
+}
+
+#[test]
+fn untrace_stops_tracing() {
+    assert_eq!(
+        trace_log(
+            "(def! f (fn* [x] x))\n(trace 'f)\n(untrace 'f)\n(f 1)\n(trace '+)\n(untrace)\n(+ 1 2)"
+        ),
+        ""
+    );
+}
+
+#[test]
+fn functions_are_traced_by_name() {
+    let error = |source: &str| {
+        let e = Interpreter::new().eval_str(source).expect_err(source);
+        e.downcast_ref::<MalError>().cloned()
+    };
+    assert_eq!(
+        error("(trace 1)"),
+        Some(MalError::type_error("symbol", &Atom::Integer(1)))
+    );
+    assert_eq!(
+        error("(untrace 'nothing)"),
+        Some(MalError::UnboundSymbol(String::from("nothing")))
+    );
+    assert_eq!(
+        error("(def! x 1)\n(trace 'x)"),
+        Some(MalError::type_error("function", &Atom::Integer(1)))
+    );
+}
+
+#[test]
+fn sandboxed_code_can_trace() {
+    let errors = Output::default();
+    let mut mal = Interpreter::with_env(sandbox_env());
+    mal.set_stderr(errors.clone());
+    mal.set_limits(Limits {
+        sandbox: true,
+        ..Limits::default()
+    });
+    mal.eval_str("(def! inc (fn* [x] (+ x 1)))").unwrap();
+    mal.eval_str("(do (trace '+) (+ 1 2))").unwrap();
+    mal.eval_str("(untrace)").unwrap();
+    mal.eval_str("(trace-all (inc 1))").unwrap();
+    assert_eq!(errors.take(), "(+ 1 2)\n=> 3\n(inc 1)\n=> 2\n");
+}
@@ -0,0 +1,134 @@
+//! Checks that the bytecode VM gives the same output, values, errors and backtraces as the
+//! tree-walker, also when it runs out of fuel halfway, and that locals are kept in slots and
+//! tail calls don't nest.
+
+mod common;
+
+use mal::compiler::{compile, Op};
+use mal::eval::Engine;
+use mal::interpreter::Interpreter;
+use mal::limits::Limits;
+use mal::reader::read_str;
+use mal::stack::{format_backtrace, take_backtrace};
+
+use common::Output;
+
+const SOURCES: &[&str] = &[
+    "42",
+    "()",
+    "(+ 1 (* 2 3))",
+    "(+ 9223372036854775807 (- 0 -1))",
+    "[(println 1) {:a (println 2) (println 3) 4} (quote (a b))]",
+    "{(println :k) (println :v)}",
+    "(:a {:a 1})",
+    "({:a 1} :b 2)",
+    "([1 2 3] (+ 1 1))",
+    "(+ 1 (* 2 (- nil 3)))",
+    "(println (+ 1 undefined))",
+    "(1 2)",
+    "([1 2 3] 5)",
+    "[(println :before) (quote)]",
+    "(+ 1 (quote 1 2))",
+    "(trace-all (+ 1 2))",
+    "(/ (println 1) 0)",
+    "(let* [[a & r] [(println 1) 2 3] {:keys [x] :or {x (+ a 1)}} {}] [a r x])",
+    "(let* [{:keys [x]} (println 1)] (+ x 1))",
+    "(if (println 1) (println 2) (println 3))",
+    "[(if 1 2) (if nil 2) (do) (do (println 1) 2)]",
+    "(let* [a 1 [b c] [2 a] a 3] [a b c])",
+    "(let* [f (fn* [x & [y :as r]] [x y r])] [(f 1) (f 1 2 3)])",
+    "(let* [x 1 f (fn* [y] (+ x y)) x 10] (f x))",
+    "(let* [f (fn* [n acc] (if (= n 0) acc (f (- n 1) (+ acc n))))] (f 3 0))",
+    "((fn* [n acc] (if (= n 0) acc (recur (- n 1) (+ acc n)))) 20 0)",
+    "((fn* [n & more] (if (= n 0) more (recur (- n 1) [n more]))) 3)",
+    "(loop [i 0 acc []] (if (< i 3) (recur (+ i 1) [acc (println i)]) acc))",
+    "(loop [[x & xs] [1 2 3] {:keys [n] :or {n x}} {}] (if xs (recur xs {:n x}) n))",
+    "(loop [i 0] (+ 1 (recur i)))",
+    "(loop [i 0 j 0] (recur 1))",
+    "((fn* [x] (let* [y (+ x 1)] ((fn* [z] (/ z 0)) y))) 1)",
+    "((fn* [[a b]] a) 1)",
+    "((fn* [x] x))",
+    "(let* [x 2] (trace-all ((fn* [y] (* x y)) 3)))",
+    "(let* [x 1] (def! y x))",
+    "(fn* [x &] x)",
+    "(let* [g (fn* [x] (* x 2)) f (fn* [x] (g (+ x 1)))] [(f 1) (f (println 2))])",
+    "(try* (+ 1 (println 2)))",
+    "[(println 1) (try* (+ 1 (throw (println 2))) (catch* e [e *stack* (stacktrace)]))]",
+    "(try* (+ 1 nil) (catch* e (println e)))",
+    "(let* [f (fn* [x] (/ x 0))] [1 (try* (f 1) (catch* e (let* [[top] *stack*] top)))])",
+    "(try* (try* (throw 1) (catch* e (throw [e 2]))) (catch* [a b] (+ a b)))",
+    "(try* (throw {:n 1}) (catch* {:keys [n m] :or {m *stack*}} [n m]))",
+    "(loop [i 0] (try* (if (< i 3) (throw i) i) (catch* e (recur (+ e 1)))))",
+    "(try* 1 (catch e 2))",
+    "(try* (throw 1) (catch* 2 3))",
+];
+
+/// Runs `source`, and returns its output and error output, its value or error, and the backtrace.
+fn run(source: &str, engine: Engine, fuel: Option<u64>) -> (String, String, Option<String>) {
+    let output = Output::default();
+    let mut mal = Interpreter::new();
+    mal.set_engine(engine);
+    mal.set_stdout(output.clone());
+    mal.set_stderr(output.clone());
+    mal.set_limits(Limits {
+        fuel,
+        ..Limits::default()
+    });
+    let res = match mal.eval_str(source) {
+        Ok(atom) => atom.to_string(),
+        Err(e) => format!("error: {:#}", e),
+    };
+    let backtrace = take_backtrace().map(|frames| format_backtrace(&frames));
+    let output = output.take();
+    (output, res, backtrace)
+}
+
+#[test]
+fn same_as_tree_walker() {
+    for source in SOURCES {
+        let expected = run(source, Engine::TreeWalker, None);
+        assert_eq!(run(source, Engine::Vm, None), expected, "{}", source);
+    }
+}
+
+#[test]
+fn same_as_tree_walker_when_out_of_fuel() {
+    for source in SOURCES {
+        for fuel in 0..48 {
+            let expected = run(source, Engine::TreeWalker, Some(fuel));
+            assert_eq!(
+                run(source, Engine::Vm, Some(fuel)),
+                expected,
+                "{} with {} fuel",
+                source,
+                fuel
+            );
+        }
+    }
+}
+
+#[test]
+fn locals_are_slots() {
+    let chunk = compile(&read_str(String::from("(let* [a 1 [b] [a]] (if a b))")).unwrap());
+    assert!(chunk.code.contains(&Op::Local(0)));
+    assert!(chunk.code.iter().any(|op| matches!(op, Op::JumpIfFalse(_))));
+    assert!(!chunk.code.iter().any(|op| matches!(op, Op::Global(_))));
+}
+
+#[test]
+fn tail_calls_do_not_grow_the_stack() {
+    let mut mal = Interpreter::new();
+    mal.set_engine(Engine::Vm);
+    mal.eval_str("(def! count-down (fn* [n] (if (= n 0) :done (count-down (- n 1)))))")
+        .unwrap();
+    assert_eq!(
+        mal.eval_str("(count-down 100000)").unwrap().to_string(),
+        ":done"
+    );
+    assert_eq!(
+        mal.eval_str("(loop [i 0] (if (< i 100000) (recur (+ i 1)) i))")
+            .unwrap()
+            .to_string(),
+        "100000"
+    );
+}